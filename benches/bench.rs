@@ -1,5 +1,9 @@
+#![expect(clippy::unwrap_used)]
+
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use sqlite_compressions::{BrotliEncoder, Bzip2Encoder, Encoder as _, GzipEncoder};
+use sqlite_compressions::{
+    BrotliEncoder, BsdiffRawDiffer, Bzip2Encoder, Differ as _, Encoder as _, GzipEncoder,
+};
 
 macro_rules! enc_test {
     ($func_name:ident, $enc_type:ident, $func:literal) => {
@@ -21,6 +25,42 @@ enc_test!(gzip_test, GzipEncoder, "gzip");
 enc_test!(brotli_test, BrotliEncoder, "brotli");
 enc_test!(bzip2_test, Bzip2Encoder, "bzip2");
 
+/// Compare `bsdiffraw` with the `bsdiff` crate it replaced, which produces the same patches.
+fn bsdiffraw_diff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bsdiffraw");
+    group.sample_size(10);
+    for size in [64 * 1024, 1024 * 1024, 8 * 1024 * 1024] {
+        let (source, target) = gen_versions(size);
+        group.bench_function(BenchmarkId::new("bsdiffraw", size), |b| {
+            b.iter(|| BsdiffRawDiffer::diff(&source, &target).unwrap());
+        });
+        group.bench_function(BenchmarkId::new("bsdiff crate", size), |b| {
+            b.iter(|| {
+                let mut patch = Vec::new();
+                bsdiff::diff(&source, &target, &mut patch).unwrap();
+                patch
+            });
+        });
+    }
+    group.finish();
+}
+
+/// Generate pseudo-random data, and a copy of it with a few small changes.
+fn gen_versions(size: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut seed = 42_u64;
+    let source: Vec<u8> = (0..size)
+        .map(|_| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            seed.to_be_bytes()[0]
+        })
+        .collect();
+    let mut target = source.clone();
+    for pos in (0..size).step_by(4096) {
+        target[pos] = target[pos].wrapping_add(1);
+    }
+    (source, target)
+}
+
 fn gen_data(size: usize) -> Vec<u8> {
     let mut byte_data: Vec<u8> = Vec::with_capacity(size);
     for i in 0..size {
@@ -30,5 +70,5 @@ fn gen_data(size: usize) -> Vec<u8> {
     byte_data
 }
 
-criterion_group!(benches, gzip_test, brotli_test, bzip2_test);
+criterion_group!(benches, gzip_test, brotli_test, bzip2_test, bsdiffraw_diff);
 criterion_main!(benches);
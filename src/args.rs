@@ -0,0 +1,20 @@
+use rusqlite::functions::Context;
+use rusqlite::types::{Type, ValueRef};
+use rusqlite::Error::{InvalidFunctionParameterType, UserFunctionError};
+
+use crate::rusqlite::Result;
+
+/// Get the bytes of a blob or text argument, or `None` if it is `NULL`.
+pub(crate) fn get_bytes<'a>(ctx: &'a Context, index: usize) -> Result<Option<&'a [u8]>> {
+    match ctx.get_raw(index) {
+        ValueRef::Blob(val) | ValueRef::Text(val) => Ok(Some(val)),
+        ValueRef::Null => Ok(None),
+        ValueRef::Integer(_) => Err(InvalidFunctionParameterType(index, Type::Integer)),
+        ValueRef::Real(_) => Err(InvalidFunctionParameterType(index, Type::Real)),
+    }
+}
+
+/// The error for an unknown compression algorithm name.
+pub(crate) fn unknown_algorithm(name: &str) -> rusqlite::Error {
+    UserFunctionError(format!("Unknown compression algorithm '{name}'").into())
+}
@@ -1,9 +1,9 @@
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 
 use brotli::{CompressorWriter, Decompressor};
-use rusqlite::Error::UserFunctionError;
 
 use crate::common::{register_compression, Encoder};
+use crate::error::CompressionError;
 use crate::rusqlite::{Connection, Result};
 
 /// Register the `brotli` SQL function with the given `SQLite` connection.
@@ -48,20 +48,18 @@ impl Encoder for BrotliEncoder {
         "brotli_test"
     }
 
-    fn encode(data: &[u8], quality: Option<u32>) -> Result<Vec<u8>> {
-        let mut encoder = CompressorWriter::new(Vec::new(), 4 * 1024, quality.unwrap_or(11), 22);
-        encoder
-            .write_all(data)
-            .map_err(|e| UserFunctionError(e.into()))?;
+    fn encode_stream<R: Read, W: Write>(
+        reader: &mut R,
+        writer: W,
+        quality: Option<u32>,
+    ) -> Result<W, CompressionError> {
+        let mut encoder = compressor(writer, quality);
+        io::copy(reader, &mut encoder).map_err(|e| CompressionError::io("brotli", e))?;
         Ok(encoder.into_inner())
     }
 
-    fn decode(data: &[u8]) -> Result<Vec<u8>> {
-        let mut decompressed = Vec::new();
+    fn decoder(data: &[u8]) -> impl Read + '_ {
         Decompressor::new(data, 4 * 1024)
-            .read_to_end(&mut decompressed)
-            .map_err(|e| UserFunctionError(e.into()))?;
-        Ok(decompressed)
     }
 
     fn test(data: &[u8]) -> bool {
@@ -78,3 +76,8 @@ impl Encoder for BrotliEncoder {
         false
     }
 }
+
+/// Create the compressor for the optional quality argument, 11 by default, with a 4 MiB window.
+pub(crate) fn compressor<W: Write>(writer: W, quality: Option<u32>) -> CompressorWriter<W> {
+    CompressorWriter::new(writer, 4 * 1024, quality.unwrap_or(11), 22)
+}
@@ -1,16 +1,40 @@
-use std::io::Cursor;
+use std::collections::HashSet;
+use std::io::{self, Cursor, Read};
 
-use qbsdiff::bsdiff::Bsdiff;
+use bzip2::read::BzDecoder;
+use qbsdiff::bsdiff::{Bsdiff, ParallelScheme};
 use qbsdiff::bspatch::Bspatch;
-use rusqlite::Error::UserFunctionError;
+use rusqlite::functions::{Aggregate, Context, FunctionFlags};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::Error::{InvalidFunctionParameterType, UserFunctionError};
 
-use crate::common_diff::{register_differ, Differ};
+use crate::args::get_bytes;
+use crate::bsdiff43::{self, BSDIFF43_MAGIC};
+use crate::common_diff::{register_differ, run_diff, ConnLimits, Differ};
+use crate::error::CompressionError;
 use crate::rusqlite::{Connection, Result};
 
 /// Register the `bsdiff4` and `bspatch4` SQL functions with the given `SQLite` connection.
 /// The `bsdiff4` function takes two arguments, and returns the [BSDiff delta](https://github.com/mendsley/bsdiff#readme) (blob) of the binary difference.
 /// The arguments can be either a string or a blob.
-/// If any of the arguments are `NULL`, the result is `NULL`.
+/// The optional third argument is either the bzip2 compression level, or a JSON object with the options,
+/// e.g. `{"level":9,"buffer_size":65536,"parallel_chunk":1048576}`. See [`Bsdiff4Options`] for details.
+/// It can also be `'verified'` to create a patch that checks the source before, and the result after patching,
+/// or `"verified":true` can be added to the JSON options, e.g. `{"level":9,"verified":true}`.
+/// The `bspatch4_test` function checks that a patch is well-formed, and if the source is given as the first argument,
+/// that the patch can be applied to it.
+/// The `bspatch4_chain` aggregate function applies a series of patches in order to the base blob from the first row,
+/// e.g. `bspatch4_chain(base, patch ORDER BY version)`.
+/// The `bsdiff4_info` function returns a JSON object with the target size and the sizes of the compressed
+/// control, diff, and extra blocks of a patch, and `bsdiff4_controls` returns a JSON array of its decoded
+/// control tuples, which can be expanded into a table with `json_each`. Like `bspatch4`, it stops at the tuple
+/// that reaches the target size, and fails if a tuple goes past it.
+/// The `bsdiff4_best_base(id, candidate, target)` aggregate function finds the candidate that gives the smallest patch
+/// for the target, and returns a blob with both the id (integer or text) of that candidate and its patch.
+/// Get them with the `bsdiff4_best_base_id(result)` and `bsdiff4_best_base_patch(result)` functions.
+/// Candidates are ranked by a cheap similarity estimate first, and only the few most similar
+/// ones are actually diffed. The target must be the same in all rows.
+/// Rows with a `NULL` argument are skipped, and if no rows are left, the result is `NULL`.
 ///
 /// # Example
 ///
@@ -23,16 +47,375 @@ use crate::rusqlite::{Connection, Result};
 /// let result: Vec<u8> = db.query_row("SELECT bspatch4('013479', bsdiff4('013479', '23456789'))", [], |r| r.get(0))?;
 /// let expected = b"23456789";
 /// assert_eq!(result, expected);
+/// let result: bool = db.query_row("SELECT bspatch4_test('013479', bsdiff4('013479', '23456789'))", [], |r| r.get(0))?;
+/// assert!(result);
+/// let result: String = db.query_row("SELECT bsdiff4_controls(bsdiff4('013479', '23456789'))", [], |r| r.get(0))?;
+/// assert_eq!(result, r#"[{"add":0,"copy":7,"seek":5},{"add":1,"copy":0,"seek":0}]"#);
+/// let result: i64 = db.query_row("SELECT sum(value->>'copy') FROM json_each(bsdiff4_controls(bsdiff4('013479', '23456789')))", [], |r| r.get(0))?;
+/// assert_eq!(result, 7);
+/// let sql = r#"SELECT bspatch4('013479', bsdiff4('013479', '23456789', '{"level":9,"parallel_chunk":0}'))"#;
+/// let result: Vec<u8> = db.query_row(sql, [], |r| r.get(0))?;
+/// assert_eq!(result, expected);
+/// let sql = "SELECT bsdiff4_best_base_id(b), bspatch4('hello world, hello world!', bsdiff4_best_base_patch(b)) FROM (SELECT
+///            bsdiff4_best_base(id, data, 'hello world, hello world') AS b FROM (SELECT 1 AS id, 'zzzz' AS data UNION ALL SELECT 2, 'hello world, hello world!'))";
+/// let (id, result): (i64, Vec<u8>) = db.query_row(sql, [], |r| Ok((r.get(0)?, r.get(1)?)))?;
+/// assert_eq!((id, result.as_slice()), (2, &b"hello world, hello world"[..]));
 /// # Ok(())
 /// # }
 /// ```
 pub fn register_bsdiff4_functions(conn: &Connection) -> Result<()> {
-    register_differ::<Bsdiff4Differ>(conn)
+    register_bsdiff4_functions_with_limits(conn, &ConnLimits::default())
+}
+
+pub(crate) fn register_bsdiff4_functions_with_limits(
+    conn: &Connection,
+    limits: &ConnLimits,
+) -> Result<()> {
+    // FunctionFlags derive Copy trait only in v0.31+, but we support v0.30+
+    macro_rules! flags {
+        () => {
+            FunctionFlags::SQLITE_UTF8
+                | FunctionFlags::SQLITE_DETERMINISTIC
+                | FunctionFlags::SQLITE_DIRECTONLY
+        };
+    }
+
+    register_differ::<Bsdiff4Differ>(conn, limits)?;
+    conn.create_scalar_function("bsdiff4_info", 1, flags!(), info_fn)?;
+    conn.create_scalar_function("bsdiff4_controls", 1, flags!(), controls_fn)?;
+    conn.create_aggregate_function(
+        "bsdiff4_best_base",
+        3,
+        flags!(),
+        BestBase {
+            limits: limits.clone(),
+        },
+    )?;
+    conn.create_scalar_function("bsdiff4_best_base_id", 1, flags!(), best_base_id_fn)?;
+    conn.create_scalar_function("bsdiff4_best_base_patch", 1, flags!(), best_base_patch_fn)
+}
+
+fn info_fn(ctx: &Context) -> Result<Option<String>> {
+    let Some(patch) = get_bytes(ctx, 0)? else {
+        return Ok(None);
+    };
+    let info = Bsdiff4Differ::info(patch)?;
+    Ok(Some(format!(
+        r#"{{"target_size":{},"control_size":{},"diff_size":{},"extra_size":{}}}"#,
+        info.target_size, info.control_size, info.diff_size, info.extra_size
+    )))
+}
+
+fn controls_fn(ctx: &Context) -> Result<Option<String>> {
+    let Some(patch) = get_bytes(ctx, 0)? else {
+        return Ok(None);
+    };
+    let controls: Vec<_> = Bsdiff4Differ::controls(patch)?
+        .iter()
+        .map(|c| format!(r#"{{"add":{},"copy":{},"seek":{}}}"#, c.add, c.copy, c.seek))
+        .collect();
+    Ok(Some(format!("[{}]", controls.join(","))))
+}
+
+fn best_base_id_fn(ctx: &Context) -> Result<Option<Value>> {
+    let Some(result) = get_bytes(ctx, 0)? else {
+        return Ok(None);
+    };
+    let (id, _) = parse_best_base(result)?;
+    Ok(Some(match id {
+        serde_json::Value::String(id) => Value::Text(id),
+        id => Value::Integer(id.as_i64().ok_or_else(invalid_best_base)?),
+    }))
+}
+
+fn best_base_patch_fn(ctx: &Context) -> Result<Option<Vec<u8>>> {
+    let Some(result) = get_bytes(ctx, 0)? else {
+        return Ok(None);
+    };
+    Ok(Some(parse_best_base(result)?.1.to_vec()))
+}
+
+/// The result of `bsdiff4_best_base`: the [`BEST_BASE_MAGIC`] header, the id of the best candidate as JSON
+/// followed by a newline, which compact JSON never contains, and the patch.
+const BEST_BASE_MAGIC: &[u8] = b"SQLCBB1\0";
+
+fn parse_best_base(result: &[u8]) -> Result<(serde_json::Value, &[u8])> {
+    let (id, patch) = result
+        .strip_prefix(BEST_BASE_MAGIC)
+        .and_then(|body| body.split_at_checked(body.iter().position(|&b| b == b'\n')?))
+        .ok_or_else(invalid_best_base)?;
+    let id = serde_json::from_slice(id).map_err(|_| invalid_best_base())?;
+    Ok((id, &patch[1..]))
+}
+
+fn invalid_best_base() -> rusqlite::Error {
+    UserFunctionError("not a bsdiff4_best_base() result".into())
+}
+
+/// Aggregate that finds the candidate base giving the smallest `bsdiff4` patch for the target.
+/// Every candidate gets a cheap similarity score, and only the best few are kept and fully diffed.
+struct BestBase {
+    limits: ConnLimits,
 }
 
+/// Number of candidates with the best similarity scores that are fully diffed.
+const BEST_BASE_CANDIDATES: usize = 3;
+
+/// Maximum number of sampled target windows used for the similarity scores.
+const MAX_SAMPLE: usize = 1 << 16;
+
+#[derive(Default)]
+struct BestBaseState {
+    target: Option<Vec<u8>>,
+    /// Sampling rate of the windows, and the sampled window hashes of the target
+    rate: u64,
+    sample: HashSet<u64>,
+    /// The best candidates so far, ordered by descending score, then by row
+    candidates: Vec<Candidate>,
+}
+
+struct Candidate {
+    id: serde_json::Value,
+    data: Vec<u8>,
+    score: usize,
+}
+
+/// Hashes of all 8-byte windows of the data that are sampled at the given rate.
+fn sampled_windows(data: &[u8], rate: u64) -> impl Iterator<Item = u64> + '_ {
+    data.windows(8)
+        .map(|w| {
+            let hash = u64::from_le_bytes(w.try_into().unwrap_or_default())
+                .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            hash ^ (hash >> 29)
+        })
+        .filter(move |hash| hash % rate == 0)
+}
+
+impl BestBaseState {
+    /// The number of distinct sampled target windows that also appear in the candidate.
+    fn score(&self, candidate: &[u8]) -> usize {
+        let mut found = HashSet::new();
+        for hash in sampled_windows(candidate, self.rate) {
+            if self.sample.contains(&hash) {
+                found.insert(hash);
+            }
+        }
+        found.len()
+    }
+}
+
+impl Aggregate<BestBaseState, Option<Vec<u8>>> for BestBase {
+    fn init(&self, _: &mut Context<'_>) -> Result<BestBaseState> {
+        Ok(BestBaseState::default())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut BestBaseState) -> Result<()> {
+        let id = match ctx.get_raw(0) {
+            ValueRef::Integer(v) => serde_json::Value::from(v),
+            ValueRef::Text(v) => serde_json::Value::from(String::from_utf8_lossy(v)),
+            ValueRef::Null => return Ok(()),
+            v => return Err(InvalidFunctionParameterType(0, v.data_type())),
+        };
+        let (Some(data), Some(target)) = (get_bytes(ctx, 1)?, get_bytes(ctx, 2)?) else {
+            return Ok(());
+        };
+        match &acc.target {
+            Some(prev) if prev != target => {
+                return Err(UserFunctionError(
+                    "bsdiff4_best_base() target must be the same in all rows".into(),
+                ));
+            }
+            Some(_) => {}
+            None => {
+                // Sample every window of small targets, so that their scores are not all zero
+                acc.rate = (target.len() / MAX_SAMPLE).max(1) as u64;
+                acc.sample = sampled_windows(target, acc.rate).collect();
+                acc.target = Some(target.to_vec());
+            }
+        }
+
+        let score = acc.score(data);
+        if acc.candidates.len() == BEST_BASE_CANDIDATES
+            && acc.candidates.last().is_some_and(|c| c.score >= score)
+        {
+            return Ok(());
+        }
+        let pos = acc.candidates.partition_point(|c| c.score >= score);
+        acc.candidates.insert(
+            pos,
+            Candidate {
+                id,
+                data: data.to_vec(),
+                score,
+            },
+        );
+        acc.candidates.truncate(BEST_BASE_CANDIDATES);
+        Ok(())
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, acc: Option<BestBaseState>) -> Result<Option<Vec<u8>>> {
+        let Some(BestBaseState {
+            target: Some(target),
+            candidates,
+            ..
+        }) = acc
+        else {
+            return Ok(None);
+        };
+        let mut best: Option<(serde_json::Value, Vec<u8>)> = None;
+        for candidate in candidates {
+            let patch =
+                run_diff::<Bsdiff4Differ>(&candidate.data, &target, None, self.limits.get())?;
+            if best
+                .as_ref()
+                .is_none_or(|(_, best)| patch.len() < best.len())
+            {
+                best = Some((candidate.id, patch));
+            }
+        }
+        Ok(best.map(|(id, patch)| {
+            let id = id.to_string();
+            let mut result = Vec::with_capacity(BEST_BASE_MAGIC.len() + id.len() + 1 + patch.len());
+            result.extend_from_slice(BEST_BASE_MAGIC);
+            result.extend_from_slice(id.as_bytes());
+            result.push(b'\n');
+            result.extend_from_slice(&patch);
+            result
+        }))
+    }
+}
+
+pub(crate) const BSDIFF40_MAGIC: &[u8] = b"BSDIFF40";
+
 pub struct Bsdiff4Differ;
 
+/// Header values of a `BSDIFF40` patch, as returned by [`Bsdiff4Differ::info`].
+#[expect(clippy::struct_field_names)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bsdiff4Info {
+    /// Size of the target produced by the patch
+    pub target_size: u64,
+    /// Size of the bzip2-compressed control block
+    pub control_size: u64,
+    /// Size of the bzip2-compressed diff block
+    pub diff_size: u64,
+    /// Size of the bzip2-compressed extra block
+    pub extra_size: u64,
+}
+
+/// Options for [`Bsdiff4Differ::diff_options`]. Any value that is not set uses the qbsdiff default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bsdiff4Options {
+    /// The bzip2 compression level of the patch blocks, from 1 to 9 (6 by default)
+    pub level: Option<u32>,
+    /// The buffer size used to compute the delta, at least 128 bytes (4096 by default)
+    pub buffer_size: Option<usize>,
+    /// The size of the target chunks that are searched in parallel, at least 256 KiB, or 0 to search in a single thread.
+    /// By default, the chunk size is chosen automatically.
+    pub parallel_chunk: Option<usize>,
+}
+
+impl Bsdiff4Options {
+    /// Parse the options from a JSON object like `{"level":9,"buffer_size":65536,"parallel_chunk":1048576}`.
+    /// A boolean `"verified"` member is also accepted, because the `bsdiff4()` SQL function uses it to wrap
+    /// the patch in a verified envelope, but it does not change the options.
+    pub fn from_json(json: &[u8]) -> Result<Self, CompressionError> {
+        let invalid = |msg: String| {
+            CompressionError::invalid_option("bsdiff4", format!("Invalid bsdiff4() options: {msg}"))
+        };
+        let Ok(serde_json::Value::Object(map)) = serde_json::from_slice(json) else {
+            return Err(invalid("expected a JSON object".into()));
+        };
+        let mut options = Self::default();
+        for (key, value) in map {
+            let int = || {
+                value
+                    .as_u64()
+                    .and_then(|v| usize::try_from(v).ok())
+                    .ok_or_else(|| invalid(format!("\"{key}\" must be a non-negative integer")))
+            };
+            match key.as_str() {
+                "level" => {
+                    options.level = Some(
+                        u32::try_from(int()?)
+                            .ok()
+                            .filter(|v| (1..=9).contains(v))
+                            .ok_or_else(|| invalid("\"level\" must be between 1 and 9".into()))?,
+                    );
+                }
+                "buffer_size" => options.buffer_size = Some(int()?),
+                "parallel_chunk" => options.parallel_chunk = Some(int()?),
+                "verified" if value.is_boolean() => {}
+                "verified" => return Err(invalid("\"verified\" must be a boolean".into())),
+                _ => return Err(invalid(format!("unknown option \"{key}\""))),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// A single bsdiff instruction: add `add` bytes of the diff block to the source,
+/// copy `copy` bytes from the extra block, and move the source position by `seek` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bsdiff4Control {
+    pub add: u64,
+    pub copy: u64,
+    pub seek: i64,
+}
+
+impl Bsdiff4Differ {
+    /// Parse the header of a `BSDIFF40` patch without decompressing it.
+    pub fn info(patch: &[u8]) -> Result<Bsdiff4Info, CompressionError> {
+        let blocks =
+            PatchBlocks::parse(patch).map_err(|e| CompressionError::decode_io("bsdiff4", e))?;
+        Ok(Bsdiff4Info {
+            target_size: blocks.target_size,
+            control_size: blocks.ctrl.len() as u64,
+            diff_size: blocks.diff.len() as u64,
+            extra_size: blocks.extra.len() as u64,
+        })
+    }
+
+    /// Create a `BSDIFF40` patch using the given compression and search options.
+    pub fn diff_options(
+        source: &[u8],
+        target: &[u8],
+        options: &Bsdiff4Options,
+    ) -> Result<Vec<u8>, CompressionError> {
+        let mut bsdiff = Bsdiff::new(source, target);
+        if let Some(level) = options.level {
+            bsdiff = bsdiff.compression_level(level);
+        }
+        if let Some(buffer_size) = options.buffer_size {
+            bsdiff = bsdiff.buffer_size(buffer_size);
+        }
+        match options.parallel_chunk {
+            Some(0) => bsdiff = bsdiff.parallel_scheme(ParallelScheme::Never),
+            Some(chunk) => bsdiff = bsdiff.parallel_scheme(ParallelScheme::ChunkSize(chunk)),
+            None => {}
+        }
+        let mut patch = Vec::new();
+        bsdiff
+            .compare(Cursor::new(&mut patch))
+            .map_err(|e| CompressionError::io("bsdiff4", e))?;
+        Ok(patch)
+    }
+
+    /// Decode all control tuples of a `BSDIFF40` patch.
+    pub fn controls(patch: &[u8]) -> Result<Vec<Bsdiff4Control>, CompressionError> {
+        PatchBlocks::parse(patch)
+            .and_then(|blocks| blocks.controls().collect())
+            .map_err(|e| CompressionError::decode_io("bsdiff4", e))
+    }
+}
+
 impl Differ for Bsdiff4Differ {
+    type Options = Bsdiff4Options;
+
+    fn algorithm() -> &'static str {
+        "bsdiff4"
+    }
+
     fn diff_name() -> &'static str {
         "bsdiff4"
     }
@@ -41,19 +424,275 @@ impl Differ for Bsdiff4Differ {
         "bspatch4"
     }
 
-    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>> {
-        let mut patch = Vec::new();
-        Bsdiff::new(source, target)
-            .compare(Cursor::new(&mut patch))
-            .map_err(|e| UserFunctionError(e.into()))?;
-        Ok(patch)
+    fn test_name() -> &'static str {
+        "bspatch4_test"
     }
 
-    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    fn chain_name() -> &'static str {
+        "bspatch4_chain"
+    }
+
+    fn prev_name() -> &'static str {
+        "bsdiff4_prev"
+    }
+
+    const DIFF_OPTIONS: bool = true;
+
+    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        Self::diff_options(source, target, &Bsdiff4Options::default())
+    }
+
+    fn parse_options(value: ValueRef<'_>) -> Result<Bsdiff4Options> {
+        match value {
+            ValueRef::Integer(level) => Ok(Bsdiff4Options {
+                level: Some(u32::try_from(level).ok().filter(|v| (1..=9).contains(v)).ok_or_else(|| {
+                    CompressionError::invalid_level(
+                        "bsdiff4",
+                        "The optional third argument to bsdiff4() must be a compression level between 1 and 9, or a JSON object",
+                    )
+                })?),
+                ..Bsdiff4Options::default()
+            }),
+            ValueRef::Text(json) => Ok(Bsdiff4Options::from_json(json)?),
+            v => Err(InvalidFunctionParameterType(2, v.data_type())),
+        }
+    }
+
+    fn is_verified(value: ValueRef<'_>) -> bool {
+        match value {
+            ValueRef::Text(b"verified") => true,
+            ValueRef::Text(json) => serde_json::from_slice::<serde_json::Value>(json)
+                .is_ok_and(|v| v.get("verified") == Some(&serde_json::Value::Bool(true))),
+            _ => false,
+        }
+    }
+
+    fn diff_with_options(
+        source: &[u8],
+        target: &[u8],
+        options: &Bsdiff4Options,
+    ) -> Result<Vec<u8>, CompressionError> {
+        Self::diff_options(source, target, options)
+    }
+
+    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CompressionError> {
         let mut target = Vec::new();
-        Bspatch::new(patch)
-            .and_then(|patch| patch.apply(source, Cursor::new(&mut target)))
-            .map_err(|e| UserFunctionError(e.into()))?;
+        Self::patch_into(source, patch, &mut target)?;
         Ok(target)
     }
+
+    fn patch_into(
+        source: &[u8],
+        patch: &[u8],
+        target: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        target.clear();
+        if patch.starts_with(BSDIFF43_MAGIC) {
+            return bsdiff43::apply(Some(source), patch, target)
+                .map_err(|e| CompressionError::decode_io("bsdiff43", e));
+        }
+        Bspatch::new(patch)
+            .and_then(|patch| patch.apply(source, target))
+            .map_err(|e| CompressionError::decode_io("bsdiff4", e))?;
+        Ok(())
+    }
+
+    fn test(patch: &[u8], source: Option<&[u8]>) -> bool {
+        if patch.starts_with(BSDIFF43_MAGIC) {
+            return bsdiff43::apply(source, patch, &mut Vec::new()).is_ok();
+        }
+        check_patch(patch, source).is_ok()
+    }
+}
+
+/// Validate the patch structure without producing the target:
+/// all three blocks must decompress, and the control tuples must match the decompressed sizes.
+/// If the source is given, every add instruction must stay within the source.
+fn check_patch(patch: &[u8], source: Option<&[u8]>) -> io::Result<()> {
+    let blocks = PatchBlocks::parse(patch)?;
+    let mut add_total = 0_u64;
+    let mut copy_total = 0_u64;
+    let mut pos = 0_u64;
+    for ctrl in blocks.controls() {
+        let ctrl = ctrl?;
+        add_total = add_total.checked_add(ctrl.add).ok_or_else(invalid_patch)?;
+        copy_total = copy_total
+            .checked_add(ctrl.copy)
+            .ok_or_else(invalid_patch)?;
+        if let Some(source) = source {
+            pos = pos.checked_add(ctrl.add).ok_or_else(invalid_patch)?;
+            if ctrl.add > 0 && pos > source.len() as u64 {
+                return Err(invalid_patch());
+            }
+            pos = pos
+                .checked_add_signed(ctrl.seek)
+                .ok_or_else(invalid_patch)?;
+        }
+    }
+    if add_total.checked_add(copy_total) != Some(blocks.target_size)
+        || decompressed_len(blocks.diff, add_total)? != add_total
+        || decompressed_len(blocks.extra, copy_total)? != copy_total
+    {
+        return Err(invalid_patch());
+    }
+    Ok(())
+}
+
+/// Decompressed length of a block, reading at most one byte past the `expected` length from the header,
+/// so that a small block that decompresses to a huge stream is rejected early.
+fn decompressed_len(block: &[u8], expected: u64) -> io::Result<u64> {
+    let limit = expected.saturating_add(1);
+    io::copy(&mut BzDecoder::new(block).take(limit), &mut io::sink())
+}
+
+fn invalid_patch() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not a valid BSDIFF40 patch")
+}
+
+/// A `BSDIFF40` patch split into its header values and three bzip2-compressed blocks.
+pub(crate) struct PatchBlocks<'a> {
+    pub(crate) target_size: u64,
+    pub(crate) ctrl: &'a [u8],
+    pub(crate) diff: &'a [u8],
+    pub(crate) extra: &'a [u8],
+}
+
+impl<'a> PatchBlocks<'a> {
+    pub(crate) fn parse(patch: &'a [u8]) -> io::Result<Self> {
+        let Some((header, body)) = patch.split_at_checked(32) else {
+            return Err(invalid_patch());
+        };
+        if !header.starts_with(BSDIFF40_MAGIC) {
+            return Err(invalid_patch());
+        }
+        let ctrl_len = usize::try_from(read_int(&header[8..16])).map_err(|_| invalid_patch())?;
+        let diff_len = usize::try_from(read_int(&header[16..24])).map_err(|_| invalid_patch())?;
+        let target_size = u64::try_from(read_int(&header[24..32])).map_err(|_| invalid_patch())?;
+        let (ctrl, body) = body.split_at_checked(ctrl_len).ok_or_else(invalid_patch)?;
+        let (diff, extra) = body.split_at_checked(diff_len).ok_or_else(invalid_patch)?;
+        Ok(Self {
+            target_size,
+            ctrl,
+            diff,
+            extra,
+        })
+    }
+
+    pub(crate) fn controls(&self) -> Controls<'a> {
+        Controls {
+            decoder: BzDecoder::new(self.ctrl),
+            remaining: self.target_size,
+            prev_empty: false,
+        }
+    }
+}
+
+/// Iterator over the decompressed control block. Like bspatch, it stops once the tuples add up to the target size,
+/// so a small patch cannot make it decompress an unbounded control block.
+pub(crate) struct Controls<'a> {
+    decoder: BzDecoder<&'a [u8]>,
+    /// Number of target bytes not yet produced by the tuples
+    remaining: u64,
+    /// Tuples without any data only seek, so a valid patch never has two of them in a row
+    prev_empty: bool,
+}
+
+impl Iterator for Controls<'_> {
+    type Item = io::Result<Bsdiff4Control>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let mut buf = [0; 24];
+        let mut len = 0;
+        while len < buf.len() {
+            match self.decoder.read(&mut buf[len..]) {
+                Ok(0) if len == 0 => return None,
+                Ok(0) => return Some(Err(invalid_patch())),
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        let (Ok(add), Ok(copy)) = (
+            u64::try_from(read_int(&buf[0..8])),
+            u64::try_from(read_int(&buf[8..16])),
+        ) else {
+            return Some(Err(invalid_patch()));
+        };
+        let empty = add == 0 && copy == 0;
+        let Some(remaining) = add
+            .checked_add(copy)
+            .and_then(|len| self.remaining.checked_sub(len))
+            .filter(|_| !(empty && self.prev_empty))
+        else {
+            return Some(Err(invalid_patch()));
+        };
+        self.remaining = remaining;
+        self.prev_empty = empty;
+        let seek = read_int(&buf[16..24]);
+        Some(Ok(Bsdiff4Control { add, copy, seek }))
+    }
+}
+
+/// Read a sign-magnitude little-endian 64-bit integer, as used by bsdiff.
+pub(crate) fn read_int(buf: &[u8]) -> i64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(buf);
+    let value = u64::from_le_bytes(bytes);
+    // the magnitude is at most 2^63-1, so it always fits into i64
+    #[expect(clippy::cast_possible_wrap)]
+    let magnitude = (value & !(1 << 63)) as i64;
+    if value >> 63 == 0 {
+        magnitude
+    } else {
+        -magnitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use bzip2::write::BzEncoder;
+    use bzip2::Compression;
+
+    use super::*;
+
+    /// Build a `BSDIFF40` patch with the given target size and control tuples, and empty diff and extra blocks.
+    fn patch(target_size: u64, controls: &[[u64; 3]], repeat: usize) -> Vec<u8> {
+        let mut ctrl = BzEncoder::new(Vec::new(), Compression::best());
+        for _ in 0..repeat {
+            for value in controls.iter().flatten() {
+                ctrl.write_all(&value.to_le_bytes()).unwrap();
+            }
+        }
+        let ctrl = ctrl.finish().unwrap();
+        let empty = BzEncoder::new(Vec::new(), Compression::best())
+            .finish()
+            .unwrap();
+        let mut patch = BSDIFF40_MAGIC.to_vec();
+        patch.extend_from_slice(&(ctrl.len() as u64).to_le_bytes());
+        patch.extend_from_slice(&(empty.len() as u64).to_le_bytes());
+        patch.extend_from_slice(&target_size.to_le_bytes());
+        patch.extend_from_slice(&ctrl);
+        patch.extend_from_slice(&empty);
+        patch.extend_from_slice(&empty);
+        patch
+    }
+
+    #[test]
+    fn test_controls_bounded() {
+        // Tuples past the target size are not decoded
+        let controls = Bsdiff4Differ::controls(&patch(5, &[[0, 5, 0]], 10_000)).unwrap();
+        assert_eq!(controls.len(), 1);
+        // A tuple that goes past the target size
+        assert!(Bsdiff4Differ::controls(&patch(5, &[[0, 6, 0]], 1)).is_err());
+        // Empty tuples that never reach the target size
+        assert!(Bsdiff4Differ::controls(&patch(5, &[[0, 0, 1]], 10_000)).is_err());
+        // A single empty tuple only seeks
+        let controls = Bsdiff4Differ::controls(&patch(5, &[[0, 0, 1], [0, 5, 0]], 1)).unwrap();
+        assert_eq!(controls.len(), 2);
+    }
 }
@@ -0,0 +1,214 @@
+use std::io::{self, Read, Write};
+
+use bzip2::read::BzDecoder;
+use bzip2::write::BzEncoder;
+use bzip2::Compression;
+
+use crate::bsdiff4::{read_int, PatchBlocks, BSDIFF40_MAGIC};
+use crate::common_diff::{register_differ, ConnLimits, Differ};
+use crate::error::CompressionError;
+use crate::rusqlite::{Connection, Result};
+use crate::Bsdiff4Differ;
+
+/// Register the `bsdiff43` and `bspatch43` SQL functions with the given `SQLite` connection.
+/// The `bsdiff43` function takes two arguments, and returns the binary difference as an `ENDSLEY/BSDIFF43` patch (blob),
+/// the format used by the current [bsdiff](https://github.com/mendsley/bsdiff#readme) tool.
+/// Unlike `BSDIFF40`, it stores all control tuples, diff, and extra data in a single bzip2 stream.
+/// The arguments can be either a string or a blob.
+/// The `bspatch43` function applies both `ENDSLEY/BSDIFF43` and `BSDIFF40` patches, detecting the format by its header,
+/// and `bspatch4` does the same.
+/// The `bspatch43_test` function checks that a patch is well-formed, and if the source is given as the first argument,
+/// that the patch can be applied to it.
+/// The `bspatch43_chain` aggregate function applies a series of patches in order to the base blob from the first row,
+/// e.g. `bspatch43_chain(base, patch ORDER BY version)`.
+/// If any of the arguments are `NULL`, the result is `NULL`.
+///
+/// # Example
+///
+/// ```
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::register_bsdiff43_functions;
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// register_bsdiff43_functions(&db)?;
+/// let result: Vec<u8> = db.query_row("SELECT bspatch43('013479', bsdiff43('013479', '23456789'))", [], |r| r.get(0))?;
+/// let expected = b"23456789";
+/// assert_eq!(result, expected);
+/// let result: String = db.query_row("SELECT CAST(substr(bsdiff43('013479', '23456789'), 1, 16) AS TEXT)", [], |r| r.get(0))?;
+/// assert_eq!(result, "ENDSLEY/BSDIFF43");
+/// let result: bool = db.query_row("SELECT bspatch43_test('013479', bsdiff43('013479', '23456789'))", [], |r| r.get(0))?;
+/// assert!(result);
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_bsdiff43_functions(conn: &Connection) -> Result<()> {
+    register_bsdiff43_functions_with_limits(conn, &ConnLimits::default())
+}
+
+pub(crate) fn register_bsdiff43_functions_with_limits(
+    conn: &Connection,
+    limits: &ConnLimits,
+) -> Result<()> {
+    register_differ::<Bsdiff43Differ>(conn, limits)
+}
+
+pub(crate) const BSDIFF43_MAGIC: &[u8] = b"ENDSLEY/BSDIFF43";
+
+pub struct Bsdiff43Differ;
+
+impl Bsdiff43Differ {
+    /// Re-encode a `BSDIFF40` patch as `ENDSLEY/BSDIFF43`, interleaving the three blocks into a single stream.
+    fn from_bsdiff40(patch: &[u8]) -> io::Result<Vec<u8>> {
+        let blocks = PatchBlocks::parse(patch)?;
+        let mut diff = BzDecoder::new(blocks.diff);
+        let mut extra = BzDecoder::new(blocks.extra);
+
+        let mut result = BSDIFF43_MAGIC.to_vec();
+        result.extend_from_slice(&write_int(blocks.target_size));
+        let mut encoder = BzEncoder::new(result, Compression::best());
+        for ctrl in blocks.controls() {
+            let ctrl = ctrl?;
+            encoder.write_all(&write_int(ctrl.add))?;
+            encoder.write_all(&write_int(ctrl.copy))?;
+            encoder.write_all(&write_int_signed(ctrl.seek))?;
+            copy_exact(&mut diff, &mut encoder, ctrl.add)?;
+            copy_exact(&mut extra, &mut encoder, ctrl.copy)?;
+        }
+        encoder.finish()
+    }
+}
+
+impl Differ for Bsdiff43Differ {
+    type Options = ();
+
+    fn algorithm() -> &'static str {
+        "bsdiff43"
+    }
+
+    fn diff_name() -> &'static str {
+        "bsdiff43"
+    }
+
+    fn patch_name() -> &'static str {
+        "bspatch43"
+    }
+
+    fn test_name() -> &'static str {
+        "bspatch43_test"
+    }
+
+    fn chain_name() -> &'static str {
+        "bspatch43_chain"
+    }
+
+    fn prev_name() -> &'static str {
+        "bsdiff43_prev"
+    }
+
+    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let patch = Bsdiff4Differ::diff(source, target)?;
+        Self::from_bsdiff40(&patch).map_err(|e| CompressionError::io("bsdiff43", e))
+    }
+
+    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let mut target = Vec::new();
+        Self::patch_into(source, patch, &mut target)?;
+        Ok(target)
+    }
+
+    fn patch_into(
+        source: &[u8],
+        patch: &[u8],
+        target: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        if patch.starts_with(BSDIFF40_MAGIC) {
+            return Bsdiff4Differ::patch_into(source, patch, target);
+        }
+        target.clear();
+        apply(Some(source), patch, target).map_err(|e| CompressionError::decode_io("bsdiff43", e))
+    }
+
+    fn test(patch: &[u8], source: Option<&[u8]>) -> bool {
+        if patch.starts_with(BSDIFF40_MAGIC) {
+            return Bsdiff4Differ::test(patch, source);
+        }
+        apply(source, patch, &mut Vec::new()).is_ok()
+    }
+}
+
+/// Apply an `ENDSLEY/BSDIFF43` patch. Without the source, only the patch structure is validated,
+/// and the diff bytes are written to the target as is.
+pub(crate) fn apply(source: Option<&[u8]>, patch: &[u8], target: &mut Vec<u8>) -> io::Result<()> {
+    let Some(body) = patch.strip_prefix(BSDIFF43_MAGIC) else {
+        return Err(invalid_patch());
+    };
+    let (size, body) = body.split_at_checked(8).ok_or_else(invalid_patch)?;
+    let target_size = u64::try_from(read_int(size)).map_err(|_| invalid_patch())?;
+
+    let mut decoder = BzDecoder::new(body);
+    let mut ctrl = [0; 24];
+    let mut written = 0_u64;
+    let mut pos = 0_i64;
+    while written < target_size {
+        decoder.read_exact(&mut ctrl)?;
+        let (Ok(add), Ok(copy)) = (
+            u64::try_from(read_int(&ctrl[0..8])),
+            u64::try_from(read_int(&ctrl[8..16])),
+        ) else {
+            return Err(invalid_patch());
+        };
+        let seek = read_int(&ctrl[16..24]);
+        written = written
+            .checked_add(add)
+            .and_then(|v| v.checked_add(copy))
+            .filter(|&v| v <= target_size)
+            .ok_or_else(invalid_patch)?;
+
+        let start = target.len();
+        copy_exact(&mut decoder, target, add)?;
+        if let Some(source) = source {
+            let old = usize::try_from(pos)
+                .ok()
+                .and_then(|pos| source.get(pos..pos.checked_add(target.len() - start)?))
+                .ok_or_else(invalid_patch)?;
+            for (new, old) in target[start..].iter_mut().zip(old) {
+                *new = new.wrapping_add(*old);
+            }
+        }
+        copy_exact(&mut decoder, target, copy)?;
+
+        pos = i64::try_from(add)
+            .ok()
+            .and_then(|add| pos.checked_add(add)?.checked_add(seek))
+            .ok_or_else(invalid_patch)?;
+    }
+    // Make sure that nothing is left in the stream
+    if decoder.read(&mut [0])? == 0 {
+        Ok(())
+    } else {
+        Err(invalid_patch())
+    }
+}
+
+/// Copy exactly `len` bytes from the reader, without allocating more than what the reader actually provides.
+fn copy_exact(reader: &mut impl Read, writer: &mut impl Write, len: u64) -> io::Result<()> {
+    if io::copy(&mut reader.take(len), writer)? == len {
+        Ok(())
+    } else {
+        Err(invalid_patch())
+    }
+}
+
+fn invalid_patch() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not a valid BSDIFF43 patch")
+}
+
+/// Write a non-negative value as a sign-magnitude little-endian 64-bit integer, as used by bsdiff.
+fn write_int(value: u64) -> [u8; 8] {
+    value.to_le_bytes()
+}
+
+fn write_int_signed(value: i64) -> [u8; 8] {
+    let sign = if value < 0 { 1 << 63 } else { 0 };
+    (value.unsigned_abs() | sign).to_le_bytes()
+}
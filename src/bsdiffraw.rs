@@ -1,13 +1,25 @@
-use std::io::Cursor;
+use std::io::{self, Cursor};
+use std::time::Instant;
 
-use rusqlite::Error::UserFunctionError;
+use rusqlite::types::ValueRef;
+use suffix_array::{SuffixArray, MAX_LENGTH};
 
-use crate::common_diff::{register_differ, Differ};
+use crate::common_diff::{diff_deadline, register_differ, ConnLimits, Differ};
+use crate::error::CompressionError;
 use crate::rusqlite::{Connection, Result};
 
 /// Register the `bsdiffraw` and `bspatchraw` SQL functions with the given `SQLite` connection.
 /// The `bsdiffraw` function takes two arguments, and returns the [BSDiff delta](https://github.com/mendsley/bsdiff#readme) (blob) of the binary difference.
 /// The arguments can be either a string or a blob.
+/// If the optional third argument is `'versioned'`, the patch starts with a header that records the format version,
+/// so that it can still be applied if the raw format of the bsdiff crate changes. `bspatchraw` applies patches
+/// with or without the header.
+/// If the optional third argument is `'verified'`, the patch also contains the SHA-256 hashes of the source and
+/// the target. Applying it fails if the source is not the one it was created for, or if the result is wrong.
+/// The `bspatchraw_test` function checks that a patch is well-formed, and if the source is given as the first argument,
+/// that the patch can be applied to it.
+/// The `bspatchraw_chain` aggregate function applies a series of patches in order to the base blob from the first row,
+/// e.g. `bspatchraw_chain(base, patch ORDER BY version)`.
 /// If any of the arguments are `NULL`, the result is `NULL`.
 ///
 /// # Example
@@ -25,16 +37,66 @@ use crate::rusqlite::{Connection, Result};
 /// let result: Vec<u8> = db.query_row("SELECT bspatchraw('013479', bsdiffraw('013479', '23456789'))", [], |r| r.get(0))?;
 /// let expected = b"23456789";
 /// assert_eq!(result, expected);
+/// let result: bool = db.query_row("SELECT bspatchraw_test('013479', bsdiffraw('013479', '23456789'))", [], |r| r.get(0))?;
+/// assert!(result);
 /// # Ok(())
 /// # }
 /// ```
 pub fn register_bsdiffraw_functions(conn: &Connection) -> Result<()> {
-    register_differ::<BsdiffRawDiffer>(conn)
+    register_bsdiffraw_functions_with_limits(conn, &ConnLimits::default())
 }
 
+pub(crate) fn register_bsdiffraw_functions_with_limits(
+    conn: &Connection,
+    limits: &ConnLimits,
+) -> Result<()> {
+    register_differ::<BsdiffRawDiffer>(conn, limits)
+}
+
+/// The optional header of a bsdiffraw patch: this magic followed by the format version byte.
+const BSDIFFRAW_MAGIC: &[u8] = b"BSDIFFRAW";
+
+/// The format version of the patches created by the bsdiff crate 0.2.
+const FORMAT_VERSION: u8 = 1;
+
 pub struct BsdiffRawDiffer;
 
+/// Options for [`BsdiffRawDiffer`], set by the third argument of the `bsdiffraw` SQL function.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BsdiffRawOptions {
+    /// Create the patch with [`BsdiffRawDiffer::diff_versioned`]
+    pub versioned: bool,
+}
+
+impl BsdiffRawDiffer {
+    /// Same as [`Differ::diff`], but the patch starts with a header that records the format version.
+    pub fn diff_versioned(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let mut patch = BSDIFFRAW_MAGIC.to_vec();
+        patch.push(FORMAT_VERSION);
+        Self::diff_into(source, target, &mut patch)?;
+        Ok(patch)
+    }
+
+    fn diff_into(
+        source: &[u8],
+        target: &[u8],
+        patch: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        if source.len() > MAX_LENGTH {
+            bsdiff::diff(source, target, patch).map_err(|e| CompressionError::io("bsdiffraw", e))
+        } else {
+            diff(source, target, patch, diff_deadline())
+        }
+    }
+}
+
 impl Differ for BsdiffRawDiffer {
+    type Options = BsdiffRawOptions;
+
+    fn algorithm() -> &'static str {
+        "bsdiffraw"
+    }
+
     fn diff_name() -> &'static str {
         "bsdiffraw"
     }
@@ -43,18 +105,304 @@ impl Differ for BsdiffRawDiffer {
         "bspatchraw"
     }
 
-    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>> {
+    fn test_name() -> &'static str {
+        "bspatchraw_test"
+    }
+
+    fn chain_name() -> &'static str {
+        "bspatchraw_chain"
+    }
+
+    fn prev_name() -> &'static str {
+        "bsdiffraw_prev"
+    }
+
+    const DIFF_OPTIONS: bool = true;
+
+    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
         let mut patch = Vec::new();
-        bsdiff::diff(source, target, &mut patch).map_err(|e| UserFunctionError(e.into()))?;
+        Self::diff_into(source, target, &mut patch)?;
         Ok(patch)
     }
 
-    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    fn parse_options(value: ValueRef<'_>) -> Result<BsdiffRawOptions> {
+        match value {
+            ValueRef::Text(b"versioned") => Ok(BsdiffRawOptions { versioned: true }),
+            _ => Err(CompressionError::invalid_option(
+                "bsdiffraw",
+                "The optional third argument to bsdiffraw() must be 'versioned' or 'verified'",
+            )
+            .into()),
+        }
+    }
+
+    fn diff_with_options(
+        source: &[u8],
+        target: &[u8],
+        options: &BsdiffRawOptions,
+    ) -> Result<Vec<u8>, CompressionError> {
+        if options.versioned {
+            Self::diff_versioned(source, target)
+        } else {
+            Self::diff(source, target)
+        }
+    }
+
+    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CompressionError> {
         let mut target = Vec::new();
-        bsdiff::patch(source, &mut Cursor::new(patch), &mut target)
-            .map_err(|e| UserFunctionError(e.into()))?;
+        Self::patch_into(source, patch, &mut target)?;
         Ok(target)
     }
+
+    fn patch_into(
+        source: &[u8],
+        patch: &[u8],
+        target: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        let body = strip_header(patch)?;
+        let header_len = (patch.len() - body.len()) as u64;
+        target.clear();
+        let mut cursor = Cursor::new(body);
+        bsdiff::patch(source, &mut cursor, target).map_err(|e| {
+            CompressionError::decode_io_at("bsdiffraw", header_len + cursor.position(), e)
+        })
+    }
+
+    fn test(patch: &[u8], source: Option<&[u8]>) -> bool {
+        strip_header(patch).is_ok_and(|patch| check_patch(patch, source).is_some())
+    }
+}
+
+/// Remove the optional header from a patch, making sure its format version is supported.
+/// Patches without the header are assumed to be in the current format.
+fn strip_header(patch: &[u8]) -> Result<&[u8], CompressionError> {
+    match patch.strip_prefix(BSDIFFRAW_MAGIC) {
+        None => Ok(patch),
+        Some([FORMAT_VERSION, body @ ..]) => Ok(body),
+        Some([version, ..]) => Err(CompressionError::unsupported(
+            "bsdiffraw",
+            format!("Unsupported bsdiffraw patch format version {version}"),
+        )),
+        Some([]) => Err(CompressionError::corrupt_at(
+            "bsdiffraw",
+            BSDIFFRAW_MAGIC.len() as u64,
+            "Truncated bsdiffraw patch header",
+        )),
+    }
+}
+
+/// Create the same patch as `bsdiff::diff`, but with a much faster suffix array construction (divsufsort instead of
+/// qsufsort) that uses 4 bytes per source byte instead of 16. The source must not be longer than [`MAX_LENGTH`].
+/// Fails if the deadline is reached before the patch is complete.
+fn diff(
+    old: &[u8],
+    new: &[u8],
+    patch: &mut Vec<u8>,
+    deadline: Option<Instant>,
+) -> Result<(), CompressionError> {
+    let (_, sa) = SuffixArray::new(old).into_parts();
+    let matches = |old_pos: usize, new_pos: usize| old.get(old_pos) == Some(&new[new_pos]);
+
+    let mut scan = 0;
+    let mut len = 0;
+    let mut pos = 0;
+    let mut last_scan = 0;
+    let mut last_pos = 0;
+    let mut last_offset = 0_isize;
+    let mut steps = 0_u32;
+    while scan < new.len() {
+        // Find the next match that is at least 8 bytes better than just continuing the previous one
+        let mut old_score = 0;
+        scan += len;
+        let mut scsc = scan;
+        while scan < new.len() {
+            steps = steps.wrapping_add(1);
+            if steps % 4096 == 0 && deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                return Err(CompressionError::io(
+                    "bsdiffraw",
+                    io::Error::new(io::ErrorKind::TimedOut, "bsdiffraw() deadline exceeded"),
+                ));
+            }
+            (pos, len) = search(&sa, old, &new[scan..]);
+            while scsc < scan + len {
+                if scsc
+                    .checked_add_signed(last_offset)
+                    .is_some_and(|i| matches(i, scsc))
+                {
+                    old_score += 1;
+                }
+                scsc += 1;
+            }
+            if (len == old_score && len != 0) || len > old_score + 8 {
+                break;
+            }
+            if scan
+                .checked_add_signed(last_offset)
+                .is_some_and(|i| matches(i, scan))
+            {
+                old_score -= 1;
+            }
+            scan += 1;
+        }
+        if len == old_score && scan != new.len() {
+            continue;
+        }
+
+        let (len_fwd, len_back) = extend_matches(old, new, (last_scan, last_pos), (scan, pos));
+        let extra = last_scan + len_fwd..scan - len_back;
+        patch.extend_from_slice(&offtout(to_signed(len_fwd)));
+        patch.extend_from_slice(&offtout(to_signed(extra.len())));
+        patch.extend_from_slice(&offtout(
+            to_signed(pos - len_back) - to_signed(last_pos + len_fwd),
+        ));
+        patch.extend(
+            new[last_scan..last_scan + len_fwd]
+                .iter()
+                .zip(&old[last_pos..last_pos + len_fwd])
+                .map(|(n, o)| n.wrapping_sub(*o)),
+        );
+        patch.extend_from_slice(&new[extra]);
+
+        last_scan = scan - len_back;
+        last_pos = pos - len_back;
+        last_offset = to_signed(pos) - to_signed(scan);
+    }
+    Ok(())
+}
+
+/// Extend the previous match forward, and the next one backward, as long as at least half of the bytes match,
+/// returning both extension lengths. Each match is given as its position in `new` and in `old`.
+fn extend_matches(
+    old: &[u8],
+    new: &[u8],
+    (last_scan, last_pos): (usize, usize),
+    (scan, pos): (usize, usize),
+) -> (usize, usize) {
+    let mut score = 0_isize;
+    let mut best = 0_isize;
+    let mut len_fwd = 0;
+    let mut i = 0;
+    while last_scan + i < scan && last_pos + i < old.len() {
+        if old[last_pos + i] == new[last_scan + i] {
+            score += 1;
+        }
+        i += 1;
+        if score * 2 - to_signed(i) > best * 2 - to_signed(len_fwd) {
+            best = score;
+            len_fwd = i;
+        }
+    }
+    let mut len_back = 0;
+    if scan < new.len() {
+        let mut score = 0_isize;
+        let mut best = 0_isize;
+        let mut i = 1;
+        while scan >= last_scan + i && pos >= i {
+            if old[pos - i] == new[scan - i] {
+                score += 1;
+            }
+            if score * 2 - to_signed(i) > best * 2 - to_signed(len_back) {
+                best = score;
+                len_back = i;
+            }
+            i += 1;
+        }
+    }
+    // If the extensions overlap, split the overlapping part where it matches best
+    if last_scan + len_fwd > scan - len_back {
+        let overlap = last_scan + len_fwd - (scan - len_back);
+        let mut score = 0;
+        let mut best = 0;
+        let mut len_split = 0;
+        for i in 0..overlap {
+            if new[last_scan + len_fwd - overlap + i] == old[last_pos + len_fwd - overlap + i] {
+                score += 1;
+            }
+            if new[scan - len_back + i] == old[pos - len_back + i] {
+                score -= 1;
+            }
+            if score > best {
+                best = score;
+                len_split = i + 1;
+            }
+        }
+        len_fwd = len_fwd + len_split - overlap;
+        len_back -= len_split;
+    }
+
+    (len_fwd, len_back)
+}
+
+/// Find the longest match of `new` among the suffixes of `old`, with the same binary search as bsdiff,
+/// returning the match position in `old` and its length.
+fn search(mut sa: &[u32], old: &[u8], new: &[u8]) -> (usize, usize) {
+    fn match_len(old: &[u8], new: &[u8]) -> usize {
+        old.iter().zip(new).take_while(|(a, b)| a == b).count()
+    }
+
+    while sa.len() > 2 {
+        let mid = (sa.len() - 1) / 2;
+        let suffix = &old[sa[mid] as usize..];
+        let len = suffix.len().min(new.len());
+        sa = if suffix[..len] < new[..len] {
+            &sa[mid..]
+        } else {
+            &sa[..=mid]
+        };
+    }
+    let first = sa[0] as usize;
+    let last = sa[sa.len() - 1] as usize;
+    let first_len = match_len(&old[first..], new);
+    let last_len = match_len(&old[last..], new);
+    if first_len > last_len {
+        (first, first_len)
+    } else {
+        (last, last_len)
+    }
+}
+
+/// Positions and lengths never exceed [`MAX_LENGTH`], so they always fit into `isize`.
+#[expect(clippy::cast_possible_wrap)]
+fn to_signed(value: usize) -> isize {
+    value as isize
+}
+
+/// Write a sign-magnitude little-endian 64-bit integer, as used by bsdiff.
+fn offtout(value: isize) -> [u8; 8] {
+    let value = value as i64;
+    let sign = if value < 0 { 1 << 63 } else { 0 };
+    (value.unsigned_abs() | sign).to_le_bytes()
+}
+
+/// Walk the sequence of 24-byte control tuples, each followed by the diff and extra bytes,
+/// without producing the target. If the source is given, apply the same bounds checks as `bsdiff::patch`.
+fn check_patch(mut patch: &[u8], source: Option<&[u8]>) -> Option<()> {
+    let mut pos = 0_usize;
+    while !patch.is_empty() {
+        let (ctrl, rest) = patch.split_at_checked(24)?;
+        let add = usize::try_from(u64::from_le_bytes(ctrl[0..8].try_into().ok()?)).ok()?;
+        let copy = usize::try_from(u64::from_le_bytes(ctrl[8..16].try_into().ok()?)).ok()?;
+        let seek = offtin(ctrl[16..24].try_into().ok()?);
+        patch = rest.get(add.checked_add(copy)?..)?;
+        if let Some(source) = source {
+            pos = pos.checked_add(add).filter(|&pos| pos <= source.len())?;
+            pos = pos.checked_add_signed(isize::try_from(seek).ok()?)?;
+        }
+    }
+    Some(())
+}
+
+/// Read a sign-magnitude little-endian 64-bit integer, as used by bsdiff.
+fn offtin(buf: [u8; 8]) -> i64 {
+    let value = u64::from_le_bytes(buf);
+    // the magnitude is at most 2^63-1, so it always fits into i64
+    #[expect(clippy::cast_possible_wrap)]
+    let magnitude = (value & !(1 << 63)) as i64;
+    if value >> 63 == 0 {
+        magnitude
+    } else {
+        -magnitude
+    }
 }
 
 #[cfg(test)]
@@ -70,6 +418,43 @@ mod tests {
         assert_eq!(patch, expected);
     }
 
+    #[test]
+    #[expect(clippy::cast_possible_truncation)]
+    fn test_same_as_bsdiff() {
+        // A simple LCG, so the test does not need a random number generator dependency
+        let mut seed = 42_u64;
+        let mut next = |max: usize| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            (seed >> 33) as usize % max
+        };
+        for case in 0..500 {
+            // Small alphabets produce many repeated substrings
+            let alphabet = [2, 4, 256][case % 3];
+            let old: Vec<u8> = (0..2000).map(|_| next(alphabet) as u8).collect();
+            let mut new = old.clone();
+            for _ in 0..next(20) {
+                let pos = next(new.len() + 1);
+                let len = next(50);
+                match next(3) {
+                    0 => {
+                        new.drain(pos..(pos + len).min(new.len()));
+                    }
+                    1 => {
+                        new.splice(pos..pos, old[..len].iter().copied());
+                    }
+                    _ => new.insert(pos, next(256) as u8),
+                }
+            }
+            let mut expected = Vec::new();
+            bsdiff::diff(&old, &new, &mut expected).unwrap();
+            assert_eq!(
+                BsdiffRawDiffer::diff(&old, &new).unwrap(),
+                expected,
+                "case {case}"
+            );
+        }
+    }
+
     #[test]
     fn test_patch() {
         let source = b"abc013479zz";
@@ -1,11 +1,11 @@
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 
 use bzip2::read::BzDecoder;
 use bzip2::write::BzEncoder;
 use bzip2::Compression;
-use rusqlite::Error::UserFunctionError;
 
 use crate::common::{register_compression, Encoder};
+use crate::error::CompressionError;
 use crate::rusqlite::{Connection, Result};
 
 /// Register the `bzip2` SQL functions with the given `SQLite` connection.
@@ -50,36 +50,20 @@ impl Encoder for Bzip2Encoder {
         "bzip2_test"
     }
 
-    fn encode(data: &[u8], quality: Option<u32>) -> Result<Vec<u8>> {
-        let quality = if let Some(param) = quality {
-            if param < Compression::fast().level() || param > Compression::best().level() {
-                return Err(UserFunctionError(
-                    format!(
-                        "The optional second argument to bzip2() must be between {} and {}",
-                        Compression::fast().level(),
-                        Compression::best().level()
-                    )
-                    .into(),
-                ));
-            }
-            Compression::new(param)
-        } else {
-            Compression::default()
-        };
-
-        let mut encoder = BzEncoder::new(Vec::new(), quality);
+    fn encode_stream<R: Read, W: Write>(
+        reader: &mut R,
+        writer: W,
+        quality: Option<u32>,
+    ) -> Result<W, CompressionError> {
+        let mut encoder = BzEncoder::new(writer, compression(quality)?);
+        io::copy(reader, &mut encoder).map_err(|e| CompressionError::io("bzip2", e))?;
         encoder
-            .write_all(data)
-            .map_err(|e| UserFunctionError(e.into()))?;
-        encoder.finish().map_err(|e| UserFunctionError(e.into()))
+            .finish()
+            .map_err(|e| CompressionError::io("bzip2", e))
     }
 
-    fn decode(data: &[u8]) -> Result<Vec<u8>> {
-        let mut decompressed = Vec::new();
+    fn decoder(data: &[u8]) -> impl Read + '_ {
         BzDecoder::new(data)
-            .read_to_end(&mut decompressed)
-            .map_err(|e| UserFunctionError(e.into()))?;
-        Ok(decompressed)
     }
 
     fn test(data: &[u8]) -> bool {
@@ -96,3 +80,21 @@ impl Encoder for Bzip2Encoder {
         false
     }
 }
+
+/// The compression level for the optional quality argument.
+pub(crate) fn compression(quality: Option<u32>) -> Result<Compression, CompressionError> {
+    let Some(param) = quality else {
+        return Ok(Compression::default());
+    };
+    if param < Compression::fast().level() || param > Compression::best().level() {
+        return Err(CompressionError::invalid_level(
+            "bzip2",
+            format!(
+                "The optional second argument to bzip2() must be between {} and {}",
+                Compression::fast().level(),
+                Compression::best().level()
+            ),
+        ));
+    }
+    Ok(Compression::new(param))
+}
@@ -0,0 +1,156 @@
+use std::fmt::Write as _;
+
+use fastcdc::v2020::{
+    FastCDC, AVERAGE_MAX, AVERAGE_MIN, MAXIMUM_MAX, MAXIMUM_MIN, MINIMUM_MAX, MINIMUM_MIN,
+};
+#[cfg(feature = "trace")]
+use log::trace;
+use rusqlite::functions::{Aggregate, Context, FunctionFlags};
+use rusqlite::types::{Type, ValueRef};
+use rusqlite::Error::{InvalidFunctionParameterType, UserFunctionError};
+use sha2::{Digest as _, Sha256};
+
+use crate::args::get_bytes;
+use crate::rusqlite::{Connection, Result};
+
+#[cfg(not(feature = "trace"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {};
+}
+
+/// Register the `cdc_chunks` and `cdc_reassemble` SQL functions with the given `SQLite` connection.
+/// The `cdc_chunks(blob, min, avg, max)` function splits a blob into content-defined chunks using
+/// [FastCDC](https://www.usenix.org/conference/atc16/technical-sessions/presentation/xia), and returns a JSON array
+/// with the zero-based offset, the length, and the SHA-256 hash (as uppercase hex) of each chunk,
+/// e.g. `[{"offset":0,"length":8192,"hash":"9F86..."}]`, which can be expanded into a table with `json_each`.
+/// Chunk boundaries depend on the content, so an insertion only changes the chunks around it,
+/// and similar blobs share most of their chunks. The `min` size must be between 64 and 1048576 bytes,
+/// `avg` between 256 and 4194304, and `max` between 1024 and 16777216, with `min <= avg <= max`.
+/// The `cdc_reassemble` aggregate function concatenates the chunks in order, e.g. `cdc_reassemble(chunk ORDER BY seq)`
+/// on `SQLite` 3.44+, or over an ordered subquery, and returns `NULL` if any chunk is `NULL`, so that a missing chunk does not silently produce a shorter blob.
+/// Without any chunks, e.g. for an empty blob, it returns an empty blob.
+/// The offsets are in bytes, so `cdc_chunks` only accepts blobs: text must be cast first, e.g. `CAST(t AS BLOB)`,
+/// so that `substr` also counts bytes. If the blob is `NULL`, the result is `NULL`.
+///
+/// # Example
+///
+/// ```
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::register_cdc_functions;
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// register_cdc_functions(&db)?;
+/// let sql = "SELECT json_array_length(cdc_chunks(zeroblob(100000), 1024, 4096, 16384))";
+/// let result: i64 = db.query_row(sql, [], |r| r.get(0))?;
+/// assert_eq!(result, 7);
+/// let sql = "WITH t(b) AS (SELECT randomblob(100000))
+///            SELECT cdc_reassemble(chunk) = (SELECT b FROM t) FROM (
+///              SELECT substr(b, value->>'offset' + 1, value->>'length') AS chunk
+///              FROM t, json_each(cdc_chunks(b, 1024, 4096, 16384)) ORDER BY key)";
+/// let result: bool = db.query_row(sql, [], |r| r.get(0))?;
+/// assert!(result);
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_cdc_functions(conn: &Connection) -> Result<()> {
+    // FunctionFlags derive Copy trait only in v0.31+, but we support v0.30+
+    macro_rules! flags {
+        () => {
+            FunctionFlags::SQLITE_UTF8
+                | FunctionFlags::SQLITE_DETERMINISTIC
+                | FunctionFlags::SQLITE_DIRECTONLY
+        };
+    }
+
+    trace!("Registering function cdc_chunks");
+    conn.create_scalar_function("cdc_chunks", 4, flags!(), chunks_fn)?;
+    trace!("Registering aggregate function cdc_reassemble");
+    conn.create_aggregate_function("cdc_reassemble", 1, flags!(), Reassemble)
+}
+
+fn chunks_fn(ctx: &Context) -> Result<Option<String>> {
+    // The offsets are in bytes, but substr() counts characters in text, so they would not match
+    let data = match ctx.get_raw(0) {
+        ValueRef::Blob(val) => val,
+        ValueRef::Null => return Ok(None),
+        ValueRef::Text(_) => Err(UserFunctionError(
+            "cdc_chunks() offsets are in bytes, so text must be cast to a blob first, e.g. cdc_chunks(CAST(t AS BLOB), ...)".into(),
+        ))?,
+        ValueRef::Integer(_) => Err(InvalidFunctionParameterType(0, Type::Integer))?,
+        ValueRef::Real(_) => Err(InvalidFunctionParameterType(0, Type::Real))?,
+    };
+    let min = get_size(ctx, 1, "min", MINIMUM_MIN, MINIMUM_MAX)?;
+    let avg = get_size(ctx, 2, "avg", AVERAGE_MIN, AVERAGE_MAX)?;
+    let max = get_size(ctx, 3, "max", MAXIMUM_MIN, MAXIMUM_MAX)?;
+    if min > avg || avg > max {
+        Err(UserFunctionError(
+            "cdc_chunks() sizes must be ordered as min <= avg <= max".into(),
+        ))?;
+    }
+    trace!(
+        "cdc_chunks: chunking {} bytes with sizes {min}/{avg}/{max}",
+        data.len()
+    );
+
+    let mut result = String::from("[");
+    for chunk in FastCDC::new(data, min, avg, max) {
+        let hash = Sha256::digest(&data[chunk.offset..chunk.offset + chunk.length]);
+        if result.len() > 1 {
+            result.push(',');
+        }
+        _ = write!(
+            result,
+            r#"{{"offset":{},"length":{},"hash":""#,
+            chunk.offset, chunk.length
+        );
+        for byte in hash {
+            _ = write!(result, "{byte:02X}");
+        }
+        result.push_str("\"}");
+    }
+    result.push(']');
+    Ok(Some(result))
+}
+
+fn get_size(ctx: &Context, idx: usize, name: &str, min: u32, max: u32) -> Result<u32> {
+    let value = ctx.get::<i64>(idx)?;
+    u32::try_from(value)
+        .ok()
+        .filter(|v| (min..=max).contains(v))
+        .ok_or_else(|| {
+            UserFunctionError(
+                format!("cdc_chunks() {name} size must be between {min} and {max}, got {value}")
+                    .into(),
+            )
+        })
+}
+
+/// Aggregate that concatenates the chunks of a blob, or returns `NULL` if any of them is `NULL`.
+struct Reassemble;
+
+impl Aggregate<Option<Vec<u8>>, Option<Vec<u8>>> for Reassemble {
+    fn init(&self, _: &mut Context<'_>) -> Result<Option<Vec<u8>>> {
+        Ok(Some(Vec::new()))
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut Option<Vec<u8>>) -> Result<()> {
+        match (acc.as_mut(), get_bytes(ctx, 0)?) {
+            (Some(data), Some(chunk)) => data.extend_from_slice(chunk),
+            (Some(_), None) => {
+                trace!("cdc_reassemble: NULL chunk, the result is NULL");
+                *acc = None;
+            }
+            (None, _) => {}
+        }
+        Ok(())
+    }
+
+    fn finalize(
+        &self,
+        _: &mut Context<'_>,
+        acc: Option<Option<Vec<u8>>>,
+    ) -> Result<Option<Vec<u8>>> {
+        // Without any rows, the blob was empty
+        Ok(acc.unwrap_or_else(|| Some(Vec::new())))
+    }
+}
@@ -0,0 +1,332 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+
+use clap::{Args, Parser, Subcommand};
+use sqlite_compressions::rusqlite::types::ValueRef;
+use sqlite_compressions::rusqlite::{params, Connection};
+#[cfg(feature = "brotli")]
+use sqlite_compressions::BrotliEncoder;
+#[cfg(feature = "bzip2")]
+use sqlite_compressions::Bzip2Encoder;
+#[cfg(any(feature = "brotli", feature = "bzip2", feature = "gzip"))]
+use sqlite_compressions::Encoder as _;
+#[cfg(feature = "gzip")]
+use sqlite_compressions::GzipEncoder;
+
+type Result<T, E = Box<dyn Error>> = std::result::Result<T, E>;
+
+/// Compress, decompress, recompress, or analyze a column of an existing `SQLite` table in place.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compress all non-NULL values of the column
+    Compress {
+        #[command(flatten)]
+        column: ColumnArgs,
+        /// Compression algorithm to use
+        algo: Algorithm,
+        /// Optional compression level, same as the second argument of the SQL function
+        #[arg(short, long)]
+        level: Option<u32>,
+    },
+    /// Decompress all non-NULL values of the column
+    Decompress {
+        #[command(flatten)]
+        column: ColumnArgs,
+        /// Compression algorithm the values are currently encoded with
+        algo: Algorithm,
+    },
+    /// Decompress all non-NULL values of the column, and compress them with a different algorithm
+    Recompress {
+        #[command(flatten)]
+        column: ColumnArgs,
+        /// Compression algorithm the values are currently encoded with
+        from: Algorithm,
+        /// Compression algorithm to use
+        to: Algorithm,
+        /// Optional compression level, same as the second argument of the SQL function
+        #[arg(short, long)]
+        level: Option<u32>,
+    },
+    /// Report how much space each compression algorithm would save, without modifying the database
+    Stats {
+        #[command(flatten)]
+        column: ColumnArgs,
+        /// Compression algorithms to evaluate. Defaults to all available algorithms.
+        algos: Vec<Algorithm>,
+        /// Optional compression level, same as the second argument of the SQL function
+        #[arg(short, long)]
+        level: Option<u32>,
+    },
+}
+
+#[derive(Args)]
+struct ColumnArgs {
+    /// Path to the `SQLite` database file
+    db: PathBuf,
+    /// Name of the table. Tables without a rowid are not supported.
+    table: String,
+    /// Name of the column to process
+    column: String,
+    /// Number of rows to process in each transaction. Each batch is committed separately,
+    /// so a failure leaves the rows of earlier batches converted.
+    #[arg(short, long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    batch_size: u32,
+}
+
+#[derive(Clone, Copy)]
+enum Algorithm {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "bzip2")]
+    Bzip2,
+    #[cfg(feature = "gzip")]
+    Gzip,
+}
+
+impl Algorithm {
+    const ALL: &'static [Self] = &[
+        #[cfg(feature = "brotli")]
+        Self::Brotli,
+        #[cfg(feature = "bzip2")]
+        Self::Bzip2,
+        #[cfg(feature = "gzip")]
+        Self::Gzip,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Self::Brotli => BrotliEncoder::enc_name(),
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2 => Bzip2Encoder::enc_name(),
+            #[cfg(feature = "gzip")]
+            Self::Gzip => GzipEncoder::enc_name(),
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn encode(self, data: &[u8], level: Option<u32>) -> Result<Vec<u8>> {
+        Ok(match self {
+            #[cfg(feature = "brotli")]
+            Self::Brotli => BrotliEncoder::encode(data, level)?,
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2 => Bzip2Encoder::encode(data, level)?,
+            #[cfg(feature = "gzip")]
+            Self::Gzip => GzipEncoder::encode(data, level)?,
+        })
+    }
+
+    #[allow(unused_variables)]
+    fn decode(self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            #[cfg(feature = "brotli")]
+            Self::Brotli => BrotliEncoder::decode(data)?,
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2 => Bzip2Encoder::decode(data)?,
+            #[cfg(feature = "gzip")]
+            Self::Gzip => GzipEncoder::decode(data)?,
+        })
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|v| v.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|v| v.name()).collect();
+                format!(
+                    "unknown algorithm '{s}', expected one of: {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse().command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Compress {
+            column,
+            algo,
+            level,
+        } => column.update(|value| algo.encode(get_bytes(value, true)?, level)),
+        Command::Decompress { column, algo } => {
+            column.update(|value| algo.decode(get_bytes(value, false)?))
+        }
+        Command::Recompress {
+            column,
+            from,
+            to,
+            level,
+        } => column.update(|value| to.encode(&from.decode(get_bytes(value, false)?)?, level)),
+        Command::Stats {
+            column,
+            algos,
+            level,
+        } => {
+            let algos = if algos.is_empty() {
+                Algorithm::ALL.to_vec()
+            } else {
+                algos
+            };
+            column.stats(&algos, level)
+        }
+    }
+}
+
+/// Get the bytes of a non-NULL value, allowing text only if the value is about to be compressed.
+fn get_bytes(value: ValueRef<'_>, allow_text: bool) -> Result<&[u8]> {
+    match value {
+        ValueRef::Blob(val) => Ok(val),
+        ValueRef::Text(val) if allow_text => Ok(val),
+        v => Err(format!("unsupported value type {}", v.data_type()).into()),
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn percent(part: u64, total: u64) -> impl Display {
+    #[allow(clippy::cast_precision_loss)]
+    let value = if total == 0 {
+        100.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    };
+    format!("{value:.1}%")
+}
+
+impl ColumnArgs {
+    fn open(&self) -> Result<(Connection, u64)> {
+        let conn = Connection::open(&self.db)?;
+        let total: i64 = conn.query_row(
+            &format!(
+                "SELECT count(*) FROM {} WHERE {} IS NOT NULL",
+                quote(&self.table),
+                quote(&self.column)
+            ),
+            [],
+            |r| r.get(0),
+        )?;
+        Ok((conn, total.unsigned_abs()))
+    }
+
+    /// Iterate over all non-NULL values in batches, one transaction per batch, calling `f` for each row.
+    /// If `f` fails and `modifies` is set, the error reports how many rows were already committed.
+    fn for_each_batch(
+        &self,
+        modifies: bool,
+        mut f: impl FnMut(&Connection, i64, ValueRef<'_>) -> Result<()>,
+    ) -> Result<()> {
+        let (mut conn, total) = self.open()?;
+        let select = format!(
+            "SELECT rowid, {col} FROM {tbl} WHERE rowid > ?1 AND {col} IS NOT NULL ORDER BY rowid LIMIT ?2",
+            col = quote(&self.column),
+            tbl = quote(&self.table),
+        );
+        let mut last_rowid = i64::MIN;
+        let mut processed = 0_u64;
+        loop {
+            let tx = conn.transaction()?;
+            let mut count = 0_u64;
+            {
+                let mut stmt = tx.prepare(&select)?;
+                let mut rows = stmt.query(params![last_rowid, self.batch_size])?;
+                while let Some(row) = rows.next()? {
+                    last_rowid = row.get(0)?;
+                    f(&tx, last_rowid, row.get_ref(1)?).map_err(|e| {
+                        let note = if modifies && processed > 0 {
+                            format!(
+                                "\nThe first {processed} of {total} rows were already converted and committed, \
+                                 so the column is now only partially converted."
+                            )
+                        } else {
+                            String::new()
+                        };
+                        format!("row with rowid {last_rowid}: {e}{note}")
+                    })?;
+                    count += 1;
+                }
+            }
+            tx.commit()?;
+            if count == 0 {
+                break;
+            }
+            processed += count;
+            eprintln!(
+                "Processed {processed} of {total} rows ({})",
+                percent(processed, total)
+            );
+        }
+        Ok(())
+    }
+
+    fn update(&self, mut convert: impl FnMut(ValueRef<'_>) -> Result<Vec<u8>>) -> Result<()> {
+        let update = format!(
+            "UPDATE {} SET {} = ?2 WHERE rowid = ?1",
+            quote(&self.table),
+            quote(&self.column)
+        );
+        self.for_each_batch(true, |conn, rowid, value| {
+            let value = convert(value)?;
+            conn.prepare_cached(&update)?
+                .execute(params![rowid, value])?;
+            Ok(())
+        })
+    }
+
+    fn stats(&self, algos: &[Algorithm], level: Option<u32>) -> Result<()> {
+        let mut rows = 0_u64;
+        let mut size = 0_u64;
+        let mut sizes = vec![0_u64; algos.len()];
+        self.for_each_batch(false, |_, _, value| {
+            let value = get_bytes(value, true)?;
+            rows += 1;
+            size += value.len() as u64;
+            for (algo, total) in algos.iter().zip(&mut sizes) {
+                *total += algo.encode(value, level)?.len() as u64;
+            }
+            Ok(())
+        })?;
+
+        println!("{rows} non-NULL values, {size} bytes");
+        for (algo, total) in algos.iter().zip(sizes) {
+            let change = if total <= size {
+                format!("saves {} bytes", size - total)
+            } else {
+                format!("adds {} bytes", total - size)
+            };
+            println!(
+                "{:>8}: {total} bytes ({} of original, {change})",
+                algo.name(),
+                percent(total, size),
+            );
+        }
+        Ok(())
+    }
+}
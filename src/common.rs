@@ -1,3 +1,4 @@
+use std::io::{Read, Write};
 use std::panic::{RefUnwindSafe, UnwindSafe};
 
 #[cfg(feature = "trace")]
@@ -6,6 +7,7 @@ use rusqlite::functions::Context;
 use rusqlite::types::{Type, ValueRef};
 use rusqlite::Error::{InvalidFunctionParameterType, InvalidParameterCount};
 
+use crate::error::CompressionError;
 use crate::rusqlite::functions::FunctionFlags;
 use crate::rusqlite::{Connection, Result};
 
@@ -18,10 +20,54 @@ pub trait Encoder {
     fn enc_name() -> &'static str;
     fn dec_name() -> &'static str;
     fn test_name() -> &'static str;
-    fn encode(data: &[u8], quality: Option<u32>) -> Result<Vec<u8>>;
-    fn decode(data: &[u8]) -> Result<Vec<u8>>;
+    /// Compress everything read from `reader` into `writer`, and return the writer once the stream is finished.
+    fn encode_stream<R: Read, W: Write>(
+        reader: &mut R,
+        writer: W,
+        quality: Option<u32>,
+    ) -> Result<W, CompressionError>;
+    /// Create a reader that decompresses `data` on the fly.
+    fn decoder(data: &[u8]) -> impl Read + '_;
     fn test(data: &[u8]) -> bool;
+
+    fn encode(mut data: &[u8], quality: Option<u32>) -> Result<Vec<u8>, CompressionError> {
+        Self::encode_stream(&mut data, Vec::new(), quality)
+    }
+
+    fn decode(data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let mut decompressed = Vec::new();
+        Self::decoder(data)
+            .read_to_end(&mut decompressed)
+            .map_err(|e| CompressionError::decode_io(Self::enc_name(), e))?;
+        Ok(decompressed)
+    }
+}
+
+/// Find the [`Encoder`] whose encoding function is named `$name` (case-insensitive),
+/// and evaluate `$body` with `$enc` as its type alias. Returns `None` for unknown names.
+macro_rules! dispatch_encoder {
+    ($name:expr, |$enc:ident| $body:expr) => {
+        match $name {
+            #[cfg(feature = "brotli")]
+            name if name.eq_ignore_ascii_case("brotli") => {
+                type $enc = $crate::BrotliEncoder;
+                Some($body)
+            }
+            #[cfg(feature = "bzip2")]
+            name if name.eq_ignore_ascii_case("bzip2") => {
+                type $enc = $crate::Bzip2Encoder;
+                Some($body)
+            }
+            #[cfg(feature = "gzip")]
+            name if name.eq_ignore_ascii_case("gzip") => {
+                type $enc = $crate::GzipEncoder;
+                Some($body)
+            }
+            _ => None,
+        }
+    };
 }
+pub(crate) use dispatch_encoder;
 
 pub(crate) fn register_compression<T: Encoder + UnwindSafe + RefUnwindSafe + 'static>(
     conn: &Connection,
@@ -1,28 +1,301 @@
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
 use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "trace")]
 use log::trace;
-use rusqlite::functions::{Context, FunctionFlags};
-use rusqlite::types::{Type, ValueRef};
+use rusqlite::functions::{Aggregate, Context, FunctionFlags, WindowAggregate};
+use rusqlite::types::{Value, ValueRef};
 use rusqlite::Connection;
-use rusqlite::Error::InvalidFunctionParameterType;
+use rusqlite::Error::{InvalidFunctionParameterType, InvalidParameterCount, UserFunctionError};
+use sha2::{Digest as _, Sha256};
 
 #[cfg(not(feature = "trace"))]
 macro_rules! trace {
     ($($arg:tt)*) => {};
 }
 
+use crate::args::get_bytes;
+use crate::error::CompressionError;
 use crate::rusqlite::Result;
 
 pub trait Differ {
+    /// Options of [`Differ::diff_with_options`], e.g. a compression level. Differs without options use `()`.
+    type Options;
+    /// Stable identifier of the diff algorithm, e.g. `bsdiff4` or `fossil`, reported by [`CompressionError::algorithm`].
+    fn algorithm() -> &'static str;
     fn diff_name() -> &'static str;
     fn patch_name() -> &'static str;
-    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>>;
-    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>>;
+    fn test_name() -> &'static str;
+    fn chain_name() -> &'static str;
+    /// Name of the window function that diffs each row against the previous one, e.g. `bsdiff4_prev`.
+    fn prev_name() -> &'static str;
+    /// If true, the third argument of the diff SQL function is parsed with [`Differ::parse_options`],
+    /// and passed to [`Differ::diff_with_options`], unless it is `NULL` or just `'verified'`.
+    const DIFF_OPTIONS: bool = false;
+    /// If true, the diff, patch, and chain SQL functions return valid UTF-8 results as text instead of blobs.
+    const TEXT_OUTPUT: bool = false;
+    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError>;
+    /// Parse the third argument of the diff SQL function into [`Differ::Options`].
+    /// Invalid options are reported as SQL errors, like the other arguments.
+    fn parse_options(value: ValueRef<'_>) -> Result<Self::Options> {
+        Err(InvalidFunctionParameterType(2, value.data_type()))
+    }
+    /// Whether the third argument of the diff SQL function asks for a verified patch. By default only `'verified'` does,
+    /// differs with JSON options also accept it as `"verified": true` among the other options.
+    #[must_use]
+    fn is_verified(value: ValueRef<'_>) -> bool {
+        value == ValueRef::Text(b"verified")
+    }
+    /// Same as [`Differ::diff`], tuned by the options, e.g. a compression level.
+    fn diff_with_options(
+        source: &[u8],
+        target: &[u8],
+        _options: &Self::Options,
+    ) -> Result<Vec<u8>, CompressionError> {
+        Self::diff(source, target)
+    }
+    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CompressionError>;
+    /// Same as [`Differ::patch`], but reuses the `target` buffer, replacing its content.
+    fn patch_into(
+        source: &[u8],
+        patch: &[u8],
+        target: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        *target = Self::patch(source, patch)?;
+        Ok(())
+    }
+    /// Check that `patch` is well-formed, and if `source` is given, that it can be applied to it.
+    fn test(patch: &[u8], source: Option<&[u8]>) -> bool;
+}
+
+/// Find the [`Differ`] whose diff function is named `$name` (case-insensitive),
+/// and evaluate `$body` with `$differ` as its type alias. Returns `None` for unknown names.
+#[cfg(any(feature = "brotli", feature = "bzip2", feature = "gzip"))]
+macro_rules! dispatch_differ {
+    ($name:expr, |$differ:ident| $body:expr) => {
+        match $name {
+            #[cfg(feature = "bsdiff4")]
+            name if name.eq_ignore_ascii_case("bsdiff4") => {
+                type $differ = $crate::Bsdiff4Differ;
+                Some($body)
+            }
+            #[cfg(feature = "bsdiff4")]
+            name if name.eq_ignore_ascii_case("bsdiff43") => {
+                type $differ = $crate::Bsdiff43Differ;
+                Some($body)
+            }
+            #[cfg(feature = "bsdiffraw")]
+            name if name.eq_ignore_ascii_case("bsdiffraw") => {
+                type $differ = $crate::BsdiffRawDiffer;
+                Some($body)
+            }
+            #[cfg(feature = "fossil")]
+            name if name.eq_ignore_ascii_case("delta_create") => {
+                type $differ = $crate::FossilDiffer;
+                Some($body)
+            }
+            #[cfg(feature = "json")]
+            name if name.eq_ignore_ascii_case("json_diff") => {
+                type $differ = $crate::JsonPatchDiffer;
+                Some($body)
+            }
+            #[cfg(feature = "json")]
+            name if name.eq_ignore_ascii_case("json_merge_diff") => {
+                type $differ = $crate::JsonMergeDiffer;
+                Some($body)
+            }
+            #[cfg(feature = "rsync")]
+            name if name.eq_ignore_ascii_case("rsync_diff") => {
+                type $differ = $crate::RsyncDiffer;
+                Some($body)
+            }
+            #[cfg(feature = "text")]
+            name if name.eq_ignore_ascii_case("text_diff") => {
+                type $differ = $crate::TextDiffer;
+                Some($body)
+            }
+            #[cfg(feature = "vcdiff")]
+            name if name.eq_ignore_ascii_case("vcdiff_diff") => {
+                type $differ = $crate::VcdiffDiffer;
+                Some($body)
+            }
+            #[cfg(feature = "zstd")]
+            name if name.eq_ignore_ascii_case("zstd_diff") => {
+                type $differ = $crate::ZstdDiffer;
+                Some($body)
+            }
+            _ => None,
+        }
+    };
+}
+#[cfg(any(feature = "brotli", feature = "bzip2", feature = "gzip"))]
+pub(crate) use dispatch_differ;
+
+/// Limits that the diff SQL functions of a connection enforce on every call, set with
+/// [`register_compression_functions_with_limits`](crate::register_compression_functions_with_limits),
+/// or with the `diff_limits` SQL function registered by [`register_compression_functions`](crate::register_compression_functions).
+/// Nothing is limited by default. The limits only apply to the SQL functions, not to the [`Differ`] methods called from Rust.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffLimits {
+    /// The maximum size in bytes of the source, and of the target
+    pub max_input_size: Option<usize>,
+    /// The maximum wall-clock time of a single diff. `bsdiffraw` and `text_diff` stop as soon as it is exceeded,
+    /// the other diff functions can only check it once they are done, so it should be combined with `max_input_size`.
+    pub time_budget: Option<Duration>,
+}
+
+/// The [`DiffLimits`] of a connection, shared by all of its diff SQL functions.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnLimits(Arc<RwLock<DiffLimits>>);
+
+impl ConnLimits {
+    pub(crate) fn new(limits: DiffLimits) -> Self {
+        Self(Arc::new(RwLock::new(limits)))
+    }
+
+    pub(crate) fn get(&self) -> DiffLimits {
+        *self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set(&self, limits: DiffLimits) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = limits;
+    }
+}
+
+thread_local! {
+    /// The deadline of the diff running on this thread, if it was called by a diff SQL function with a time budget.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Register the `diff_limits` SQL function that returns the limits of the connection as a JSON object,
+/// e.g. `{"max_input_size":null,"time_budget_ms":null}`. Called as `diff_limits(max_input_size, time_budget_ms)`,
+/// it first replaces them, with `NULL` meaning no limit.
+pub(crate) fn register_diff_limits_function(conn: &Connection, limits: &ConnLimits) -> Result<()> {
+    trace!("Registering function diff_limits");
+    let limits = limits.clone();
+    conn.create_scalar_function(
+        "diff_limits",
+        -1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DIRECTONLY,
+        move |ctx| diff_limits_fn(ctx, &limits),
+    )
+}
+
+fn diff_limits_fn(ctx: &Context, limits: &ConnLimits) -> Result<String> {
+    match ctx.len() {
+        0 => {}
+        2 => {
+            let get_limit = |idx: usize, name: &str| -> Result<Option<u64>> {
+                ctx.get::<Option<i64>>(idx)?
+                    .map(|v| {
+                        u64::try_from(v).map_err(|_| {
+                            UserFunctionError(
+                                format!(
+                                    "diff_limits() {name} must be NULL or non-negative, got {v}"
+                                )
+                                .into(),
+                            )
+                        })
+                    })
+                    .transpose()
+            };
+            let max_input_size = get_limit(0, "max_input_size")?;
+            let time_budget = get_limit(1, "time_budget_ms")?;
+            limits.set(DiffLimits {
+                max_input_size: max_input_size.map(|v| usize::try_from(v).unwrap_or(usize::MAX)),
+                time_budget: time_budget.map(Duration::from_millis),
+            });
+        }
+        n => return Err(InvalidParameterCount(n, 2)),
+    }
+    let limits = limits.get();
+    let json = |v: Option<u128>| v.map_or_else(|| "null".to_string(), |v| v.to_string());
+    Ok(format!(
+        r#"{{"max_input_size":{},"time_budget_ms":{}}}"#,
+        json(limits.max_input_size.map(|v| v as u128)),
+        json(limits.time_budget.map(|v| v.as_millis()))
+    ))
+}
+
+/// Get the deadline of the current diff, so that long-running diff algorithms can stop early.
+/// The result of a diff that stopped early is discarded, so it does not need to be valid.
+#[cfg(any(feature = "bsdiffraw", feature = "text"))]
+pub(crate) fn diff_deadline() -> Option<Instant> {
+    DEADLINE.get()
+}
+
+/// The error of a diff SQL function that violates the [`DiffLimits`]. `SQLite` only keeps its message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffLimitError {
+    /// The source or the target is larger than `max_input_size`.
+    /// Compressed inputs are only decompressed up to one byte past the limit, so `size` is then `limit + 1`.
+    InputTooLarge {
+        algorithm: &'static str,
+        function: &'static str,
+        size: usize,
+        limit: usize,
+    },
+    /// The diff took longer than `time_budget`
+    TimeBudgetExceeded {
+        algorithm: &'static str,
+        function: &'static str,
+        budget: Duration,
+    },
 }
 
+impl fmt::Display for DiffLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InputTooLarge {
+                function,
+                size,
+                limit,
+                ..
+            } => write!(
+                f,
+                "{function}() input of {size} bytes exceeds the limit of {limit} bytes"
+            ),
+            Self::TimeBudgetExceeded {
+                function, budget, ..
+            } => {
+                write!(f, "{function}() exceeded the time budget of {budget:?}")
+            }
+        }
+    }
+}
+
+impl DiffLimitError {
+    /// The stable identifier of the diff algorithm, same as [`Differ::algorithm`].
+    #[must_use]
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            Self::InputTooLarge { algorithm, .. } | Self::TimeBudgetExceeded { algorithm, .. } => {
+                algorithm
+            }
+        }
+    }
+
+    /// The name of the SQL function that exceeded the limit, e.g. `bsdiff4` or `compressed_diff`.
+    #[must_use]
+    pub fn function(&self) -> &'static str {
+        match self {
+            Self::InputTooLarge { function, .. } | Self::TimeBudgetExceeded { function, .. } => {
+                function
+            }
+        }
+    }
+}
+
+impl std::error::Error for DiffLimitError {}
+
 pub(crate) fn register_differ<T: Differ + UnwindSafe + RefUnwindSafe + 'static>(
     conn: &Connection,
+    limits: &ConnLimits,
 ) -> Result<()> {
     // FunctionFlags derive Copy trait only in v0.31+, but we support v0.30+
     macro_rules! flags {
@@ -34,41 +307,377 @@ pub(crate) fn register_differ<T: Differ + UnwindSafe + RefUnwindSafe + 'static>(
     }
 
     trace!("Registering function {}", T::diff_name());
-    conn.create_scalar_function(T::diff_name(), 2, flags!(), diff_fn::<T>)?;
+    for arg_count in [2, 3] {
+        let limits = limits.clone();
+        conn.create_scalar_function(T::diff_name(), arg_count, flags!(), move |ctx| {
+            diff_fn::<T>(ctx, &limits)
+        })?;
+    }
 
     trace!("Registering function {}", T::patch_name());
-    conn.create_scalar_function(T::patch_name(), 2, flags!(), patch_fn::<T>)
+    conn.create_scalar_function(T::patch_name(), 2, flags!(), patch_fn::<T>)?;
+
+    trace!("Registering function {}", T::test_name());
+    conn.create_scalar_function(T::test_name(), -1, flags!(), testing_fn::<T>)?;
+
+    trace!("Registering aggregate function {}", T::chain_name());
+    conn.create_aggregate_function(T::chain_name(), 2, flags!(), PatchChain::<T>(PhantomData))?;
+
+    trace!("Registering window function {}", T::prev_name());
+    conn.create_window_function(
+        T::prev_name(),
+        1,
+        flags!(),
+        PrevDiff::<T> {
+            limits: limits.clone(),
+            differ: PhantomData,
+        },
+    )
 }
 
 fn diff_fn<T: Differ + UnwindSafe + RefUnwindSafe + 'static>(
     ctx: &Context,
-) -> Result<Option<Vec<u8>>> {
+    limits: &ConnLimits,
+) -> Result<Option<Value>> {
     let Some(source) = get_bytes(ctx, 0)? else {
         return Ok(None);
     };
     let Some(target) = get_bytes(ctx, 1)? else {
         return Ok(None);
     };
-    Ok(Some(T::diff(source, target)?))
+    let value = if ctx.len() > 2 {
+        ctx.get_raw(2)
+    } else {
+        ValueRef::Null
+    };
+    let verified = T::is_verified(value);
+    let options = match value {
+        ValueRef::Null | ValueRef::Text(b"verified") => None,
+        _ if T::DIFF_OPTIONS => Some(T::parse_options(value)?),
+        _ => {
+            return Err(UserFunctionError(
+                format!(
+                    "The optional third argument to {}() must be 'verified'",
+                    T::diff_name()
+                )
+                .into(),
+            ))
+        }
+    };
+
+    let patch = run_diff::<T>(source, target, options.as_ref(), limits.get())?;
+    Ok(Some(if verified {
+        Value::Blob(Envelope::wrap(source, target, &patch))
+    } else {
+        to_value::<T>(patch)
+    }))
+}
+
+/// Run [`Differ::diff`], or [`Differ::diff_with_options`] if there are options, enforcing the [`DiffLimits`].
+pub(crate) fn run_diff<T: Differ>(
+    source: &[u8],
+    target: &[u8],
+    options: Option<&T::Options>,
+    limits: DiffLimits,
+) -> Result<Vec<u8>> {
+    let size = source.len().max(target.len());
+    if let Some(limit) = limits.max_input_size.filter(|&limit| size > limit) {
+        return Err(CompressionError::from(DiffLimitError::InputTooLarge {
+            algorithm: T::algorithm(),
+            function: T::diff_name(),
+            size,
+            limit,
+        })
+        .into());
+    }
+
+    let deadline = limits.time_budget.map(|budget| Instant::now() + budget);
+    DEADLINE.set(deadline);
+    let result = match options {
+        None => T::diff(source, target),
+        Some(options) => T::diff_with_options(source, target, options),
+    };
+    DEADLINE.set(None);
+    // Also reject the results of diffs that cannot stop early, so the outcome does not depend on the algorithm
+    if let (Some(budget), Some(deadline)) = (limits.time_budget, deadline) {
+        if Instant::now() > deadline {
+            return Err(CompressionError::from(DiffLimitError::TimeBudgetExceeded {
+                algorithm: T::algorithm(),
+                function: T::diff_name(),
+                budget,
+            })
+            .into());
+        }
+    }
+    Ok(result?)
 }
 
 fn patch_fn<T: Differ + UnwindSafe + RefUnwindSafe + 'static>(
     ctx: &Context,
-) -> Result<Option<Vec<u8>>> {
+) -> Result<Option<Value>> {
     let Some(source) = get_bytes(ctx, 0)? else {
         return Ok(None);
     };
     let Some(patch) = get_bytes(ctx, 1)? else {
         return Ok(None);
     };
-    Ok(Some(T::patch(source, patch)?))
+    let mut target = Vec::new();
+    apply_patch::<T>(source, patch, &mut target)?;
+    Ok(Some(to_value::<T>(target)))
+}
+
+/// A verified patch: the [`VERIFIED_MAGIC`] header, the SHA-256 hashes of the source and of the target,
+/// and the patch itself. Patch functions detect it by its header, and check both hashes.
+struct Envelope<'a> {
+    source_hash: &'a [u8],
+    target_hash: &'a [u8],
+    patch: &'a [u8],
+}
+
+const VERIFIED_MAGIC: &[u8] = b"SQLCDV1\0";
+
+const HASH_SIZE: usize = 32;
+
+impl<'a> Envelope<'a> {
+    fn wrap(source: &[u8], target: &[u8], patch: &[u8]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(VERIFIED_MAGIC.len() + 2 * HASH_SIZE + patch.len());
+        result.extend_from_slice(VERIFIED_MAGIC);
+        result.extend_from_slice(&Sha256::digest(source));
+        result.extend_from_slice(&Sha256::digest(target));
+        result.extend_from_slice(patch);
+        result
+    }
+
+    /// Parse a verified patch, or return `None` if the patch is not verified.
+    fn parse<T: Differ>(patch: &'a [u8]) -> Result<Option<Self>, CompressionError> {
+        let Some(body) = patch.strip_prefix(VERIFIED_MAGIC) else {
+            return Ok(None);
+        };
+        if body.len() < 2 * HASH_SIZE {
+            return Err(verify_error::<T>(
+                patch.len() as u64,
+                "the verified patch is truncated",
+            ));
+        }
+        let (source_hash, body) = body.split_at(HASH_SIZE);
+        let (target_hash, patch) = body.split_at(HASH_SIZE);
+        Ok(Some(Self {
+            source_hash,
+            target_hash,
+            patch,
+        }))
+    }
+}
+
+fn verify_error<T: Differ>(offset: u64, msg: &str) -> CompressionError {
+    CompressionError::corrupt_at(
+        T::algorithm(),
+        offset,
+        format!("{}(): {msg}", T::patch_name()),
+    )
+}
+
+/// Apply a patch with [`Differ::patch_into`]. If it is a verified patch, fail before patching if the source
+/// does not match, and after patching if the result does not match.
+pub(crate) fn apply_patch<T: Differ>(
+    source: &[u8],
+    patch: &[u8],
+    target: &mut Vec<u8>,
+) -> Result<(), CompressionError> {
+    let Some(envelope) = Envelope::parse::<T>(patch)? else {
+        return T::patch_into(source, patch, target);
+    };
+    if Sha256::digest(source).as_slice() != envelope.source_hash {
+        return Err(verify_error::<T>(
+            VERIFIED_MAGIC.len() as u64,
+            "the source does not match the one the verified patch was created for",
+        ));
+    }
+    T::patch_into(source, envelope.patch, target)?;
+    if Sha256::digest(&*target).as_slice() != envelope.target_hash {
+        return Err(verify_error::<T>(
+            (VERIFIED_MAGIC.len() + HASH_SIZE) as u64,
+            "the result does not match the target of the verified patch",
+        ));
+    }
+    Ok(())
+}
+
+/// Same as [`Differ::test`], but also accepts verified patches. With a source, a verified patch is applied
+/// to check the hash of the result.
+fn test_patch<T: Differ>(patch: &[u8], source: Option<&[u8]>) -> bool {
+    match (Envelope::parse::<T>(patch), source) {
+        (Ok(None), _) => T::test(patch, source),
+        (Ok(Some(envelope)), None) => T::test(envelope.patch, None),
+        (Ok(Some(_)), Some(source)) => apply_patch::<T>(source, patch, &mut Vec::new()).is_ok(),
+        (Err(_), _) => false,
+    }
+}
+
+pub(crate) fn to_value<T: Differ>(data: Vec<u8>) -> Value {
+    if T::TEXT_OUTPUT {
+        match String::from_utf8(data) {
+            Ok(text) => Value::Text(text),
+            Err(e) => Value::Blob(e.into_bytes()),
+        }
+    } else {
+        Value::Blob(data)
+    }
+}
+
+fn testing_fn<T: Differ + UnwindSafe + RefUnwindSafe + 'static>(
+    ctx: &Context,
+) -> Result<Option<bool>> {
+    // The patch is always the last argument, optionally preceded by the source
+    let param_count = ctx.len();
+    if param_count == 0 || param_count > 2 {
+        return Err(InvalidParameterCount(param_count, 1));
+    }
+    let source = if param_count == 2 {
+        let Some(source) = get_bytes(ctx, 0)? else {
+            return Ok(None);
+        };
+        Some(source)
+    } else {
+        None
+    };
+    let Some(patch) = get_bytes(ctx, param_count - 1)? else {
+        return Ok(None);
+    };
+    trace!("{}: testing patch {patch:?}", T::test_name());
+    Ok(Some(test_patch::<T>(patch, source)))
+}
+
+/// Aggregate that applies an ordered series of patches to the base blob given in the first row.
+/// Rows with a `NULL` patch are skipped, and a `NULL` base results in `NULL`.
+struct PatchChain<T>(PhantomData<T>);
+
+#[derive(Default)]
+struct ChainState {
+    started: bool,
+    current: Option<Vec<u8>>,
+    spare: Vec<u8>,
 }
 
-pub(crate) fn get_bytes<'a>(ctx: &'a Context, index: usize) -> Result<Option<&'a [u8]>> {
-    match ctx.get_raw(index) {
-        ValueRef::Blob(val) | ValueRef::Text(val) => Ok(Some(val)),
-        ValueRef::Null => Ok(None),
-        ValueRef::Integer(_) => Err(InvalidFunctionParameterType(index, Type::Integer)),
-        ValueRef::Real(_) => Err(InvalidFunctionParameterType(index, Type::Real)),
+impl<T: Differ + UnwindSafe + RefUnwindSafe + 'static> Aggregate<ChainState, Option<Value>>
+    for PatchChain<T>
+{
+    fn init(&self, _: &mut Context<'_>) -> Result<ChainState> {
+        Ok(ChainState::default())
     }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut ChainState) -> Result<()> {
+        if !acc.started {
+            acc.started = true;
+            acc.current = get_bytes(ctx, 0)?.map(<[u8]>::to_vec);
+        }
+        let Some(current) = &mut acc.current else {
+            return Ok(());
+        };
+        let Some(patch) = get_bytes(ctx, 1)? else {
+            return Ok(());
+        };
+        trace!("{}: applying patch {patch:?}", T::chain_name());
+        apply_patch::<T>(current, patch, &mut acc.spare)?;
+        mem::swap(current, &mut acc.spare);
+        Ok(())
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, acc: Option<ChainState>) -> Result<Option<Value>> {
+        Ok(acc.and_then(|acc| acc.current).map(to_value::<T>))
+    }
+}
+
+/// Window function that diffs the last row of the window frame against the row before it,
+/// e.g. `bsdiff4_prev(content) OVER (ORDER BY version)`. The first row, and rows after a `NULL`, result in `NULL`.
+/// `SQLite` does not tell which row of the frame is the current one, so the last row must be: the frame must end at
+/// the current row, and its peers must not be added with it. This holds when exactly one row is added to the frame
+/// before each [`WindowAggregate::value`] call, which is checked. Rows are only removed from the start of the frame,
+/// so only the number of rows and the last two values need to be kept.
+struct PrevDiff<T> {
+    limits: ConnLimits,
+    differ: PhantomData<T>,
+}
+
+#[derive(Default)]
+struct PrevState {
+    rows: usize,
+    /// Number of rows added since the last `value` call, and whether it was called at all
+    added: usize,
+    windowed: bool,
+    prev: Option<Vec<u8>>,
+    current: Option<Vec<u8>>,
+}
+
+impl PrevState {
+    fn diff<T: Differ>(&self, limits: DiffLimits) -> Result<Option<Value>> {
+        match (&self.prev, &self.current) {
+            (Some(prev), Some(current)) if self.rows > 1 => Ok(Some(to_value::<T>(run_diff::<T>(
+                prev, current, None, limits,
+            )?))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<T: Differ + UnwindSafe + RefUnwindSafe + 'static> Aggregate<PrevState, Option<Value>>
+    for PrevDiff<T>
+{
+    fn init(&self, _: &mut Context<'_>) -> Result<PrevState> {
+        Ok(PrevState::default())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut PrevState) -> Result<()> {
+        acc.rows += 1;
+        acc.added += 1;
+        // Reuse the buffer of the value that leaves the state
+        mem::swap(&mut acc.prev, &mut acc.current);
+        acc.current = get_bytes(ctx, 0)?.map(|value| {
+            let mut buf = acc.current.take().unwrap_or_default();
+            buf.clear();
+            buf.extend_from_slice(value);
+            buf
+        });
+        Ok(())
+    }
+
+    /// Called at the end of each partition, after the last `value` call. Without any, the function was used as a plain
+    /// aggregate, or with an `EXCLUDE` frame, which `SQLite` evaluates from scratch for every row.
+    fn finalize(&self, _: &mut Context<'_>, acc: Option<PrevState>) -> Result<Option<Value>> {
+        match acc {
+            Some(acc) if !acc.windowed => Err(frame_error::<T>()),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<T: Differ + UnwindSafe + RefUnwindSafe + 'static> WindowAggregate<PrevState, Option<Value>>
+    for PrevDiff<T>
+{
+    fn value(&self, acc: Option<&mut PrevState>) -> Result<Option<Value>> {
+        // Without any rows added, the frame does not include the current row
+        let Some(acc) = acc else {
+            return Err(frame_error::<T>());
+        };
+        acc.windowed = true;
+        if mem::take(&mut acc.added) != 1 {
+            return Err(frame_error::<T>());
+        }
+        acc.diff::<T>(self.limits.get())
+    }
+
+    fn inverse(&self, _: &mut Context<'_>, acc: &mut PrevState) -> Result<()> {
+        acc.rows -= 1;
+        Ok(())
+    }
+}
+
+fn frame_error<T: Differ>() -> rusqlite::Error {
+    UserFunctionError(
+        format!(
+            "{}() must be used as a window function whose frame ends at the current row, without ties in ORDER BY, e.g. OVER (ORDER BY version)",
+            T::prev_name()
+        )
+        .into(),
+    )
 }
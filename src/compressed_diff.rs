@@ -0,0 +1,169 @@
+use std::io::Read as _;
+
+#[cfg(feature = "trace")]
+use log::trace;
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::Error::UserFunctionError;
+
+use crate::args::{get_bytes, unknown_algorithm};
+use crate::common::{dispatch_encoder, Encoder};
+use crate::common_diff::{
+    apply_patch, dispatch_differ, run_diff, ConnLimits, DiffLimitError, Differ,
+};
+use crate::error::CompressionError;
+use crate::rusqlite::{Connection, Result};
+
+#[cfg(not(feature = "trace"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {};
+}
+
+/// Register the `compressed_diff` and `compressed_patch` SQL functions with the given `SQLite` connection.
+/// Diffing compressed blobs directly produces poor patches, because compression scrambles the bytes.
+/// The `compressed_diff` function takes two compressed blobs, the name of the compression algorithm,
+/// and the name of a diff function, e.g. `compressed_diff(old, new, 'gzip', 'bsdiff4')`, and returns the patch
+/// between the decompressed contents, same as `bsdiff4(gzip_decode(old), gzip_decode(new))`.
+/// The `compressed_patch` function takes a compressed blob, a patch, the same names, and an optional quality,
+/// and returns the patched content compressed again, same as `gzip(bspatch4(gzip_decode(old), patch), quality)`.
+/// The same quality must be used as for the original blob to get the exact same bytes.
+/// The [`DiffLimits`](crate::DiffLimits) apply to the decompressed contents, and decompression stops
+/// as soon as they exceed `max_input_size`.
+/// If the data or the patch is `NULL`, the result is `NULL`.
+///
+/// # Example
+///
+/// ```
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::register_compression_functions;
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// register_compression_functions(&db)?;
+/// # if cfg!(all(feature = "gzip", feature = "bsdiff4")) {
+/// let sql = "SELECT compressed_patch(gzip('013479'), compressed_diff(gzip('013479'), gzip('23456789'), 'gzip', 'bsdiff4'), 'gzip', 'bsdiff4') = gzip('23456789')";
+/// let result: bool = db.query_row(sql, [], |r| r.get(0))?;
+/// assert!(result);
+/// let sql = "SELECT compressed_diff(gzip('013479'), gzip('23456789'), 'gzip', 'bsdiff4') = bsdiff4('013479', '23456789')";
+/// let result: bool = db.query_row(sql, [], |r| r.get(0))?;
+/// assert!(result);
+/// # }
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_compressed_diff_functions(conn: &Connection) -> Result<()> {
+    register_compressed_diff_functions_with_limits(conn, &ConnLimits::default())
+}
+
+pub(crate) fn register_compressed_diff_functions_with_limits(
+    conn: &Connection,
+    limits: &ConnLimits,
+) -> Result<()> {
+    // FunctionFlags derive Copy trait only in v0.31+, but we support v0.30+
+    macro_rules! flags {
+        () => {
+            FunctionFlags::SQLITE_UTF8
+                | FunctionFlags::SQLITE_DETERMINISTIC
+                | FunctionFlags::SQLITE_DIRECTONLY
+        };
+    }
+
+    trace!("Registering function compressed_diff");
+    let diff_limits = limits.clone();
+    conn.create_scalar_function("compressed_diff", 4, flags!(), move |ctx| {
+        compressed_diff_fn(ctx, &diff_limits)
+    })?;
+    trace!("Registering function compressed_patch");
+    let patch_limits = limits.clone();
+    conn.create_scalar_function("compressed_patch", 4, flags!(), move |ctx| {
+        compressed_patch_fn(ctx, &patch_limits)
+    })?;
+    let patch_limits = limits.clone();
+    conn.create_scalar_function("compressed_patch", 5, flags!(), move |ctx| {
+        compressed_patch_fn(ctx, &patch_limits)
+    })
+}
+
+fn compressed_diff_fn(ctx: &Context, limits: &ConnLimits) -> Result<Option<Vec<u8>>> {
+    let Some(source) = get_bytes(ctx, 0)? else {
+        return Ok(None);
+    };
+    let Some(target) = get_bytes(ctx, 1)? else {
+        return Ok(None);
+    };
+    let compression = ctx.get::<String>(2)?;
+    let differ = ctx.get::<String>(3)?;
+    trace!("compressed_diff: diffing {compression} blobs with {differ}");
+    let limits = limits.get();
+    dispatch_differ!(differ.as_str(), |Diff| {
+        dispatch_encoder!(compression.as_str(), |Enc| {
+            let source = decode::<Enc, Diff>(source, "compressed_diff", limits.max_input_size)?;
+            let target = decode::<Enc, Diff>(target, "compressed_diff", limits.max_input_size)?;
+            run_diff::<Diff>(&source, &target, None, limits)
+        })
+        .ok_or_else(|| unknown_algorithm(&compression))?
+    })
+    .ok_or_else(|| unknown_differ(&differ))?
+    .map(Some)
+}
+
+fn compressed_patch_fn(ctx: &Context, limits: &ConnLimits) -> Result<Option<Vec<u8>>> {
+    let Some(source) = get_bytes(ctx, 0)? else {
+        return Ok(None);
+    };
+    let Some(patch) = get_bytes(ctx, 1)? else {
+        return Ok(None);
+    };
+    let compression = ctx.get::<String>(2)?;
+    let differ = ctx.get::<String>(3)?;
+    let quality = if ctx.len() > 4 {
+        Some(ctx.get::<u32>(4)?)
+    } else {
+        None
+    };
+    trace!("compressed_patch: patching {compression} blob with {differ}");
+    let limit = limits.get().max_input_size;
+    dispatch_differ!(differ.as_str(), |Diff| {
+        dispatch_encoder!(compression.as_str(), |Enc| {
+            let source = decode::<Enc, Diff>(source, "compressed_patch", limit)?;
+            let mut target = Vec::new();
+            apply_patch::<Diff>(&source, patch, &mut target)?;
+            Enc::encode(&target, quality)
+        })
+        .ok_or_else(|| unknown_algorithm(&compression))?
+    })
+    .ok_or_else(|| unknown_differ(&differ))?
+    .map(Some)
+    .map_err(Into::into)
+}
+
+/// Decompress `data`, but stop as soon as the output exceeds `limit`,
+/// so that a small compressed blob cannot expand to a huge buffer before the [`DiffLimits`] are checked.
+///
+/// [`DiffLimits`]: crate::DiffLimits
+fn decode<Enc: Encoder, Diff: Differ>(
+    data: &[u8],
+    function: &'static str,
+    limit: Option<usize>,
+) -> Result<Vec<u8>, CompressionError> {
+    let Some(limit) = limit else {
+        return Enc::decode(data);
+    };
+    let mut decoded = Vec::new();
+    Enc::decoder(data)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|e| CompressionError::decode_io(Enc::enc_name(), e))?;
+    if decoded.len() > limit {
+        return Err(DiffLimitError::InputTooLarge {
+            algorithm: Diff::algorithm(),
+            function,
+            size: decoded.len(),
+            limit,
+        }
+        .into());
+    }
+    Ok(decoded)
+}
+
+fn unknown_differ(name: &str) -> rusqlite::Error {
+    UserFunctionError(format!("Unknown diff function '{name}'").into())
+}
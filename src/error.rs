@@ -0,0 +1,220 @@
+use std::{fmt, io};
+
+use rusqlite::Error::UserFunctionError;
+
+#[cfg(any(
+    feature = "bsdiff4",
+    feature = "bsdiffraw",
+    feature = "fossil",
+    feature = "json",
+    feature = "rsync",
+    feature = "text",
+    feature = "vcdiff",
+    feature = "zstd"
+))]
+use crate::common_diff::DiffLimitError;
+
+/// The error of the [`Encoder`](crate::Encoder) and [`Differ`](crate::Differ) methods.
+/// It is converted to [`rusqlite::Error::UserFunctionError`] at the SQL boundary, where `SQLite` only keeps its message.
+/// In Rust, it can be recovered from that error with `downcast_ref::<CompressionError>()` on the boxed error.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CompressionError {
+    /// The compression level or quality is out of range
+    InvalidLevel {
+        algorithm: &'static str,
+        message: String,
+    },
+    /// An option other than the level is unknown, has the wrong type, or is out of range
+    InvalidOption {
+        algorithm: &'static str,
+        message: String,
+    },
+    /// The compressed data or the patch is corrupt or truncated, or does not match the source.
+    /// The offset is the position in the compressed data or the patch where the problem was found, if known.
+    CorruptData {
+        algorithm: &'static str,
+        offset: Option<u64>,
+        message: String,
+    },
+    /// The input of a diff SQL function exceeded the [`DiffLimits`](crate::DiffLimits)
+    #[cfg(any(
+        feature = "bsdiff4",
+        feature = "bsdiffraw",
+        feature = "fossil",
+        feature = "json",
+        feature = "rsync",
+        feature = "text",
+        feature = "vcdiff",
+        feature = "zstd"
+    ))]
+    LimitExceeded(DiffLimitError),
+    /// The data is valid, but uses a format version or a feature that is not supported,
+    /// or cannot be represented in the output format
+    UnsupportedFormat {
+        algorithm: &'static str,
+        message: String,
+    },
+    /// Reading or writing the data failed for another reason
+    Io {
+        algorithm: &'static str,
+        source: io::Error,
+    },
+}
+
+impl CompressionError {
+    /// The stable identifier of the algorithm that failed: the name of the compression algorithm, e.g. `gzip`,
+    /// or the [`Differ::algorithm`](crate::Differ::algorithm), e.g. `bsdiff4`, `fossil`, or `json_patch`.
+    #[must_use]
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            Self::InvalidLevel { algorithm, .. }
+            | Self::InvalidOption { algorithm, .. }
+            | Self::CorruptData { algorithm, .. }
+            | Self::UnsupportedFormat { algorithm, .. }
+            | Self::Io { algorithm, .. } => algorithm,
+            #[cfg(any(
+                feature = "bsdiff4",
+                feature = "bsdiffraw",
+                feature = "fossil",
+                feature = "json",
+                feature = "rsync",
+                feature = "text",
+                feature = "vcdiff",
+                feature = "zstd"
+            ))]
+            Self::LimitExceeded(err) => err.algorithm(),
+        }
+    }
+
+    /// Create an [`CompressionError::InvalidLevel`] error.
+    #[must_use]
+    pub fn invalid_level(algorithm: &'static str, message: impl Into<String>) -> Self {
+        Self::InvalidLevel {
+            algorithm,
+            message: message.into(),
+        }
+    }
+
+    /// Create an [`CompressionError::InvalidOption`] error.
+    #[must_use]
+    pub fn invalid_option(algorithm: &'static str, message: impl Into<String>) -> Self {
+        Self::InvalidOption {
+            algorithm,
+            message: message.into(),
+        }
+    }
+
+    /// Create a [`CompressionError::CorruptData`] error without an offset.
+    #[must_use]
+    pub fn corrupt(algorithm: &'static str, message: impl fmt::Display) -> Self {
+        Self::CorruptData {
+            algorithm,
+            offset: None,
+            message: message.to_string(),
+        }
+    }
+
+    /// Create a [`CompressionError::CorruptData`] error at the given offset.
+    #[must_use]
+    pub fn corrupt_at(algorithm: &'static str, offset: u64, message: impl fmt::Display) -> Self {
+        Self::CorruptData {
+            algorithm,
+            offset: Some(offset),
+            message: message.to_string(),
+        }
+    }
+
+    /// Create an [`CompressionError::UnsupportedFormat`] error.
+    #[must_use]
+    pub fn unsupported(algorithm: &'static str, message: impl fmt::Display) -> Self {
+        Self::UnsupportedFormat {
+            algorithm,
+            message: message.to_string(),
+        }
+    }
+
+    /// Wrap an I/O error of an encoder or a differ as [`CompressionError::Io`].
+    #[must_use]
+    pub fn io(algorithm: &'static str, source: io::Error) -> Self {
+        Self::Io { algorithm, source }
+    }
+
+    /// Wrap an I/O error of a decoder or a patch. They report invalid input as I/O errors,
+    /// so those become [`CompressionError::CorruptData`].
+    #[must_use]
+    pub fn decode_io(algorithm: &'static str, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::InvalidData
+            | io::ErrorKind::InvalidInput
+            | io::ErrorKind::UnexpectedEof => Self::corrupt(algorithm, source),
+            _ => Self::io(algorithm, source),
+        }
+    }
+
+    /// Same as [`CompressionError::decode_io`], with the position in the compressed data or the patch.
+    #[must_use]
+    pub fn decode_io_at(algorithm: &'static str, offset: u64, source: io::Error) -> Self {
+        match Self::decode_io(algorithm, source) {
+            Self::CorruptData {
+                algorithm, message, ..
+            } => Self::corrupt_at(algorithm, offset, message),
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLevel { message, .. }
+            | Self::InvalidOption { message, .. }
+            | Self::CorruptData { message, .. }
+            | Self::UnsupportedFormat { message, .. } => f.write_str(message),
+            #[cfg(any(
+                feature = "bsdiff4",
+                feature = "bsdiffraw",
+                feature = "fossil",
+                feature = "json",
+                feature = "rsync",
+                feature = "text",
+                feature = "vcdiff",
+                feature = "zstd"
+            ))]
+            Self::LimitExceeded(err) => err.fmt(f),
+            Self::Io { source, .. } => source.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CompressionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "bsdiff4",
+    feature = "bsdiffraw",
+    feature = "fossil",
+    feature = "json",
+    feature = "rsync",
+    feature = "text",
+    feature = "vcdiff",
+    feature = "zstd"
+))]
+impl From<DiffLimitError> for CompressionError {
+    fn from(err: DiffLimitError) -> Self {
+        Self::LimitExceeded(err)
+    }
+}
+
+/// The SQL functions report the error with its message, see [`CompressionError`].
+impl From<CompressionError> for rusqlite::Error {
+    fn from(err: CompressionError) -> Self {
+        UserFunctionError(Box::new(err))
+    }
+}
@@ -0,0 +1,447 @@
+use rusqlite::functions::{Context, FunctionFlags};
+
+use crate::args::get_bytes;
+use crate::common_diff::{register_differ, ConnLimits, Differ};
+use crate::error::CompressionError;
+use crate::rusqlite::{Connection, Result};
+
+/// Register the `delta_create`, `delta_apply`, and `delta_output_size` SQL functions with the given `SQLite` connection.
+/// These are compatible with the functions of the same name in the `SQLite`
+/// [fossildelta](https://sqlite.org/src/file/ext/misc/fossildelta.c) extension, and use
+/// the [Fossil delta format](https://fossil-scm.org/home/doc/tip/www/delta_format.wiki).
+/// The `delta_create` function takes two arguments, and returns the delta (blob) that converts the first one into
+/// the second one. The arguments can be either a string or a blob.
+/// The `delta_apply` function applies the delta to the source, and verifies the checksum of the result.
+/// The `delta_output_size` function returns the size of the target that the delta would produce.
+/// The `delta_apply_test` function checks that a delta is well-formed, and if the source is given as the first argument,
+/// that the delta can be applied to it.
+/// The `delta_apply_chain` aggregate function applies a series of deltas in order to the base blob from the first row,
+/// e.g. `delta_apply_chain(base, delta ORDER BY version)`.
+/// If any of the arguments are `NULL`, the result is `NULL`.
+///
+/// # Example
+///
+/// ```
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::register_fossil_functions;
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// register_fossil_functions(&db)?;
+/// let result: String = db.query_row("SELECT CAST(delta_create('hello world', 'hello brave new world') AS TEXT)", [], |r| r.get(0))?;
+/// assert_eq!(result.as_str(), "L\nL:hello brave new world22pHrA;");
+/// let result: String = db.query_row("SELECT CAST(delta_apply('013479', delta_create('013479', '23456789')) AS TEXT)", [], |r| r.get(0))?;
+/// assert_eq!(result.as_str(), "23456789");
+/// let result: i64 = db.query_row("SELECT delta_output_size(delta_create('013479', '23456789'))", [], |r| r.get(0))?;
+/// assert_eq!(result, 8);
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_fossil_functions(conn: &Connection) -> Result<()> {
+    register_fossil_functions_with_limits(conn, &ConnLimits::default())
+}
+
+pub(crate) fn register_fossil_functions_with_limits(
+    conn: &Connection,
+    limits: &ConnLimits,
+) -> Result<()> {
+    register_differ::<FossilDiffer>(conn, limits)?;
+    conn.create_scalar_function(
+        "delta_output_size",
+        1,
+        FunctionFlags::SQLITE_UTF8
+            | FunctionFlags::SQLITE_DETERMINISTIC
+            | FunctionFlags::SQLITE_DIRECTONLY,
+        output_size_fn,
+    )
+}
+
+fn output_size_fn(ctx: &Context) -> Result<Option<i64>> {
+    let Some(delta) = get_bytes(ctx, 0)? else {
+        return Ok(None);
+    };
+    Ok(Some(FossilDiffer::output_size(delta)?.into()))
+}
+
+pub struct FossilDiffer;
+
+impl FossilDiffer {
+    /// Return the size of the target that applying the delta would produce, as declared in its header.
+    pub fn output_size(delta: &[u8]) -> Result<u32, CompressionError> {
+        let mut reader = Reader(delta);
+        let size = reader.int();
+        if reader.peek() == Some(b'\n') {
+            Ok(size)
+        } else {
+            Err(corrupt(reader.offset(delta)))
+        }
+    }
+}
+
+impl Differ for FossilDiffer {
+    type Options = ();
+
+    fn algorithm() -> &'static str {
+        "fossil"
+    }
+
+    fn diff_name() -> &'static str {
+        "delta_create"
+    }
+
+    fn patch_name() -> &'static str {
+        "delta_apply"
+    }
+
+    fn test_name() -> &'static str {
+        "delta_apply_test"
+    }
+
+    fn chain_name() -> &'static str {
+        "delta_apply_chain"
+    }
+
+    fn prev_name() -> &'static str {
+        "delta_prev"
+    }
+
+    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        if u32::try_from(source.len()).is_err() || u32::try_from(target.len()).is_err() {
+            return Err(CompressionError::unsupported(
+                "fossil",
+                "fossil delta does not support blobs of 4GB or larger",
+            ));
+        }
+        Ok(create(source, target))
+    }
+
+    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let mut target = Vec::new();
+        Self::patch_into(source, patch, &mut target)?;
+        Ok(target)
+    }
+
+    fn patch_into(
+        source: &[u8],
+        patch: &[u8],
+        target: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        target.clear();
+        apply(Some(source), patch, target).map_err(corrupt)
+    }
+
+    fn test(patch: &[u8], source: Option<&[u8]>) -> bool {
+        apply(source, patch, &mut Vec::new()).is_ok()
+    }
+}
+
+/// Same error message as the fossildelta extension.
+fn corrupt(offset: u64) -> CompressionError {
+    CompressionError::corrupt_at("fossil", offset, "corrupt fossil delta")
+}
+
+/// Size of the blocks of the source that are indexed, and of the rolling hash window.
+const NHASH: usize = 16;
+
+const DIGITS: &[u8; 64] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz~";
+
+/// Rolling hash of `NHASH` bytes.
+///
+/// The reference implementation hashes `char` values, which are signed on the common platforms,
+/// so bytes are sign-extended here to pick the same matches and produce identical deltas.
+struct Hash {
+    a: u16,
+    b: u16,
+    i: usize,
+    z: [u16; NHASH],
+}
+
+fn signed(byte: u8) -> u16 {
+    // Sign extension of a `char`, wrapping as in the C code
+    #[expect(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+    let value = i16::from(byte as i8) as u16;
+    value
+}
+
+impl Hash {
+    fn new(data: &[u8]) -> Self {
+        let mut z = [0; NHASH];
+        for (z, &byte) in z.iter_mut().zip(&data[..NHASH]) {
+            *z = signed(byte);
+        }
+        let mut a = z[0];
+        let mut b = z[0];
+        for &v in &z[1..] {
+            a = a.wrapping_add(v);
+            b = b.wrapping_add(a);
+        }
+        Self { a, b, i: 0, z }
+    }
+
+    #[expect(clippy::cast_possible_truncation)]
+    fn next(&mut self, byte: u8) {
+        let old = self.z[self.i];
+        let new = signed(byte);
+        self.z[self.i] = new;
+        self.i = (self.i + 1) & (NHASH - 1);
+        self.a = self.a.wrapping_sub(old).wrapping_add(new);
+        self.b = self
+            .b
+            .wrapping_sub(old.wrapping_mul(NHASH as u16))
+            .wrapping_add(self.a);
+    }
+
+    fn value(&self) -> u32 {
+        u32::from(self.a) | (u32::from(self.b) << 16)
+    }
+}
+
+/// Number of base-64 digits needed to write `v`.
+fn digit_count(v: usize) -> usize {
+    let mut count = 1;
+    let mut x = 64_usize;
+    while v >= x {
+        count += 1;
+        x <<= 6;
+    }
+    count
+}
+
+/// Write `v` as a base-64 number. Values are limited to 32 bits like in the reference implementation.
+fn put_int(out: &mut Vec<u8>, v: usize) {
+    if v == 0 {
+        out.push(b'0');
+        return;
+    }
+    let start = out.len();
+    let mut v = v;
+    while v > 0 {
+        out.push(DIGITS[v & 0x3F]);
+        v >>= 6;
+    }
+    out[start..].reverse();
+}
+
+/// Sum of the data as big-endian 32-bit words, with the last partial word padded with zeros.
+fn checksum(data: &[u8]) -> u32 {
+    let chunks = data.chunks_exact(4);
+    let mut last = [0_u8; 4];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    chunks
+        .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+        .fold(u32::from_be_bytes(last), u32::wrapping_add)
+}
+
+/// A literal insert of the given bytes.
+fn put_insert(out: &mut Vec<u8>, data: &[u8]) {
+    put_int(out, data.len());
+    out.push(b':');
+    out.extend_from_slice(data);
+}
+
+/// A port of `delta_create()` from the reference implementation, producing byte-identical output.
+fn create(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(target.len() + 60);
+    put_int(&mut out, target.len());
+    out.push(b'\n');
+
+    // If the source is very small, there is no chance of ever doing a copy command
+    if source.len() <= NHASH {
+        put_insert(&mut out, target);
+        put_int(&mut out, checksum(target) as usize);
+        out.push(b';');
+        return out;
+    }
+
+    // Index the start of every NHASH-byte block of the source, except for the last one
+    let hash_count = source.len() / NHASH;
+    let mut collide = vec![usize::MAX; hash_count];
+    let mut landmark = vec![usize::MAX; hash_count];
+    for block in 0..(source.len() - NHASH).div_ceil(NHASH) {
+        let hv = Hash::new(&source[block * NHASH..]).value() as usize % hash_count;
+        collide[block] = landmark[hv];
+        landmark[hv] = block;
+    }
+
+    let mut base = 0;
+    while base + NHASH < target.len() {
+        let mut hash = Hash::new(&target[base..]);
+        let mut i = 0;
+        loop {
+            let (mut best_count, mut best_offset, mut best_literal) = (0, 0, 0);
+            let mut block = landmark[hash.value() as usize % hash_count];
+            let mut limit = 250;
+            while block != usize::MAX && limit > 0 {
+                limit -= 1;
+                let src = block * NHASH;
+                let pos = base + i;
+
+                // Match forward from source[src] and target[pos]
+                let forward = source[src..]
+                    .iter()
+                    .zip(&target[pos..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                // Match backward, neither reaching source[0] nor going before target[base]
+                let mut k = 1;
+                while k < src && k <= i && source[src - k] == target[pos - k] {
+                    k += 1;
+                }
+                let backward = k - 1;
+
+                let offset = src - backward;
+                let count = forward + backward;
+                let literal = i - backward;
+                // Only copy if the command is not longer than the bytes it replaces
+                let size = digit_count(literal) + digit_count(count) + digit_count(offset) + 3;
+                if count >= size && count > best_count {
+                    best_count = count;
+                    best_offset = offset;
+                    best_literal = literal;
+                }
+                block = collide[block];
+            }
+
+            if best_count > 0 {
+                if best_literal > 0 {
+                    put_insert(&mut out, &target[base..base + best_literal]);
+                    base += best_literal;
+                }
+                base += best_count;
+                put_int(&mut out, best_count);
+                out.push(b'@');
+                put_int(&mut out, best_offset);
+                out.push(b',');
+                break;
+            }
+            if base + i + NHASH >= target.len() {
+                // Reached the end of the target without finding any matches
+                put_insert(&mut out, &target[base..]);
+                base = target.len();
+                break;
+            }
+            hash.next(target[base + i + NHASH]);
+            i += 1;
+        }
+    }
+    if base < target.len() {
+        put_insert(&mut out, &target[base..]);
+    }
+    put_int(&mut out, checksum(target) as usize);
+    out.push(b';');
+    out
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.0.first().copied()
+    }
+
+    /// Read a base-64 number, wrapping on overflow. Like the reference implementation,
+    /// only the low 7 bits of each byte are used to look up the digit.
+    fn int(&mut self) -> u32 {
+        let mut value = 0_u32;
+        while let Some(digit) = self.peek().and_then(|c| digit_value(c & 0x7F)) {
+            value = (value << 6).wrapping_add(digit);
+            self.0 = &self.0[1..];
+        }
+        value
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        (self.peek()? == byte).then(|| self.0 = &self.0[1..])
+    }
+
+    /// Position of the reader in `data`, which must be the slice it was created with.
+    fn offset(&self, data: &[u8]) -> u64 {
+        (data.len() - self.0.len()) as u64
+    }
+}
+
+fn digit_value(c: u8) -> Option<u32> {
+    match c {
+        b'0'..=b'9' => Some(u32::from(c - b'0')),
+        b'A'..=b'Z' => Some(u32::from(c - b'A') + 10),
+        b'_' => Some(36),
+        b'a'..=b'z' => Some(u32::from(c - b'a') + 37),
+        b'~' => Some(63),
+        _ => None,
+    }
+}
+
+/// Apply the delta, or only validate it if the source is unknown.
+/// On failure, return the position in the delta where the problem was found.
+fn apply(source: Option<&[u8]>, delta: &[u8], target: &mut Vec<u8>) -> Result<(), u64> {
+    let mut reader = Reader(delta);
+    apply_from(&mut reader, source, delta.len(), target).ok_or_else(|| reader.offset(delta))
+}
+
+fn apply_from(
+    reader: &mut Reader,
+    source: Option<&[u8]>,
+    delta_len: usize,
+    target: &mut Vec<u8>,
+) -> Option<()> {
+    let limit = reader.int() as usize;
+    reader.expect(b'\n')?;
+    let mut total = 0_usize;
+    if source.is_some() {
+        target.reserve(limit.min(delta_len.saturating_mul(64)));
+    }
+    loop {
+        let count = reader.int() as usize;
+        match reader.peek()? {
+            b'@' => {
+                reader.expect(b'@')?;
+                let offset = reader.int() as usize;
+                reader.expect(b',')?;
+                total = total.checked_add(count).filter(|&t| t <= limit)?;
+                if let Some(source) = source {
+                    target.extend_from_slice(source.get(offset..offset.checked_add(count)?)?);
+                }
+            }
+            b':' => {
+                reader.expect(b':')?;
+                total = total.checked_add(count).filter(|&t| t <= limit)?;
+                let (literal, rest) = reader.0.split_at_checked(count)?;
+                if source.is_some() {
+                    target.extend_from_slice(literal);
+                }
+                reader.0 = rest;
+            }
+            b';' => {
+                let valid =
+                    total == limit && (source.is_none() || count == checksum(target) as usize);
+                return valid.then_some(());
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash() {
+        // the rolling hash must be the same as hashing the window from scratch
+        let data = b"0123456789ABCDEF\xFF\x80\x7F\x00FEDCBA9876543210";
+        let mut hash = Hash::new(data);
+        for i in 0..data.len() - NHASH {
+            hash.next(data[i + NHASH]);
+            assert_eq!(hash.value(), Hash::new(&data[i + 1..]).value());
+        }
+    }
+
+    #[test]
+    fn test_int() {
+        for v in [0, 1, 63, 64, 4095, 4096, u32::MAX as usize] {
+            let mut out = Vec::new();
+            put_int(&mut out, v);
+            assert_eq!(out.len(), digit_count(v));
+            assert_eq!(Reader(&out).int() as usize, v);
+        }
+    }
+}
@@ -1,11 +1,11 @@
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use rusqlite::Error::UserFunctionError;
 
 use crate::common::{register_compression, Encoder};
+use crate::error::CompressionError;
 use crate::rusqlite::{Connection, Result};
 
 /// Register the `gzip` SQL functions with the given `SQLite` connection.
@@ -50,31 +50,20 @@ impl Encoder for GzipEncoder {
         "gzip_test"
     }
 
-    fn encode(data: &[u8], quality: Option<u32>) -> Result<Vec<u8>> {
-        let quality = if let Some(param) = quality {
-            if param > 9 {
-                return Err(UserFunctionError(
-                    "The optional second argument to gzip() must be between 0 and 9".into(),
-                ));
-            }
-            Compression::new(param)
-        } else {
-            Compression::default()
-        };
-
-        let mut encoder = GzEncoder::new(Vec::new(), quality);
+    fn encode_stream<R: Read, W: Write>(
+        reader: &mut R,
+        writer: W,
+        quality: Option<u32>,
+    ) -> Result<W, CompressionError> {
+        let mut encoder = GzEncoder::new(writer, compression(quality)?);
+        io::copy(reader, &mut encoder).map_err(|e| CompressionError::io("gzip", e))?;
         encoder
-            .write_all(data)
-            .map_err(|e| UserFunctionError(e.into()))?;
-        encoder.finish().map_err(|e| UserFunctionError(e.into()))
+            .finish()
+            .map_err(|e| CompressionError::io("gzip", e))
     }
 
-    fn decode(data: &[u8]) -> Result<Vec<u8>> {
-        let mut decompressed = Vec::new();
+    fn decoder(data: &[u8]) -> impl Read + '_ {
         GzDecoder::new(data)
-            .read_to_end(&mut decompressed)
-            .map_err(|e| UserFunctionError(e.into()))?;
-        Ok(decompressed)
     }
 
     fn test(data: &[u8]) -> bool {
@@ -91,3 +80,17 @@ impl Encoder for GzipEncoder {
         false
     }
 }
+
+/// The compression level for the optional quality argument.
+pub(crate) fn compression(quality: Option<u32>) -> Result<Compression, CompressionError> {
+    let Some(param) = quality else {
+        return Ok(Compression::default());
+    };
+    if param > 9 {
+        return Err(CompressionError::invalid_level(
+            "gzip",
+            "The optional second argument to gzip() must be between 0 and 9",
+        ));
+    }
+    Ok(Compression::new(param))
+}
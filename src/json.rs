@@ -0,0 +1,243 @@
+use std::fmt::Display;
+
+use json_patch::{diff, merge, patch, Patch};
+use serde_json::{Map, Value};
+
+use crate::common_diff::{register_differ, ConnLimits, Differ};
+use crate::error::CompressionError;
+use crate::rusqlite::{Connection, Result};
+
+/// Register the JSON Patch and JSON Merge Patch SQL functions with the given `SQLite` connection.
+/// The `json_diff` function takes two JSON documents, and returns an [RFC 6902](https://www.rfc-editor.org/rfc/rfc6902)
+/// JSON Patch (text) that converts the first document into the second one.
+/// The `json_patch_apply` function applies an RFC 6902 patch to a document. A patch that fails to apply,
+/// e.g. because of a failed `test` operation, results in an error.
+/// The `json_merge_diff` and `json_merge_patch` functions do the same using an
+/// [RFC 7396](https://www.rfc-editor.org/rfc/rfc7396) JSON Merge Patch, which is also accepted by the built-in
+/// `json_patch` function of `SQLite`.
+/// The `json_patch_test` and `json_merge_patch_test` functions check that a patch is well-formed, and if the document
+/// is given as the first argument, that the patch can be applied to it.
+/// The `json_patch_chain` and `json_merge_patch_chain` aggregate functions apply a series of patches in order
+/// to the base document from the first row, e.g. `json_patch_chain(base, patch ORDER BY version)`.
+/// The arguments must be JSON text. If any of the arguments are `NULL`, the result is `NULL`.
+///
+/// # Example
+///
+/// ```
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::register_json_functions;
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// register_json_functions(&db)?;
+/// let result: String = db.query_row(r#"SELECT json_diff('{"a":1,"b":[1,2]}', '{"a":1,"b":[1,3]}')"#, [], |r| r.get(0))?;
+/// assert_eq!(result, r#"[{"op":"replace","path":"/b/1","value":3}]"#);
+/// let result: String = db.query_row(r#"SELECT json_patch_apply('{"a":1}', '[{"op":"add","path":"/b","value":2}]')"#, [], |r| r.get(0))?;
+/// assert_eq!(result, r#"{"a":1,"b":2}"#);
+/// let result: String = db.query_row(r#"SELECT json_merge_diff('{"a":1,"b":2}', '{"a":1,"c":3}')"#, [], |r| r.get(0))?;
+/// assert_eq!(result, r#"{"b":null,"c":3}"#);
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_json_functions(conn: &Connection) -> Result<()> {
+    register_json_functions_with_limits(conn, &ConnLimits::default())
+}
+
+pub(crate) fn register_json_functions_with_limits(
+    conn: &Connection,
+    limits: &ConnLimits,
+) -> Result<()> {
+    register_differ::<JsonPatchDiffer>(conn, limits)?;
+    register_differ::<JsonMergeDiffer>(conn, limits)
+}
+
+/// Creates and applies [RFC 6902](https://www.rfc-editor.org/rfc/rfc6902) JSON Patch documents.
+pub struct JsonPatchDiffer;
+
+impl Differ for JsonPatchDiffer {
+    type Options = ();
+
+    fn algorithm() -> &'static str {
+        "json_patch"
+    }
+
+    fn diff_name() -> &'static str {
+        "json_diff"
+    }
+
+    fn patch_name() -> &'static str {
+        "json_patch_apply"
+    }
+
+    fn test_name() -> &'static str {
+        "json_patch_test"
+    }
+
+    fn chain_name() -> &'static str {
+        "json_patch_chain"
+    }
+
+    fn prev_name() -> &'static str {
+        "json_diff_prev"
+    }
+
+    const TEXT_OUTPUT: bool = true;
+
+    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let patch = diff(&parse::<Self>(source)?, &parse::<Self>(target)?);
+        serde_json::to_vec(&patch).map_err(corrupt::<Self>)
+    }
+
+    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let mut doc = parse::<Self>(source)?;
+        apply(&mut doc, &parse_patch(patch)?)?;
+        to_json::<Self>(&doc)
+    }
+
+    fn test(patch: &[u8], source: Option<&[u8]>) -> bool {
+        let Ok(patch) = parse_patch(patch) else {
+            return false;
+        };
+        match source.map(parse::<Self>) {
+            None => true,
+            Some(Ok(mut doc)) => apply(&mut doc, &patch).is_ok(),
+            Some(Err(_)) => false,
+        }
+    }
+}
+
+/// Creates and applies [RFC 7396](https://www.rfc-editor.org/rfc/rfc7396) JSON Merge Patch documents.
+pub struct JsonMergeDiffer;
+
+impl JsonMergeDiffer {
+    /// Compute the merge patch that converts `source` into `target`.
+    /// Merge patches use `null` to remove object members, so they cannot set any object member to `null`.
+    fn merge_diff(source: &Value, target: &Value) -> Result<Value, CompressionError> {
+        let (Value::Object(source), Value::Object(target)) = (source, target) else {
+            return Self::replacement(target);
+        };
+        let mut result = Map::new();
+        for key in source.keys() {
+            if !target.contains_key(key) {
+                result.insert(key.clone(), Value::Null);
+            }
+        }
+        for (key, value) in target {
+            match source.get(key) {
+                Some(old) if old == value => {}
+                _ if value.is_null() => return Err(null_member()),
+                Some(old) => {
+                    result.insert(key.clone(), Self::merge_diff(old, value)?);
+                }
+                None => {
+                    result.insert(key.clone(), Self::replacement(value)?);
+                }
+            }
+        }
+        Ok(Value::Object(result))
+    }
+
+    /// Use a value as is, making sure none of its object members are `null`.
+    /// Arrays are always replaced as a whole, so their content is not checked.
+    fn replacement(value: &Value) -> Result<Value, CompressionError> {
+        fn has_null_member(value: &Value) -> bool {
+            match value {
+                Value::Object(map) => map.values().any(|v| v.is_null() || has_null_member(v)),
+                _ => false,
+            }
+        }
+        if has_null_member(value) {
+            Err(null_member())
+        } else {
+            Ok(value.clone())
+        }
+    }
+}
+
+fn null_member() -> CompressionError {
+    CompressionError::unsupported(
+        "json_merge_patch",
+        "json_merge_diff() cannot set an object member to null, use json_diff() instead",
+    )
+}
+
+impl Differ for JsonMergeDiffer {
+    type Options = ();
+
+    fn algorithm() -> &'static str {
+        "json_merge_patch"
+    }
+
+    fn diff_name() -> &'static str {
+        "json_merge_diff"
+    }
+
+    fn patch_name() -> &'static str {
+        "json_merge_patch"
+    }
+
+    fn test_name() -> &'static str {
+        "json_merge_patch_test"
+    }
+
+    fn chain_name() -> &'static str {
+        "json_merge_patch_chain"
+    }
+
+    fn prev_name() -> &'static str {
+        "json_merge_diff_prev"
+    }
+
+    const TEXT_OUTPUT: bool = true;
+
+    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        to_json::<Self>(&Self::merge_diff(
+            &parse::<Self>(source)?,
+            &parse::<Self>(target)?,
+        )?)
+    }
+
+    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let mut doc = parse::<Self>(source)?;
+        merge(&mut doc, &parse::<Self>(patch)?);
+        to_json::<Self>(&doc)
+    }
+
+    fn test(patch: &[u8], source: Option<&[u8]>) -> bool {
+        // Any JSON value is a valid merge patch, and it can be applied to any document
+        parse::<Self>(patch).is_ok() && source.is_none_or(|v| parse::<Self>(v).is_ok())
+    }
+}
+
+fn corrupt<T: Differ>(err: impl Display) -> CompressionError {
+    CompressionError::corrupt(T::algorithm(), err)
+}
+
+fn parse<T: Differ>(data: &[u8]) -> Result<Value, CompressionError> {
+    serde_json::from_slice(data).map_err(|e| parse_error::<T>(data, &e))
+}
+
+fn parse_patch(data: &[u8]) -> Result<Patch, CompressionError> {
+    serde_json::from_slice(data).map_err(|e| parse_error::<JsonPatchDiffer>(data, &e))
+}
+
+/// `serde_json` only reports the line and the column of syntax errors, so find their byte offset from them.
+fn parse_error<T: Differ>(data: &[u8], err: &serde_json::Error) -> CompressionError {
+    if err.line() == 0 {
+        return corrupt::<T>(err);
+    }
+    let line_start: usize = data
+        .split_inclusive(|&b| b == b'\n')
+        .take(err.line() - 1)
+        .map(<[u8]>::len)
+        .sum();
+    let offset = line_start + err.column().saturating_sub(1);
+    CompressionError::corrupt_at(T::algorithm(), offset as u64, err)
+}
+
+fn apply(doc: &mut Value, ops: &Patch) -> Result<(), CompressionError> {
+    patch(doc, ops).map_err(corrupt::<JsonPatchDiffer>)
+}
+
+fn to_json<T: Differ>(value: &Value) -> Result<Vec<u8>, CompressionError> {
+    serde_json::to_vec(value).map_err(corrupt::<T>)
+}
@@ -8,10 +8,17 @@
     feature = "bsdiff4",
     feature = "bsdiffraw",
     feature = "bzip2",
+    feature = "cdc",
+    feature = "fossil",
     feature = "gzip",
+    feature = "json",
+    feature = "rsync",
+    feature = "text",
+    feature = "vcdiff",
+    feature = "zstd",
 )))]
 compile_error!(
-    "At least one of these features must be enabled: gzip, brotli, bzip2, bsdiff4, bsdiffraw"
+    "At least one of these features must be enabled: gzip, brotli, bzip2, bsdiff4, bsdiffraw, cdc, fossil, json, rsync, text, vcdiff, zstd"
 );
 
 /// Re-export of the [`rusqlite`](https://crates.io/crates/rusqlite) crate to avoid version conflicts.
@@ -19,25 +26,138 @@ pub use rusqlite;
 
 use crate::rusqlite::{Connection, Result};
 
-#[cfg(any(feature = "bsdiff4", feature = "bsdiffraw"))]
+mod args;
+mod error;
+pub use crate::error::CompressionError;
+
+#[cfg(any(
+    feature = "bsdiff4",
+    feature = "bsdiffraw",
+    feature = "fossil",
+    feature = "json",
+    feature = "rsync",
+    feature = "text",
+    feature = "vcdiff",
+    feature = "zstd"
+))]
 mod common_diff;
-#[cfg(any(feature = "bsdiff4", feature = "bsdiffraw"))]
-pub use crate::common_diff::Differ;
+#[cfg(any(
+    feature = "bsdiff4",
+    feature = "bsdiffraw",
+    feature = "fossil",
+    feature = "json",
+    feature = "rsync",
+    feature = "text",
+    feature = "vcdiff",
+    feature = "zstd"
+))]
+pub use crate::common_diff::{DiffLimitError, DiffLimits, Differ};
 
 #[cfg(any(feature = "brotli", feature = "bzip2", feature = "gzip"))]
 mod common;
 #[cfg(any(feature = "brotli", feature = "bzip2", feature = "gzip"))]
 pub use crate::common::Encoder;
 
+#[cfg(any(
+    feature = "brotli",
+    feature = "bzip2",
+    feature = "gzip",
+    feature = "zstd"
+))]
+mod ncd;
+#[cfg(any(
+    feature = "brotli",
+    feature = "bzip2",
+    feature = "gzip",
+    feature = "zstd"
+))]
+pub use crate::ncd::register_ncd_functions;
+
+#[cfg(any(feature = "brotli", feature = "bzip2", feature = "gzip"))]
+mod recompress;
+#[cfg(any(feature = "brotli", feature = "bzip2", feature = "gzip"))]
+pub use crate::recompress::register_recompress_functions;
+
+#[cfg(all(
+    any(feature = "brotli", feature = "bzip2", feature = "gzip"),
+    any(
+        feature = "bsdiff4",
+        feature = "bsdiffraw",
+        feature = "fossil",
+        feature = "json",
+        feature = "rsync",
+        feature = "text",
+        feature = "vcdiff",
+        feature = "zstd"
+    )
+))]
+mod compressed_diff;
+#[cfg(all(
+    any(feature = "brotli", feature = "bzip2", feature = "gzip"),
+    any(
+        feature = "bsdiff4",
+        feature = "bsdiffraw",
+        feature = "fossil",
+        feature = "json",
+        feature = "rsync",
+        feature = "text",
+        feature = "vcdiff",
+        feature = "zstd"
+    )
+))]
+pub use crate::compressed_diff::register_compressed_diff_functions;
+
 #[cfg(feature = "bsdiff4")]
 mod bsdiff4;
 #[cfg(feature = "bsdiff4")]
-pub use crate::bsdiff4::{register_bsdiff4_functions, Bsdiff4Differ};
+pub use crate::bsdiff4::{
+    register_bsdiff4_functions, Bsdiff4Control, Bsdiff4Differ, Bsdiff4Info, Bsdiff4Options,
+};
+
+#[cfg(feature = "bsdiff4")]
+mod bsdiff43;
+#[cfg(feature = "bsdiff4")]
+pub use crate::bsdiff43::{register_bsdiff43_functions, Bsdiff43Differ};
 
 #[cfg(feature = "bsdiffraw")]
 mod bsdiffraw;
 #[cfg(feature = "bsdiffraw")]
-pub use crate::bsdiffraw::{register_bsdiffraw_functions, BsdiffRawDiffer};
+pub use crate::bsdiffraw::{register_bsdiffraw_functions, BsdiffRawDiffer, BsdiffRawOptions};
+
+#[cfg(feature = "cdc")]
+mod cdc;
+#[cfg(feature = "cdc")]
+pub use crate::cdc::register_cdc_functions;
+
+#[cfg(feature = "fossil")]
+mod fossil;
+#[cfg(feature = "fossil")]
+pub use crate::fossil::{register_fossil_functions, FossilDiffer};
+
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "json")]
+pub use crate::json::{register_json_functions, JsonMergeDiffer, JsonPatchDiffer};
+
+#[cfg(feature = "rsync")]
+mod rsync;
+#[cfg(feature = "rsync")]
+pub use crate::rsync::{register_rsync_functions, RsyncDiffer};
+
+#[cfg(feature = "text")]
+mod text;
+#[cfg(feature = "text")]
+pub use crate::text::{register_text_functions, TextDiffer};
+
+#[cfg(feature = "vcdiff")]
+mod vcdiff;
+#[cfg(feature = "vcdiff")]
+pub use crate::vcdiff::{register_vcdiff_functions, VcdiffDiffer};
+
+#[cfg(feature = "zstd")]
+mod zstd;
+#[cfg(feature = "zstd")]
+pub use crate::zstd::{register_zstd_functions, ZstdDiffer};
 
 #[cfg(feature = "brotli")]
 mod brotli;
@@ -57,6 +177,10 @@ pub use crate::gzip::{register_gzip_functions, GzipEncoder};
 /// Register all compression functions for the given `SQLite` connection.
 /// This is a convenience function that calls all the `register_*_functions` functions.
 /// Features must be enabled for the corresponding functions to be registered.
+/// With any diff feature, it also registers the `diff_limits` SQL function, which returns the [`DiffLimits`]
+/// of the connection as JSON, e.g. `{"max_input_size":null,"time_budget_ms":null}`,
+/// and replaces them when called as `diff_limits(max_input_size, time_budget_ms)`, with `NULL` meaning no limit.
+/// Diff functions registered with the individual `register_*_functions` functions are not limited.
 ///
 /// # Example
 ///
@@ -82,16 +206,134 @@ pub use crate::gzip::{register_gzip_functions, GzipEncoder};
 /// # }
 /// ```
 pub fn register_compression_functions(conn: &Connection) -> Result<()> {
+    #[cfg(any(
+        feature = "bsdiff4",
+        feature = "bsdiffraw",
+        feature = "fossil",
+        feature = "json",
+        feature = "rsync",
+        feature = "text",
+        feature = "vcdiff",
+        feature = "zstd"
+    ))]
+    {
+        let limits = common_diff::ConnLimits::default();
+        common_diff::register_diff_limits_function(conn, &limits)?;
+        register_functions(conn, &limits)
+    }
+    #[cfg(not(any(
+        feature = "bsdiff4",
+        feature = "bsdiffraw",
+        feature = "fossil",
+        feature = "json",
+        feature = "rsync",
+        feature = "text",
+        feature = "vcdiff",
+        feature = "zstd"
+    )))]
+    register_functions(conn)
+}
+
+/// Same as [`register_compression_functions`], but the diff functions of this connection enforce the given limits,
+/// and the `diff_limits` SQL function is not registered, so queries cannot change them.
+///
+/// # Example
+///
+/// ```
+/// # use std::time::Duration;
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::{register_compression_functions_with_limits, DiffLimits};
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// let limits = DiffLimits { max_input_size: Some(1000), time_budget: Some(Duration::from_secs(5)) };
+/// register_compression_functions_with_limits(&db, limits)?;
+/// # if cfg!(feature = "bsdiff4") {
+/// let result = db.query_row("SELECT bsdiff4(zeroblob(2000), '')", [], |r| r.get::<_, Vec<u8>>(0));
+/// assert!(result.is_err());
+/// # }
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(any(
+    feature = "bsdiff4",
+    feature = "bsdiffraw",
+    feature = "fossil",
+    feature = "json",
+    feature = "rsync",
+    feature = "text",
+    feature = "vcdiff",
+    feature = "zstd"
+))]
+pub fn register_compression_functions_with_limits(
+    conn: &Connection,
+    limits: DiffLimits,
+) -> Result<()> {
+    register_functions(conn, &common_diff::ConnLimits::new(limits))
+}
+
+fn register_functions(
+    conn: &Connection,
+    #[cfg(any(
+        feature = "bsdiff4",
+        feature = "bsdiffraw",
+        feature = "fossil",
+        feature = "json",
+        feature = "rsync",
+        feature = "text",
+        feature = "vcdiff",
+        feature = "zstd"
+    ))]
+    limits: &common_diff::ConnLimits,
+) -> Result<()> {
     #[cfg(feature = "gzip")]
     register_gzip_functions(conn)?;
     #[cfg(feature = "brotli")]
     register_brotli_functions(conn)?;
     #[cfg(feature = "bzip2")]
     register_bzip2_functions(conn)?;
+    #[cfg(any(feature = "brotli", feature = "bzip2", feature = "gzip"))]
+    register_recompress_functions(conn)?;
+    #[cfg(any(
+        feature = "brotli",
+        feature = "bzip2",
+        feature = "gzip",
+        feature = "zstd"
+    ))]
+    register_ncd_functions(conn)?;
+    #[cfg(feature = "bsdiff4")]
+    bsdiff4::register_bsdiff4_functions_with_limits(conn, limits)?;
     #[cfg(feature = "bsdiff4")]
-    register_bsdiff4_functions(conn)?;
+    bsdiff43::register_bsdiff43_functions_with_limits(conn, limits)?;
+    #[cfg(feature = "json")]
+    json::register_json_functions_with_limits(conn, limits)?;
     #[cfg(feature = "bsdiffraw")]
-    register_bsdiffraw_functions(conn)?;
+    bsdiffraw::register_bsdiffraw_functions_with_limits(conn, limits)?;
+    #[cfg(feature = "fossil")]
+    fossil::register_fossil_functions_with_limits(conn, limits)?;
+    #[cfg(feature = "cdc")]
+    register_cdc_functions(conn)?;
+    #[cfg(feature = "rsync")]
+    rsync::register_rsync_functions_with_limits(conn, limits)?;
+    #[cfg(feature = "text")]
+    text::register_text_functions_with_limits(conn, limits)?;
+    #[cfg(feature = "vcdiff")]
+    vcdiff::register_vcdiff_functions_with_limits(conn, limits)?;
+    #[cfg(feature = "zstd")]
+    zstd::register_zstd_functions_with_limits(conn, limits)?;
+    #[cfg(all(
+        any(feature = "brotli", feature = "bzip2", feature = "gzip"),
+        any(
+            feature = "bsdiff4",
+            feature = "bsdiffraw",
+            feature = "fossil",
+            feature = "json",
+            feature = "rsync",
+            feature = "text",
+            feature = "vcdiff",
+            feature = "zstd"
+        )
+    ))]
+    compressed_diff::register_compressed_diff_functions_with_limits(conn, limits)?;
 
     Ok(())
 }
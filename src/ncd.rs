@@ -0,0 +1,211 @@
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+#[cfg(feature = "bzip2")]
+use bzip2::write::BzEncoder;
+#[cfg(feature = "gzip")]
+use flate2::write::GzEncoder;
+#[cfg(feature = "trace")]
+use log::trace;
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::Error::InvalidParameterCount;
+
+use crate::args::{get_bytes, unknown_algorithm};
+use crate::error::CompressionError;
+use crate::rusqlite::{Connection, Result};
+
+#[cfg(not(feature = "trace"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {};
+}
+
+/// Register the `ncd` SQL function with the given `SQLite` connection.
+/// The function takes two blobs or strings, the name of a compression algorithm, and an optional quality,
+/// and returns their [normalized compression distance](https://en.wikipedia.org/wiki/Normalized_compression_distance),
+/// `(C(ab) - min(C(a), C(b))) / max(C(a), C(b))`, where `C` is the compressed size.
+/// The result is close to 0 for near-duplicates, and close to 1 for unrelated data.
+/// The algorithm is `gzip`, `brotli`, `bzip2`, or `zstd` if the `zstd` feature is enabled.
+/// The first argument and the concatenation are compressed by the same compressor without copying the data:
+/// `C(a)` is the compressed size once `a` is flushed, and the compressor then continues with `b` to get `C(ab)`.
+/// Bzip2 ends its block on flush, and brotli cannot refer back across a flush at its highest qualities,
+/// so both compress `a` separately instead. Nothing is cached between calls.
+/// Gzip only looks back 32 KiB, and bzip2 compresses blocks of at most 900 kB independently, so for larger values
+/// the second one cannot refer to most of the first, and the distance approaches 1 even for identical values.
+/// Zstd and brotli have much larger windows, but they also shrink at low qualities.
+/// Brotli uses quality 11 by default, which is slow, so consider a lower one for large tables.
+/// If either value is `NULL`, the result is `NULL`.
+///
+/// # Example
+///
+/// ```
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::register_compression_functions;
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// register_compression_functions(&db)?;
+/// # if cfg!(feature = "gzip") {
+/// let sql = "SELECT ncd('the quick brown fox jumps over the lazy dog', 'the quick brown fox jumped over the lazy dog', 'gzip')
+///                 < ncd('the quick brown fox jumps over the lazy dog', 'lorem ipsum dolor sit amet, consectetur', 'gzip')";
+/// let result: bool = db.query_row(sql, [], |r| r.get(0))?;
+/// assert!(result);
+/// # }
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_ncd_functions(conn: &Connection) -> Result<()> {
+    trace!("Registering function ncd");
+    conn.create_scalar_function(
+        "ncd",
+        -1,
+        FunctionFlags::SQLITE_UTF8
+            | FunctionFlags::SQLITE_DETERMINISTIC
+            | FunctionFlags::SQLITE_DIRECTONLY,
+        ncd_fn,
+    )
+}
+
+fn ncd_fn(ctx: &Context) -> Result<Option<f64>> {
+    let param_count = ctx.len();
+    if !(3..=4).contains(&param_count) {
+        return Err(InvalidParameterCount(param_count, 3));
+    }
+    let Some(a) = get_bytes(ctx, 0)? else {
+        return Ok(None);
+    };
+    let Some(b) = get_bytes(ctx, 1)? else {
+        return Ok(None);
+    };
+    let algo = ctx.get::<String>(2)?;
+    let quality = if param_count == 4 {
+        Some(ctx.get::<u32>(3)?)
+    } else {
+        None
+    };
+
+    let [a_size, b_size, joined_size] = compressed_sizes(&algo, quality, a, b)?;
+    trace!("ncd: {algo} sizes {a_size}, {b_size}, {joined_size}");
+
+    let (min, max) = (a_size.min(b_size), a_size.max(b_size));
+    #[expect(clippy::cast_precision_loss)]
+    Ok(Some(joined_size.saturating_sub(min) as f64 / max as f64))
+}
+
+/// The compressed sizes of `a`, `b`, and their concatenation.
+fn compressed_sizes(algo: &str, quality: Option<u32>, a: &[u8], b: &[u8]) -> Result<[u64; 3]> {
+    #[cfg(feature = "gzip")]
+    if algo.eq_ignore_ascii_case("gzip") {
+        let level = crate::gzip::compression(quality)?;
+        return sizes(
+            "gzip",
+            a,
+            b,
+            true,
+            |w| Ok(GzEncoder::new(w, level)),
+            |e| e.finish().map(drop),
+        );
+    }
+    #[cfg(feature = "brotli")]
+    if algo.eq_ignore_ascii_case("brotli") {
+        return sizes(
+            "brotli",
+            a,
+            b,
+            false,
+            |w| Ok(crate::brotli::compressor(w, quality)),
+            |e| {
+                e.into_inner();
+                Ok(())
+            },
+        );
+    }
+    #[cfg(feature = "bzip2")]
+    if algo.eq_ignore_ascii_case("bzip2") {
+        let level = crate::bzip2::compression(quality)?;
+        return sizes(
+            "bzip2",
+            a,
+            b,
+            false,
+            |w| Ok(BzEncoder::new(w, level)),
+            |e| e.finish().map(drop),
+        );
+    }
+    #[cfg(feature = "zstd")]
+    if algo.eq_ignore_ascii_case("zstd") {
+        let range = zstd::compression_level_range();
+        let level = match quality.map(i32::try_from) {
+            None => zstd::DEFAULT_COMPRESSION_LEVEL,
+            Some(Ok(level)) if range.contains(&level) => level,
+            Some(_) => {
+                return Err(CompressionError::invalid_level(
+                    "zstd",
+                    format!(
+                        "The optional fourth argument to ncd() must be between {} and {} for zstd",
+                        range.start(),
+                        range.end()
+                    ),
+                )
+                .into())
+            }
+        };
+        return sizes(
+            "zstd",
+            a,
+            b,
+            true,
+            |w| zstd::stream::write::Encoder::new(w, level),
+            |e| e.finish().map(drop),
+        );
+    }
+    Err(unknown_algorithm(algo))
+}
+
+/// Compress `b`, and `a` followed by `b` with a single compressor created by `new` and finished by `finish`.
+/// If `flush` is set, the size of `a` is taken by flushing that compressor after `a`,
+/// otherwise `a` is compressed separately.
+fn sizes<E: Write>(
+    algorithm: &'static str,
+    a: &[u8],
+    b: &[u8],
+    flush: bool,
+    new: impl Fn(ByteCounter) -> io::Result<E>,
+    finish: impl Fn(E) -> io::Result<()>,
+) -> Result<[u64; 3]> {
+    let compress = |first: &[u8], second: Option<&[u8]>| -> io::Result<(u64, u64)> {
+        let counter = ByteCounter::default();
+        let mut encoder = new(counter.clone())?;
+        encoder.write_all(first)?;
+        if flush {
+            encoder.flush()?;
+        }
+        let first_size = counter.0.get();
+        if let Some(second) = second {
+            encoder.write_all(second)?;
+        }
+        finish(encoder)?;
+        Ok((first_size, counter.0.get()))
+    };
+    let result = (|| {
+        let (a_size, joined_size) = compress(a, Some(b))?;
+        let a_size = if flush { a_size } else { compress(a, None)?.1 };
+        let b_size = compress(b, None)?.1;
+        Ok([a_size, b_size, joined_size])
+    })();
+    result.map_err(|e| CompressionError::io(algorithm, e).into())
+}
+
+/// A writer that discards the data, and only counts its size. Clones share the count.
+#[derive(Clone, Default)]
+struct ByteCounter(Rc<Cell<u64>>);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.set(self.0.get() + buf.len() as u64);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+#[cfg(feature = "trace")]
+use log::trace;
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::types::{Type, ValueRef};
+use rusqlite::Error::{InvalidFunctionParameterType, InvalidParameterCount};
+
+use crate::args::unknown_algorithm;
+use crate::common::{dispatch_encoder, Encoder as _};
+use crate::rusqlite::{Connection, Result};
+
+#[cfg(not(feature = "trace"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {};
+}
+
+/// Register the `recompress` SQL function with the given `SQLite` connection.
+/// The function takes a compressed blob, the name of the algorithm it was compressed with,
+/// the name of the algorithm to compress it with, and an optional quality for the new algorithm.
+/// The decompressed data is streamed directly into the new encoder without creating an intermediate `SQLite` value.
+/// If the data is `NULL`, the result is `NULL`.
+///
+/// # Example
+///
+/// ```
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::register_compression_functions;
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// register_compression_functions(&db)?;
+/// # if cfg!(all(feature = "gzip", feature = "brotli")) {
+/// let result: String = db.query_row("SELECT hex(recompress(gzip('hello'), 'gzip', 'brotli'))", [], |r| r.get(0))?;
+/// assert_eq!(&result, "0B028068656C6C6F03");
+/// let result: String = db.query_row("SELECT CAST(gzip_decode(recompress(brotli('hello'), 'brotli', 'gzip', 9)) AS TEXT)", [], |r| r.get(0))?;
+/// assert_eq!(&result, "hello");
+/// # }
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_recompress_functions(conn: &Connection) -> Result<()> {
+    trace!("Registering function recompress");
+    conn.create_scalar_function(
+        "recompress",
+        -1,
+        FunctionFlags::SQLITE_UTF8
+            | FunctionFlags::SQLITE_DETERMINISTIC
+            | FunctionFlags::SQLITE_DIRECTONLY,
+        recompress_fn,
+    )
+}
+
+fn recompress_fn(ctx: &Context) -> Result<Option<Vec<u8>>> {
+    let param_count = ctx.len();
+    if !(3..=4).contains(&param_count) {
+        return Err(InvalidParameterCount(param_count, 3));
+    }
+    let from = ctx.get::<String>(1)?;
+    let to = ctx.get::<String>(2)?;
+    let quality = if param_count == 4 {
+        Some(ctx.get::<u32>(3)?)
+    } else {
+        None
+    };
+
+    match ctx.get_raw(0) {
+        ValueRef::Blob(val) => {
+            trace!("recompress: recompressing blob {val:?} from {from} to {to}");
+            Ok(Some(recompress(val, &from, &to, quality)?))
+        }
+        ValueRef::Null => {
+            trace!("recompress: ignoring NULL");
+            Ok(None)
+        }
+        ValueRef::Text(_) => Err(InvalidFunctionParameterType(0, Type::Text)),
+        ValueRef::Integer(_) => Err(InvalidFunctionParameterType(0, Type::Integer)),
+        ValueRef::Real(_) => Err(InvalidFunctionParameterType(0, Type::Real)),
+    }
+}
+
+fn recompress(data: &[u8], from: &str, to: &str, quality: Option<u32>) -> Result<Vec<u8>> {
+    dispatch_encoder!(from, |Src| {
+        dispatch_encoder!(to, |Dst| {
+            Dst::encode_stream(&mut Src::decoder(data), Vec::new(), quality)
+        })
+        .ok_or_else(|| unknown_algorithm(to))?
+    })
+    .ok_or_else(|| unknown_algorithm(from))?
+    .map_err(Into::into)
+}
@@ -0,0 +1,257 @@
+use std::io;
+
+use fast_rsync::{apply_limited, diff, Signature, SignatureOptions};
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::types::ValueRef;
+use rusqlite::Error::{InvalidFunctionParameterType, UserFunctionError};
+
+use crate::args::get_bytes;
+use crate::common_diff::{register_differ, ConnLimits, Differ};
+use crate::error::CompressionError;
+use crate::rusqlite::{Connection, Result};
+
+/// Register the `rsync_signature`, `rsync_delta`, and `rsync_patch` SQL functions with the given `SQLite` connection.
+/// These use the [librsync](https://librsync.github.io/) format, so the results can be exchanged with `rdiff`
+/// and other librsync-based tools.
+/// The `rsync_signature` function takes a blob and an optional block size (2048 by default),
+/// and returns its MD4 signature, same as `rdiff signature --hash=md4`.
+/// The `rsync_delta` function takes a signature and the new blob, and returns the delta that converts
+/// the old blob into the new one, without needing the old blob itself.
+/// The `rsync_patch` function applies the delta to the old blob. A delta can copy the same block of the old blob
+/// any number of times, so to keep a small delta from allocating without bound, the result may be at most
+/// 256 times the size of the old blob, plus the size of the delta.
+/// The `rsync_diff` function is a shortcut for `rsync_delta(rsync_signature(source), target)`.
+/// The `rsync_patch_test` function checks that a delta is well-formed, and if the old blob is given as the first
+/// argument, that the delta can be applied to it.
+/// The `rsync_patch_chain` aggregate function applies a series of deltas in order to the base blob from the first row,
+/// e.g. `rsync_patch_chain(base, delta ORDER BY version)`.
+/// The arguments can be either a string or a blob.
+/// If any of the arguments are `NULL`, the result is `NULL`.
+///
+/// # Example
+///
+/// ```
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::register_rsync_functions;
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// register_rsync_functions(&db)?;
+/// let result: String = db.query_row("SELECT hex(rsync_signature('013479', 4))", [], |r| r.get(0))?;
+/// assert_eq!(result, "727301360000000400000010032301449F6254A89E415A025BA93CFC0DEE0B3D010400AECC63CD0C07B3885E0BFB7E08FB1874A5");
+/// let sql = "SELECT rsync_patch('013479', rsync_delta(rsync_signature('013479'), '0134789'))";
+/// let result: Vec<u8> = db.query_row(sql, [], |r| r.get(0))?;
+/// assert_eq!(result, b"0134789");
+/// let result: bool = db.query_row("SELECT rsync_patch_test('013479', rsync_diff('013479', '0134789'))", [], |r| r.get(0))?;
+/// assert!(result);
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_rsync_functions(conn: &Connection) -> Result<()> {
+    register_rsync_functions_with_limits(conn, &ConnLimits::default())
+}
+
+pub(crate) fn register_rsync_functions_with_limits(
+    conn: &Connection,
+    limits: &ConnLimits,
+) -> Result<()> {
+    // FunctionFlags derive Copy trait only in v0.31+, but we support v0.30+
+    macro_rules! flags {
+        () => {
+            FunctionFlags::SQLITE_UTF8
+                | FunctionFlags::SQLITE_DETERMINISTIC
+                | FunctionFlags::SQLITE_DIRECTONLY
+        };
+    }
+
+    register_differ::<RsyncDiffer>(conn, limits)?;
+    conn.create_scalar_function("rsync_signature", 1, flags!(), signature_fn)?;
+    conn.create_scalar_function("rsync_signature", 2, flags!(), signature_fn)?;
+    conn.create_scalar_function("rsync_delta", 2, flags!(), delta_fn)
+}
+
+fn signature_fn(ctx: &Context) -> Result<Option<Vec<u8>>> {
+    let Some(data) = get_bytes(ctx, 0)? else {
+        return Ok(None);
+    };
+    let block_size = if ctx.len() > 1 {
+        match ctx.get_raw(1) {
+            ValueRef::Null => DEFAULT_BLOCK_SIZE,
+            ValueRef::Integer(size) => match u32::try_from(size) {
+                Ok(size) if size > 0 => size,
+                _ => {
+                    return Err(UserFunctionError(
+                        format!(
+                            "The optional second argument to rsync_signature() must be between 1 and {}",
+                            u32::MAX
+                        )
+                        .into(),
+                    ))
+                }
+            },
+            v => return Err(InvalidFunctionParameterType(1, v.data_type())),
+        }
+    } else {
+        DEFAULT_BLOCK_SIZE
+    };
+    Ok(Some(RsyncDiffer::signature(data, block_size)))
+}
+
+fn delta_fn(ctx: &Context) -> Result<Option<Vec<u8>>> {
+    let Some(signature) = get_bytes(ctx, 0)? else {
+        return Ok(None);
+    };
+    let Some(target) = get_bytes(ctx, 1)? else {
+        return Ok(None);
+    };
+    Ok(Some(RsyncDiffer::delta(signature, target)?))
+}
+
+/// Same default block size as librsync.
+const DEFAULT_BLOCK_SIZE: u32 = 2048;
+/// Length of the MD4 hash, which is stored in full in the signature.
+const STRONG_SUM_SIZE: u32 = 16;
+
+const DELTA_MAGIC: [u8; 4] = 0x7273_0236_u32.to_be_bytes();
+const OP_END: u8 = 0x00;
+const OP_LITERAL_1: u8 = 0x01;
+const OP_LITERAL_64: u8 = 0x40;
+const OP_LITERAL_N1: u8 = 0x41;
+const OP_LITERAL_N8: u8 = 0x44;
+const OP_COPY_N1_N1: u8 = 0x45;
+const OP_COPY_N8_N8: u8 = 0x54;
+
+/// The result of a patch may be at most this many times the size of the source, plus the size of the delta.
+const MAX_GROWTH: usize = 256;
+
+/// The maximum size of the result of applying `patch` to `source`, see [`MAX_GROWTH`].
+fn output_limit(source: &[u8], patch: &[u8]) -> usize {
+    source
+        .len()
+        .saturating_mul(MAX_GROWTH)
+        .saturating_add(patch.len())
+}
+
+pub struct RsyncDiffer;
+
+impl RsyncDiffer {
+    /// Compute the librsync MD4 signature of `data`, using blocks of `block_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `block_size` is zero.
+    #[must_use]
+    pub fn signature(data: &[u8], block_size: u32) -> Vec<u8> {
+        let options = SignatureOptions {
+            block_size,
+            crypto_hash_size: STRONG_SUM_SIZE,
+        };
+        Signature::calculate(data, options).into_serialized()
+    }
+
+    /// Compute the librsync delta that converts the blob described by `signature` into `target`.
+    /// Only MD4 signatures are supported, e.g. the ones created by `rdiff signature --hash=md4`.
+    pub fn delta(signature: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let signature = Signature::deserialize(signature.to_vec())
+            .map_err(|e| CompressionError::corrupt("rsync", e))?;
+        let mut delta = Vec::new();
+        diff(&signature.index(), target, &mut delta)
+            .map_err(|e| CompressionError::corrupt("rsync", e))?;
+        Ok(delta)
+    }
+}
+
+impl Differ for RsyncDiffer {
+    type Options = ();
+
+    fn algorithm() -> &'static str {
+        "rsync"
+    }
+
+    fn diff_name() -> &'static str {
+        "rsync_diff"
+    }
+
+    fn patch_name() -> &'static str {
+        "rsync_patch"
+    }
+
+    fn test_name() -> &'static str {
+        "rsync_patch_test"
+    }
+
+    fn chain_name() -> &'static str {
+        "rsync_patch_chain"
+    }
+
+    fn prev_name() -> &'static str {
+        "rsync_diff_prev"
+    }
+
+    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        Self::delta(&Self::signature(source, DEFAULT_BLOCK_SIZE), target)
+    }
+
+    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let mut target = Vec::new();
+        Self::patch_into(source, patch, &mut target)?;
+        Ok(target)
+    }
+
+    fn patch_into(
+        source: &[u8],
+        patch: &[u8],
+        target: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        target.clear();
+        apply_limited(source, patch, target, output_limit(source, patch))
+            .map_err(|e| CompressionError::corrupt("rsync", e))
+    }
+
+    fn test(patch: &[u8], source: Option<&[u8]>) -> bool {
+        match source {
+            Some(source) => {
+                apply_limited(source, patch, &mut io::sink(), output_limit(source, patch)).is_ok()
+            }
+            None => is_valid_delta(patch),
+        }
+    }
+}
+
+/// Walk all commands of a librsync delta without applying it.
+fn is_valid_delta(delta: &[u8]) -> bool {
+    fn take<'a>(delta: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+        let (head, tail) = delta.split_at_checked(len)?;
+        *delta = tail;
+        Some(head)
+    }
+
+    fn take_int(delta: &mut &[u8], len: usize) -> Option<u64> {
+        let mut buf = [0; 8];
+        buf[8 - len..].copy_from_slice(take(delta, len)?);
+        Some(u64::from_be_bytes(buf))
+    }
+
+    let Some(mut delta) = delta.strip_prefix(&DELTA_MAGIC) else {
+        return false;
+    };
+    while let Some(cmd) = take(&mut delta, 1).map(|v| v[0]) {
+        let valid = match cmd {
+            OP_END => return delta.is_empty(),
+            OP_LITERAL_1..=OP_LITERAL_64 => take(&mut delta, usize::from(cmd)).is_some(),
+            OP_LITERAL_N1..=OP_LITERAL_N8 => take_int(&mut delta, 1 << (cmd - OP_LITERAL_N1))
+                .and_then(|len| usize::try_from(len).ok())
+                .and_then(|len| take(&mut delta, len))
+                .is_some(),
+            OP_COPY_N1_N1..=OP_COPY_N8_N8 => {
+                let mode = cmd - OP_COPY_N1_N1;
+                take_int(&mut delta, 1 << (mode / 4)).is_some()
+                    && take_int(&mut delta, 1 << (mode % 4)).is_some_and(|len| len > 0)
+            }
+            _ => false,
+        };
+        if !valid {
+            return false;
+        }
+    }
+    false
+}
@@ -0,0 +1,572 @@
+use std::fmt::Write as _;
+use std::ops::Range;
+
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::Error::InvalidFunctionParameterType;
+use similar::algorithms::{diff_slices_deadline, Capture, Replace};
+use similar::{group_diff_ops, Algorithm, DiffOp, DiffTag};
+
+use crate::args::get_bytes;
+use crate::common_diff::{diff_deadline, register_differ, to_value, ConnLimits, Differ};
+use crate::error::CompressionError;
+use crate::rusqlite::{Connection, Result};
+
+/// Register the `text_diff` and `text_patch` SQL functions with the given `SQLite` connection.
+/// The `text_diff` function takes two arguments, and returns a line-based
+/// [unified diff](https://www.gnu.org/software/diffutils/manual/html_node/Unified-Format.html) between them,
+/// same as `diff -u` without the file name header. The optional third argument is the number of context lines
+/// around each change, and defaults to 3.
+/// The `text_patch` function applies a unified diff, e.g. one created by `diff -u` or `git diff`, to the text.
+/// The diff, patch, and chain functions return text, or a blob if the result is not valid UTF-8.
+/// Like the `patch` tool, it tolerates hunks that moved to a different line (offset), and if needed,
+/// ignores up to two lines of context at the beginning and the end of a hunk (fuzz).
+/// The `text_patch_test` function checks that a diff is well-formed, and if the text is given as the first argument,
+/// that the diff can be applied to it.
+/// The `text_patch_chain` aggregate function applies a series of diffs in order to the base text from the first row,
+/// e.g. `text_patch_chain(base, diff ORDER BY version)`.
+/// The `text_merge` function takes the `base` text and two texts derived from it, `ours` and `theirs`,
+/// and merges the changes of both, same as `diff3 -m` and `git merge-file --diff3`. Lines changed differently
+/// by both sides are kept as a conflict, surrounded by `<<<<<<< ours`, `||||||| base`, `=======`,
+/// and `>>>>>>> theirs` marker lines. The merged result is text, or a blob if it is not valid UTF-8.
+/// The `text_merge_conflicts` function takes the same arguments,
+/// and returns a JSON array of the conflicts, each one with the base `line` number where it starts,
+/// and the conflicting `base`, `ours`, and `theirs` text. An empty array means the merge is clean.
+/// The arguments can be either a string or a blob.
+/// If any of the arguments are `NULL`, the result is `NULL`.
+///
+/// # Example
+///
+/// ```
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::register_text_functions;
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// register_text_functions(&db)?;
+/// let result: String = db.query_row("SELECT text_diff('a\nb\nc\n', 'a\nB\nc\n')", [], |r| r.get(0))?;
+/// assert_eq!(result, "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+/// let result: String = db.query_row("SELECT text_patch('x\na\nb\nc\n', text_diff('a\nb\nc\n', 'a\nB\nc\n'))", [], |r| r.get(0))?;
+/// assert_eq!(result, "x\na\nB\nc\n");
+/// let result: bool = db.query_row("SELECT text_patch_test('a\nb\nc\n', text_diff('a\nb\nc\n', 'a\nB\nc\n', 0))", [], |r| r.get(0))?;
+/// assert!(result);
+/// let result: String = db.query_row("SELECT text_merge('a\nb\nc\n', 'A\nb\nc\n', 'a\nb\nC\n')", [], |r| r.get(0))?;
+/// assert_eq!(result, "A\nb\nC\n");
+/// let result: String = db.query_row("SELECT text_merge_conflicts('a\nb\nc\n', 'a\nx\nc\n', 'a\ny\nc\n')", [], |r| r.get(0))?;
+/// assert_eq!(result, r#"[{"line":2,"base":"b\n","ours":"x\n","theirs":"y\n"}]"#);
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_text_functions(conn: &Connection) -> Result<()> {
+    register_text_functions_with_limits(conn, &ConnLimits::default())
+}
+
+pub(crate) fn register_text_functions_with_limits(
+    conn: &Connection,
+    limits: &ConnLimits,
+) -> Result<()> {
+    // FunctionFlags derive Copy trait only in v0.31+, but we support v0.30+
+    macro_rules! flags {
+        () => {
+            FunctionFlags::SQLITE_UTF8
+                | FunctionFlags::SQLITE_DETERMINISTIC
+                | FunctionFlags::SQLITE_DIRECTONLY
+        };
+    }
+
+    register_differ::<TextDiffer>(conn, limits)?;
+    conn.create_scalar_function("text_merge", 3, flags!(), merge_fn)?;
+    conn.create_scalar_function("text_merge_conflicts", 3, flags!(), merge_conflicts_fn)
+}
+
+fn merge_args<'a>(ctx: &'a Context) -> Result<Option<[&'a [u8]; 3]>> {
+    let (Some(base), Some(ours), Some(theirs)) =
+        (get_bytes(ctx, 0)?, get_bytes(ctx, 1)?, get_bytes(ctx, 2)?)
+    else {
+        return Ok(None);
+    };
+    Ok(Some([base, ours, theirs]))
+}
+
+fn merge_fn(ctx: &Context) -> Result<Option<Value>> {
+    Ok(merge_args(ctx)?
+        .map(|[base, ours, theirs]| to_value::<TextDiffer>(TextDiffer::merge(base, ours, theirs))))
+}
+
+fn merge_conflicts_fn(ctx: &Context) -> Result<Option<String>> {
+    let Some([base, ours, theirs]) = merge_args(ctx)? else {
+        return Ok(None);
+    };
+    let (base, ours, theirs) = (lines(base), lines(ours), lines(theirs));
+    let conflicts: Vec<_> = merge3(&base, &ours, &theirs)
+        .into_iter()
+        .filter_map(|region| match region {
+            Merged::Clean(_) => None,
+            Merged::Conflict {
+                line,
+                base,
+                ours,
+                theirs,
+            } => Some(format!(
+                r#"{{"line":{},"base":{},"ours":{},"theirs":{}}}"#,
+                line + 1,
+                json_string(base),
+                json_string(ours),
+                json_string(theirs)
+            )),
+        })
+        .collect();
+    Ok(Some(format!("[{}]", conflicts.join(","))))
+}
+
+/// Same default as `diff -u`.
+const DEFAULT_CONTEXT: usize = 3;
+/// Same default as the `patch` tool.
+const MAX_FUZZ: usize = 2;
+const NO_NEWLINE: &[u8] = b"\\ No newline at end of file\n";
+
+pub struct TextDiffer;
+
+impl TextDiffer {
+    /// Create a unified diff between `source` and `target` with the given number of context lines.
+    #[must_use]
+    pub fn diff_context(source: &[u8], target: &[u8], context: usize) -> Vec<u8> {
+        let old = lines(source);
+        let new = lines(target);
+        let mut result = Vec::new();
+        for group in group_diff_ops(diff_ops(&old, &new), context) {
+            let (Some(first), Some(last)) = (group.first(), group.last()) else {
+                continue;
+            };
+            let old_range = first.old_range().start..last.old_range().end;
+            let new_range = first.new_range().start..last.new_range().end;
+            result.extend_from_slice(
+                format!(
+                    "@@ -{} +{} @@\n",
+                    hunk_range(old_range.start, old_range.len()),
+                    hunk_range(new_range.start, new_range.len())
+                )
+                .as_bytes(),
+            );
+            for op in group {
+                let (tag, old_range, new_range) = op.as_tag_tuple();
+                match tag {
+                    DiffTag::Equal => write_lines(&mut result, b' ', &old[old_range]),
+                    DiffTag::Delete => write_lines(&mut result, b'-', &old[old_range]),
+                    DiffTag::Insert => write_lines(&mut result, b'+', &new[new_range]),
+                    DiffTag::Replace => {
+                        write_lines(&mut result, b'-', &old[old_range]);
+                        write_lines(&mut result, b'+', &new[new_range]);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Merge the changes made to `base` by `ours` and `theirs`, keeping the conflicting changes
+    /// between `diff3`-style conflict marker lines.
+    #[must_use]
+    pub fn merge(base: &[u8], ours: &[u8], theirs: &[u8]) -> Vec<u8> {
+        fn write_section(result: &mut Vec<u8>, marker: &[u8], lines: &[&[u8]]) {
+            result.extend_from_slice(marker);
+            for line in lines {
+                result.extend_from_slice(line);
+            }
+            if !result.ends_with(b"\n") {
+                result.push(b'\n');
+            }
+        }
+
+        let (base, ours, theirs) = (lines(base), lines(ours), lines(theirs));
+        let mut result = Vec::new();
+        for region in merge3(&base, &ours, &theirs) {
+            match region {
+                Merged::Clean(lines) => {
+                    for line in lines {
+                        result.extend_from_slice(line);
+                    }
+                }
+                Merged::Conflict {
+                    base, ours, theirs, ..
+                } => {
+                    write_section(&mut result, b"<<<<<<< ours\n", ours);
+                    write_section(&mut result, b"||||||| base\n", base);
+                    write_section(&mut result, b"=======\n", theirs);
+                    result.extend_from_slice(b">>>>>>> theirs\n");
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Differ for TextDiffer {
+    type Options = usize;
+
+    fn algorithm() -> &'static str {
+        "text"
+    }
+
+    fn diff_name() -> &'static str {
+        "text_diff"
+    }
+
+    fn patch_name() -> &'static str {
+        "text_patch"
+    }
+
+    fn test_name() -> &'static str {
+        "text_patch_test"
+    }
+
+    fn chain_name() -> &'static str {
+        "text_patch_chain"
+    }
+
+    fn prev_name() -> &'static str {
+        "text_diff_prev"
+    }
+
+    const DIFF_OPTIONS: bool = true;
+    const TEXT_OUTPUT: bool = true;
+
+    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        Ok(Self::diff_context(source, target, DEFAULT_CONTEXT))
+    }
+
+    fn parse_options(value: ValueRef<'_>) -> Result<usize> {
+        let ValueRef::Integer(context) = value else {
+            return Err(InvalidFunctionParameterType(2, value.data_type()));
+        };
+        usize::try_from(context).map_err(|_| {
+            CompressionError::invalid_option(
+                "text",
+                "The optional third argument to text_diff() must be a non-negative number of lines",
+            )
+            .into()
+        })
+    }
+
+    fn diff_with_options(
+        source: &[u8],
+        target: &[u8],
+        context: &usize,
+    ) -> Result<Vec<u8>, CompressionError> {
+        Ok(Self::diff_context(source, target, *context))
+    }
+
+    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let hunks = parse(patch).map_err(|e| CompressionError::corrupt("text", e))?;
+        apply(source, &hunks).map_err(|e| CompressionError::corrupt("text", e))
+    }
+
+    fn test(patch: &[u8], source: Option<&[u8]>) -> bool {
+        match (parse(patch), source) {
+            (Ok(hunks), Some(source)) => apply(source, &hunks).is_ok(),
+            (Ok(_), None) => true,
+            (Err(_), _) => false,
+        }
+    }
+}
+
+type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Split the text into lines, keeping the line endings.
+fn lines(text: &[u8]) -> Vec<&[u8]> {
+    text.split_inclusive(|&c| c == b'\n').collect()
+}
+
+/// Compute the line diff operations. This avoids `similar::capture_diff_slices`, which compacts the operations
+/// and may report a wrong target position for a deletion at the start of a hunk.
+/// Past the deadline of the current diff, the result is still valid, but not minimal.
+fn diff_ops(old: &[&[u8]], new: &[&[u8]]) -> Vec<DiffOp> {
+    let mut hook = Replace::new(Capture::new());
+    diff_slices_deadline(Algorithm::Myers, &mut hook, old, new, diff_deadline())
+        .unwrap_or_else(|e| match e {});
+    hook.into_inner().into_ops()
+}
+
+/// Format a hunk range the same way as `diff -u`, where an empty range refers to the line before it.
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+fn write_lines(result: &mut Vec<u8>, prefix: u8, lines: &[&[u8]]) {
+    for line in lines {
+        result.push(prefix);
+        result.extend_from_slice(line);
+        if !line.ends_with(b"\n") {
+            result.push(b'\n');
+            result.extend_from_slice(NO_NEWLINE);
+        }
+    }
+}
+
+/// A single hunk of a unified diff, with the lines it expects in the source and the lines that replace them.
+struct Hunk<'a> {
+    /// The first line of the hunk in the source, counting from 1, as written in the hunk header.
+    old_start: usize,
+    old: Vec<&'a [u8]>,
+    new: Vec<&'a [u8]>,
+    /// Number of unchanged context lines at the beginning of the hunk.
+    leading: usize,
+    /// Number of unchanged context lines at the end of the hunk.
+    trailing: usize,
+}
+
+impl Hunk<'_> {
+    /// Index of the source line where the hunk is expected to start.
+    fn position(&self) -> usize {
+        // An empty range refers to the line before the insertion point
+        if self.old.is_empty() {
+            self.old_start
+        } else {
+            self.old_start.saturating_sub(1)
+        }
+    }
+}
+
+/// Parse a unified diff. Any lines before the first hunk, like the file name header, are ignored.
+fn parse(patch: &[u8]) -> Result<Vec<Hunk<'_>>, Error> {
+    let invalid = || -> Error { "not a valid unified diff".into() };
+    let mut lines = lines(patch).into_iter().peekable();
+    while lines.next_if(|line| !line.starts_with(b"@@ ")).is_some() {}
+    if lines.peek().is_none() && !patch.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut hunks = Vec::new();
+    while let Some(header) = lines.next() {
+        let (old_start, mut old_len, mut new_len) = parse_header(header).ok_or_else(invalid)?;
+        let mut hunk = Hunk {
+            old_start,
+            old: Vec::with_capacity(old_len),
+            new: Vec::with_capacity(new_len),
+            leading: 0,
+            trailing: 0,
+        };
+        let mut changed = false;
+        while old_len > 0 || new_len > 0 {
+            let line = lines.next().ok_or_else(invalid)?;
+            // Some editors strip the trailing space of empty context lines
+            let (prefix, content) = match line.split_first() {
+                Some((b'\n', _)) => (b' ', line),
+                Some((prefix, content)) => (*prefix, content),
+                None => return Err(invalid()),
+            };
+            match prefix {
+                b' ' if old_len > 0 && new_len > 0 => {
+                    old_len -= 1;
+                    new_len -= 1;
+                    hunk.old.push(content);
+                    hunk.new.push(content);
+                    if changed {
+                        hunk.trailing += 1;
+                    } else {
+                        hunk.leading += 1;
+                    }
+                }
+                b'-' if old_len > 0 => {
+                    old_len -= 1;
+                    hunk.old.push(content);
+                    changed = true;
+                    hunk.trailing = 0;
+                }
+                b'+' if new_len > 0 => {
+                    new_len -= 1;
+                    hunk.new.push(content);
+                    changed = true;
+                    hunk.trailing = 0;
+                }
+                _ => return Err(invalid()),
+            }
+            if lines.next_if(|line| line.starts_with(b"\\")).is_some() {
+                let vec = match prefix {
+                    b'-' => vec![&mut hunk.old],
+                    b'+' => vec![&mut hunk.new],
+                    _ => vec![&mut hunk.old, &mut hunk.new],
+                };
+                for lines in vec {
+                    if let Some(last) = lines.last_mut() {
+                        *last = last.strip_suffix(b"\n").unwrap_or(last);
+                    }
+                }
+            }
+        }
+        hunks.push(hunk);
+        if lines.peek().is_some_and(|line| !line.starts_with(b"@@ ")) {
+            return Err(invalid());
+        }
+    }
+    Ok(hunks)
+}
+
+/// Parse a hunk header like `@@ -1,3 +1,4 @@`, returning the old start line, and the old and new line counts.
+fn parse_header(header: &[u8]) -> Option<(usize, usize, usize)> {
+    fn range(range: &str, prefix: char) -> Option<(usize, usize)> {
+        let range = range.strip_prefix(prefix)?;
+        match range.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    }
+
+    let header = std::str::from_utf8(header).ok()?.strip_prefix("@@ ")?;
+    let (ranges, _) = header.split_once(" @@")?;
+    let (old, new) = ranges.split_once(' ')?;
+    let (old_start, old_len) = range(old, '-')?;
+    let (_, new_len) = range(new, '+')?;
+    Some((old_start, old_len, new_len))
+}
+
+/// Apply the hunks in order, looking for the closest matching position of each one,
+/// and dropping some of its context lines if it does not match exactly.
+#[expect(clippy::cast_possible_wrap)]
+fn apply(source: &[u8], hunks: &[Hunk]) -> Result<Vec<u8>, Error> {
+    let source = lines(source);
+    let mut result = Vec::with_capacity(source.len());
+    let mut done = 0;
+    let mut offset = 0_isize;
+    for (idx, hunk) in hunks.iter().enumerate() {
+        let (pos, head, tail) = (0..=MAX_FUZZ)
+            .find_map(|fuzz| {
+                let head = fuzz.min(hunk.leading);
+                let tail = fuzz.min(hunk.trailing).min(hunk.old.len() - head);
+                let old = &hunk.old[head..hunk.old.len() - tail];
+                let expected = (hunk.position() + head).saturating_add_signed(offset);
+                find(&source, old, done, expected).map(|pos| (pos, head, tail))
+            })
+            .ok_or_else(|| format!("text_patch() failed to apply hunk #{}", idx + 1))?;
+        for line in &source[done..pos] {
+            result.extend_from_slice(line);
+        }
+        for line in &hunk.new[head..hunk.new.len() - tail] {
+            result.extend_from_slice(line);
+        }
+        done = pos + hunk.old.len() - head - tail;
+        offset = pos as isize - (hunk.position() + head) as isize;
+    }
+    for line in &source[done..] {
+        result.extend_from_slice(line);
+    }
+    Ok(result)
+}
+
+/// Find the position of `lines` in the source closest to the `expected` one, but not before `start`.
+fn find(source: &[&[u8]], lines: &[&[u8]], start: usize, expected: usize) -> Option<usize> {
+    let last = source.len().checked_sub(lines.len())?;
+    if start > last {
+        return None;
+    }
+    let expected = expected.clamp(start, last);
+    let matches = |pos: usize| source[pos..pos + lines.len()] == *lines;
+    (0..=(last - start)).find_map(|distance| {
+        [
+            expected.checked_add(distance),
+            expected.checked_sub(distance),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|&pos| (start..=last).contains(&pos) && matches(pos))
+    })
+}
+
+/// A region of a three-way merge result.
+enum Merged<'a> {
+    /// Lines that are either unchanged, changed by one side only, or changed the same way by both sides.
+    Clean(&'a [&'a [u8]]),
+    /// Lines changed differently by both sides, starting at the given base line index.
+    Conflict {
+        line: usize,
+        base: &'a [&'a [u8]],
+        ours: &'a [&'a [u8]],
+        theirs: &'a [&'a [u8]],
+    },
+}
+
+/// Merge the changes of both sides, grouping the ones that overlap or touch in the base into a single region.
+fn merge3<'a>(
+    base: &'a [&'a [u8]],
+    ours: &'a [&'a [u8]],
+    theirs: &'a [&'a [u8]],
+) -> Vec<Merged<'a>> {
+    // Each change is the replaced base range, the replacement range in its side, and whether it is from theirs
+    let side_changes =
+        |side: &[&[u8]], is_theirs: bool| -> Vec<(Range<usize>, Range<usize>, bool)> {
+            diff_ops(base, side)
+                .iter()
+                .filter(|op| op.tag() != DiffTag::Equal)
+                .map(|op| (op.old_range(), op.new_range(), is_theirs))
+                .collect()
+        };
+    let mut changes = side_changes(ours, false);
+    changes.extend(side_changes(theirs, true));
+    changes.sort_by_key(|(range, _, _)| (range.start, range.end));
+
+    let mut result = Vec::new();
+    let mut done = 0;
+    let mut changes = changes.as_slice();
+    while let Some((first, _, _)) = changes.first() {
+        let start = first.start;
+        let mut end = first.end;
+        let len = changes
+            .iter()
+            .take_while(|(range, _, _)| {
+                let touches = range.start <= end;
+                if touches {
+                    end = end.max(range.end);
+                }
+                touches
+            })
+            .count();
+        let (group, rest) = changes.split_at(len);
+        changes = rest;
+
+        // Unchanged lines around the changes of one side map one-to-one to the base lines
+        let side = |lines: &'a [&'a [u8]], is_theirs: bool| {
+            let first = group.iter().find(|c| c.2 == is_theirs)?;
+            let last = group.iter().rfind(|c| c.2 == is_theirs)?;
+            Some(&lines[first.1.start - (first.0.start - start)..last.1.end + (end - last.0.end)])
+        };
+        if done < start {
+            result.push(Merged::Clean(&base[done..start]));
+        }
+        result.push(match (side(ours, false), side(theirs, true)) {
+            (Some(ours), Some(theirs)) if ours != theirs => Merged::Conflict {
+                line: start,
+                base: &base[start..end],
+                ours,
+                theirs,
+            },
+            (ours, theirs) => Merged::Clean(ours.or(theirs).unwrap_or_default()),
+        });
+        done = end;
+    }
+    if done < base.len() {
+        result.push(Merged::Clean(&base[done..]));
+    }
+    result
+}
+
+/// Encode the lines as a JSON string, replacing any invalid UTF-8 sequences.
+fn json_string(lines: &[&[u8]]) -> String {
+    let mut result = String::from('"');
+    for c in String::from_utf8_lossy(&lines.concat()).chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            // Writing to a string never fails
+            c if c < ' ' => _ = write!(result, "\\u{:04x}", u32::from(c)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
@@ -0,0 +1,776 @@
+use std::io;
+use std::ops::Range;
+
+use crate::common_diff::{register_differ, ConnLimits, Differ};
+use crate::error::CompressionError;
+use crate::rusqlite::{Connection, Result};
+
+/// Register the `vcdiff_diff` and `vcdiff_patch` SQL functions with the given `SQLite` connection.
+/// The `vcdiff_diff` function takes two arguments, and returns the [VCDIFF](https://www.rfc-editor.org/rfc/rfc3284) delta (blob)
+/// of the binary difference, which can also be applied with `xdelta3 -d` or open-vcdiff.
+/// The arguments can be either a string or a blob.
+/// The `vcdiff_patch` function applies VCDIFF deltas produced by this crate or by other encoders like `xdelta3 -e`,
+/// as long as they do not use secondary compression (`xdelta3 -S none`) or a custom code table.
+/// The `vcdiff_patch_test` function checks that a patch is well-formed, and if the source is given as the first argument,
+/// that the patch can be applied to it.
+/// The `vcdiff_patch_chain` aggregate function applies a series of patches in order to the base blob from the first row,
+/// e.g. `vcdiff_patch_chain(base, patch ORDER BY version)`.
+/// If any of the arguments are `NULL`, the result is `NULL`.
+///
+/// # Example
+///
+/// ```
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::register_vcdiff_functions;
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// register_vcdiff_functions(&db)?;
+/// let result: String = db.query_row("SELECT hex(vcdiff_diff('hello world', 'hello brave new world'))", [], |r| r.get(0))?;
+/// assert_eq!(result.as_str(), "D6C3C40000010B001315000903026272617665206E6577760A160005");
+/// let result: Vec<u8> = db.query_row("SELECT vcdiff_patch('013479', vcdiff_diff('013479', '23456789'))", [], |r| r.get(0))?;
+/// let expected = b"23456789";
+/// assert_eq!(result, expected);
+/// let result: bool = db.query_row("SELECT vcdiff_patch_test('013479', vcdiff_diff('013479', '23456789'))", [], |r| r.get(0))?;
+/// assert!(result);
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_vcdiff_functions(conn: &Connection) -> Result<()> {
+    register_vcdiff_functions_with_limits(conn, &ConnLimits::default())
+}
+
+pub(crate) fn register_vcdiff_functions_with_limits(
+    conn: &Connection,
+    limits: &ConnLimits,
+) -> Result<()> {
+    register_differ::<VcdiffDiffer>(conn, limits)
+}
+
+pub struct VcdiffDiffer;
+
+impl Differ for VcdiffDiffer {
+    type Options = ();
+
+    fn algorithm() -> &'static str {
+        "vcdiff"
+    }
+
+    fn diff_name() -> &'static str {
+        "vcdiff_diff"
+    }
+
+    fn patch_name() -> &'static str {
+        "vcdiff_patch"
+    }
+
+    fn test_name() -> &'static str {
+        "vcdiff_patch_test"
+    }
+
+    fn chain_name() -> &'static str {
+        "vcdiff_patch_chain"
+    }
+
+    fn prev_name() -> &'static str {
+        "vcdiff_diff_prev"
+    }
+
+    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        Ok(encode(source, target))
+    }
+
+    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        decode(patch, Some(source)).map_err(|e| CompressionError::decode_io("vcdiff", e))
+    }
+
+    fn test(patch: &[u8], source: Option<&[u8]>) -> bool {
+        decode(patch, source).is_ok()
+    }
+}
+
+/// File magic: `V`, `C`, `D` with their high bits set, followed by the format version.
+const MAGIC: [u8; 4] = [0xD6, 0xC3, 0xC4, 0x00];
+
+// Hdr_Indicator bits
+const VCD_DECOMPRESS: u8 = 0x01;
+const VCD_CODETABLE: u8 = 0x02;
+const VCD_APPHEADER: u8 = 0x04;
+
+// Win_Indicator bits. `VCD_ADLER32` is an xdelta3 extension.
+const VCD_SOURCE: u8 = 0x01;
+const VCD_TARGET: u8 = 0x02;
+const VCD_ADLER32: u8 = 0x04;
+
+/// Sizes of the `near` and `same` address caches used by the default code table.
+const NEAR_SIZE: usize = 4;
+const SAME_SIZE: usize = 3;
+
+/// Address modes, followed by `NEAR_SIZE` near modes and `SAME_SIZE` same modes.
+const VCD_SELF: u8 = 0;
+const VCD_HERE: u8 = 1;
+const FIRST_NEAR_MODE: u8 = 2;
+#[expect(clippy::cast_possible_truncation)]
+const FIRST_SAME_MODE: u8 = FIRST_NEAR_MODE + NEAR_SIZE as u8;
+
+/// Shortest match worth encoding as a COPY instead of an ADD.
+const MIN_MATCH: usize = 4;
+/// Target is split into windows of this size, well below the limits of the common decoders.
+const WINDOW_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Noop,
+    Add,
+    Run,
+    Copy,
+}
+
+/// One half of a code table entry. A `size` of zero means the size is read from the instruction section.
+#[derive(Clone, Copy)]
+struct Half {
+    kind: Kind,
+    size: u8,
+    mode: u8,
+}
+
+/// Build the default code table from RFC 3284 section 5.6.
+fn default_code_table() -> Vec<(Half, Half)> {
+    let half = |kind, size, mode| Half { kind, size, mode };
+    let noop = half(Kind::Noop, 0, 0);
+    let mut table = Vec::with_capacity(256);
+    table.push((half(Kind::Run, 0, 0), noop));
+    for size in 0..=17 {
+        table.push((half(Kind::Add, size, 0), noop));
+    }
+    for mode in 0..FIRST_SAME_MODE + 3 {
+        table.push((half(Kind::Copy, 0, mode), noop));
+        for size in 4..=18 {
+            table.push((half(Kind::Copy, size, mode), noop));
+        }
+    }
+    for mode in 0..FIRST_SAME_MODE {
+        for add_size in 1..=4 {
+            for copy_size in 4..=6 {
+                table.push((
+                    half(Kind::Add, add_size, 0),
+                    half(Kind::Copy, copy_size, mode),
+                ));
+            }
+        }
+    }
+    for mode in FIRST_SAME_MODE..FIRST_SAME_MODE + 3 {
+        for add_size in 1..=4 {
+            table.push((half(Kind::Add, add_size, 0), half(Kind::Copy, 4, mode)));
+        }
+    }
+    for mode in 0..FIRST_SAME_MODE + 3 {
+        table.push((half(Kind::Copy, 4, mode), half(Kind::Add, 1, 0)));
+    }
+    table
+}
+
+/// The `near` and `same` address caches from RFC 3284 section 5.1.
+struct AddressCache {
+    near: [u64; NEAR_SIZE],
+    next_slot: usize,
+    same: [u64; SAME_SIZE * 256],
+}
+
+impl AddressCache {
+    fn new() -> Self {
+        Self {
+            near: [0; NEAR_SIZE],
+            next_slot: 0,
+            same: [0; SAME_SIZE * 256],
+        }
+    }
+
+    fn update(&mut self, addr: u64) {
+        self.near[self.next_slot] = addr;
+        self.next_slot = (self.next_slot + 1) % NEAR_SIZE;
+        self.same[same_index(addr)] = addr;
+    }
+
+    /// Pick the mode with the smallest encoded address, returning the mode and the value to write.
+    #[expect(clippy::cast_possible_truncation)]
+    fn encode(&mut self, addr: u64, here: u64) -> (u8, u64) {
+        let mut best = (VCD_SELF, addr);
+        if here - addr < best.1 {
+            best = (VCD_HERE, here - addr);
+        }
+        for (mode, near) in (FIRST_NEAR_MODE..).zip(self.near) {
+            if addr >= near && addr - near < best.1 {
+                best = (mode, addr - near);
+            }
+        }
+        let index = same_index(addr);
+        if self.same[index] == addr {
+            best = (FIRST_SAME_MODE + (index / 256) as u8, (index % 256) as u64);
+        }
+        self.update(addr);
+        best
+    }
+
+    fn decode(&mut self, mode: u8, here: u64, addresses: &mut Reader<'_>) -> io::Result<u64> {
+        let addr = match mode {
+            VCD_SELF => addresses.varint()?,
+            VCD_HERE => here.checked_sub(addresses.varint()?).ok_or_else(invalid)?,
+            m if m < FIRST_SAME_MODE => self.near[usize::from(m - FIRST_NEAR_MODE)]
+                .checked_add(addresses.varint()?)
+                .ok_or_else(invalid)?,
+            m => {
+                let index = usize::from(m - FIRST_SAME_MODE) * 256 + usize::from(addresses.byte()?);
+                *self.same.get(index).ok_or_else(invalid)?
+            }
+        };
+        if addr >= here {
+            return Err(invalid());
+        }
+        self.update(addr);
+        Ok(addr)
+    }
+}
+
+#[expect(clippy::cast_possible_truncation)]
+fn same_index(addr: u64) -> usize {
+    (addr % (SAME_SIZE * 256) as u64) as usize
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not a valid VCDIFF patch")
+}
+
+fn unsupported(feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("VCDIFF {feature} is not supported"),
+    )
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let (head, tail) = self.0.split_at_checked(len).ok_or_else(invalid)?;
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Read a big-endian base-128 integer, with the high bit set on all but the last byte.
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0_u64;
+        loop {
+            let byte = self.byte()?;
+            if value.leading_zeros() < 7 {
+                return Err(invalid());
+            }
+            value = (value << 7) | u64::from(byte & 0x7F);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn size(&mut self) -> io::Result<usize> {
+        usize::try_from(self.varint()?).map_err(|_| invalid())
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, value: u64) {
+    let mut buf = [0_u8; 10];
+    let mut pos = buf.len() - 1;
+    buf[pos] = (value & 0x7F) as u8;
+    let mut value = value >> 7;
+    while value != 0 {
+        pos -= 1;
+        buf[pos] = (value & 0x7F) as u8 | 0x80;
+        value >>= 7;
+    }
+    out.extend_from_slice(&buf[pos..]);
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1_u32, 0_u32);
+    // 5552 is the largest chunk that cannot overflow `b` before the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// Source of the bytes that COPY instructions can address before the target window.
+enum Segment {
+    /// Part of the source. Reads as zeros when the source is unknown, so the patch structure can still be validated.
+    Source(usize),
+    /// Part of the already decoded target.
+    Target(usize),
+}
+
+/// Decode a VCDIFF patch. If `source` is `None`, only the patch structure is validated.
+fn decode(patch: &[u8], source: Option<&[u8]>) -> io::Result<Vec<u8>> {
+    let mut reader = Reader(patch);
+    if reader.bytes(MAGIC.len()).map_err(|_| invalid())? != MAGIC {
+        return Err(invalid());
+    }
+    let indicator = reader.byte()?;
+    if indicator & VCD_DECOMPRESS != 0 {
+        // Secondary compressor ID. Windows that actually use it are rejected.
+        reader.byte()?;
+    }
+    if indicator & VCD_CODETABLE != 0 {
+        return Err(unsupported("custom code table"));
+    }
+    if indicator & VCD_APPHEADER != 0 {
+        let len = reader.size()?;
+        reader.bytes(len)?;
+    }
+
+    let table = default_code_table();
+    let mut target = Vec::new();
+    while !reader.is_empty() {
+        decode_window(&mut reader, source, &table, &mut target)?;
+    }
+    Ok(target)
+}
+
+fn decode_window(
+    reader: &mut Reader<'_>,
+    source: Option<&[u8]>,
+    table: &[(Half, Half)],
+    target: &mut Vec<u8>,
+) -> io::Result<()> {
+    let indicator = reader.byte()?;
+    let (segment, segment_len) = match indicator & (VCD_SOURCE | VCD_TARGET) {
+        0 => (Segment::Source(0), 0),
+        flag @ (VCD_SOURCE | VCD_TARGET) => {
+            let len = reader.size()?;
+            let pos = reader.size()?;
+            let end = pos.checked_add(len).ok_or_else(invalid)?;
+            if flag == VCD_SOURCE {
+                if source.is_some_and(|s| end > s.len()) {
+                    return Err(invalid());
+                }
+                (Segment::Source(pos), len)
+            } else {
+                if end > target.len() {
+                    return Err(invalid());
+                }
+                (Segment::Target(pos), len)
+            }
+        }
+        _ => return Err(invalid()),
+    };
+
+    let delta_len = reader.size()?;
+    let mut delta = Reader(reader.bytes(delta_len)?);
+    let window_len = delta.size()?;
+    if delta.byte()? != 0 {
+        return Err(unsupported("secondary compression"));
+    }
+    let data_len = delta.size()?;
+    let inst_len = delta.size()?;
+    let addr_len = delta.size()?;
+    let checksum = if indicator & VCD_ADLER32 == 0 {
+        None
+    } else {
+        Some(u32::from_be_bytes(
+            delta.bytes(4)?.try_into().map_err(|_| invalid())?,
+        ))
+    };
+    let mut data = Reader(delta.bytes(data_len)?);
+    let mut instructions = Reader(delta.bytes(inst_len)?);
+    let mut addresses = Reader(delta.bytes(addr_len)?);
+    if !delta.is_empty() {
+        return Err(invalid());
+    }
+
+    let start = target.len();
+    let end = start.checked_add(window_len).ok_or_else(invalid)?;
+    // Do not trust the declared length for the allocation, the instructions will grow the buffer as needed
+    target.reserve(window_len.min(data_len.saturating_mul(4) + (1 << 16)));
+    let mut cache = AddressCache::new();
+    while !instructions.is_empty() {
+        let (first, second) = table[usize::from(instructions.byte()?)];
+        for half in [first, second] {
+            if half.kind == Kind::Noop {
+                continue;
+            }
+            let size = if half.size == 0 {
+                instructions.size()?
+            } else {
+                usize::from(half.size)
+            };
+            if size > end - target.len() {
+                return Err(invalid());
+            }
+            match half.kind {
+                Kind::Noop => {}
+                Kind::Add => target.extend_from_slice(data.bytes(size)?),
+                Kind::Run => {
+                    let byte = data.byte()?;
+                    target.resize(target.len() + size, byte);
+                }
+                Kind::Copy => {
+                    let here = (segment_len + target.len() - start) as u64;
+                    let addr = cache.decode(half.mode, here, &mut addresses)?;
+                    let addr = usize::try_from(addr).map_err(|_| invalid())?;
+                    copy(target, start, &segment, segment_len, source, addr, size);
+                }
+            }
+        }
+    }
+
+    if target.len() != end || !data.is_empty() || !addresses.is_empty() {
+        return Err(invalid());
+    }
+    let known = source.is_some() || matches!(segment, Segment::Target(_)) || segment_len == 0;
+    if let Some(checksum) = checksum.filter(|_| known) {
+        if adler32(&target[start..]) != checksum {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "VCDIFF window checksum mismatch",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Append `size` bytes starting at `addr` in the window address space: the segment followed by the target window.
+/// The copy may overlap the bytes it produces, so the target part is copied one byte at a time.
+fn copy(
+    target: &mut Vec<u8>,
+    start: usize,
+    segment: &Segment,
+    segment_len: usize,
+    source: Option<&[u8]>,
+    mut addr: usize,
+    mut size: usize,
+) {
+    if addr < segment_len {
+        let len = size.min(segment_len - addr);
+        match *segment {
+            Segment::Source(pos) => match source {
+                Some(source) => target.extend_from_slice(&source[pos + addr..pos + addr + len]),
+                None => target.resize(target.len() + len, 0),
+            },
+            Segment::Target(pos) => target.extend_from_within(pos + addr..pos + addr + len),
+        }
+        addr += len;
+        size -= len;
+        if size == 0 {
+            return;
+        }
+    }
+    let from = start + addr - segment_len;
+    for i in from..from + size {
+        target.push(target[i]);
+    }
+}
+
+/// Encode the difference as a VCDIFF patch using the default code table, with the whole source as the source segment
+/// of every window.
+fn encode(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(0);
+    let index = HashIndex::new(source, 0..source.len().saturating_sub(MIN_MATCH - 1));
+    if target.is_empty() {
+        encode_window(source, &index, &[], &mut out);
+    }
+    for window in target.chunks(WINDOW_SIZE) {
+        encode_window(source, &index, window, &mut out);
+    }
+    out
+}
+
+/// Hash table of the last position of every `MIN_MATCH`-byte sequence, storing `position + 1` so zero means empty.
+struct HashIndex {
+    table: Vec<u32>,
+    shift: u32,
+}
+
+impl HashIndex {
+    fn new(data: &[u8], positions: Range<usize>) -> Self {
+        let bits = usize::BITS - data.len().leading_zeros();
+        let bits = bits.clamp(8, 24);
+        let mut index = Self {
+            table: vec![0; 1 << bits],
+            shift: 32 - bits,
+        };
+        for pos in positions {
+            index.insert(data, pos);
+        }
+        index
+    }
+
+    fn slot(&self, data: &[u8], pos: usize) -> usize {
+        let key = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+        (key.wrapping_mul(0x9E37_79B1) >> self.shift) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], pos: usize) {
+        let slot = self.slot(data, pos);
+        // Positions beyond u32 are not indexed, and such data could not be stored in SQLite anyway
+        if let Ok(value) = u32::try_from(pos + 1) {
+            self.table[slot] = value;
+        }
+    }
+
+    /// Find the last indexed position that might start with the same bytes as `key[pos..]`.
+    fn get(&self, key: &[u8], pos: usize) -> Option<usize> {
+        match self.table[self.slot(key, pos)] {
+            0 => None,
+            value => Some(value as usize - 1),
+        }
+    }
+}
+
+enum Inst {
+    Add(usize),
+    Copy { size: usize, addr: usize },
+}
+
+/// A match of `size` bytes starting at `pos` in the window and at `addr` in the window address space.
+struct Match {
+    pos: usize,
+    size: usize,
+    addr: usize,
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn common_suffix(a: &[u8], b: &[u8]) -> usize {
+    a.iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+fn encode_window(source: &[u8], source_index: &HashIndex, window: &[u8], out: &mut Vec<u8>) {
+    let segment_len = source.len();
+    let mut window_index = HashIndex::new(window, 0..0);
+    let mut insts = Vec::new();
+    let mut data = Vec::new();
+    let mut literal = 0;
+    let mut pos = 0;
+    while pos + MIN_MATCH <= window.len() {
+        let mut best: Option<Match> = None;
+        let mut consider = |candidate: Match| {
+            if candidate.size >= MIN_MATCH && best.as_ref().is_none_or(|b| candidate.size > b.size)
+            {
+                best = Some(candidate);
+            }
+        };
+        if let Some(src) = source_index.get(window, pos) {
+            let size = common_prefix(&source[src..], &window[pos..]);
+            let back = common_suffix(&source[..src], &window[literal..pos]);
+            consider(Match {
+                pos: pos - back,
+                size: size + back,
+                addr: src - back,
+            });
+        }
+        if let Some(prev) = window_index.get(window, pos) {
+            // Both ranges run to the end of the window, the copy may overlap the bytes it produces
+            let size = common_prefix(&window[prev..], &window[pos..]);
+            let back = common_suffix(&window[..prev], &window[literal..pos]);
+            consider(Match {
+                pos: pos - back,
+                size: size + back,
+                addr: segment_len + prev - back,
+            });
+        }
+        let Some(m) = best else {
+            window_index.insert(window, pos);
+            pos += 1;
+            continue;
+        };
+        if m.pos > literal {
+            insts.push(Inst::Add(m.pos - literal));
+            data.extend_from_slice(&window[literal..m.pos]);
+        }
+        insts.push(Inst::Copy {
+            size: m.size,
+            addr: m.addr,
+        });
+        let end = m.pos + m.size;
+        for p in pos..end.min(window.len() - MIN_MATCH + 1) {
+            window_index.insert(window, p);
+        }
+        pos = end;
+        literal = end;
+    }
+    if literal < window.len() {
+        insts.push(Inst::Add(window.len() - literal));
+        data.extend_from_slice(&window[literal..]);
+    }
+
+    let (instructions, addresses) = encode_instructions(&insts, segment_len);
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, window.len() as u64);
+    delta.push(0);
+    write_varint(&mut delta, data.len() as u64);
+    write_varint(&mut delta, instructions.len() as u64);
+    write_varint(&mut delta, addresses.len() as u64);
+    delta.extend_from_slice(&data);
+    delta.extend_from_slice(&instructions);
+    delta.extend_from_slice(&addresses);
+
+    if segment_len == 0 {
+        out.push(0);
+    } else {
+        out.push(VCD_SOURCE);
+        write_varint(out, segment_len as u64);
+        write_varint(out, 0);
+    }
+    write_varint(out, delta.len() as u64);
+    out.extend_from_slice(&delta);
+}
+
+/// Encode the instructions with the default code table, merging small ADD and COPY pairs into a single opcode.
+/// All sizes cast to `u8` are checked to fit the opcode first.
+#[expect(clippy::cast_possible_truncation)]
+fn encode_instructions(insts: &[Inst], segment_len: usize) -> (Vec<u8>, Vec<u8>) {
+    // Resolve address modes first, in the same order as the decoder will update its cache
+    let mut cache = AddressCache::new();
+    let mut here = segment_len as u64;
+    let modes: Vec<_> = insts
+        .iter()
+        .map(|inst| match *inst {
+            Inst::Add(size) => {
+                here += size as u64;
+                (0, 0)
+            }
+            Inst::Copy { size, addr } => {
+                let mode = cache.encode(addr as u64, here);
+                here += size as u64;
+                mode
+            }
+        })
+        .collect();
+
+    let mut instructions = Vec::new();
+    let mut addresses = Vec::new();
+
+    let mut i = 0;
+    while i < insts.len() {
+        match (&insts[i], insts.get(i + 1)) {
+            (&Inst::Add(add @ 1..=4), Some(&Inst::Copy { size, .. }))
+                if ((4..=6).contains(&size) && modes[i + 1].0 < FIRST_SAME_MODE) || size == 4 =>
+            {
+                let mode = modes[i + 1].0;
+                let add = add as u8;
+                instructions.push(if mode < FIRST_SAME_MODE {
+                    163 + mode * 12 + (add - 1) * 3 + (size as u8 - 4)
+                } else {
+                    235 + (mode - FIRST_SAME_MODE) * 4 + (add - 1)
+                });
+                write_address(&mut addresses, modes[i + 1]);
+                i += 2;
+            }
+            (&Inst::Copy { size: 4, .. }, Some(&Inst::Add(1))) => {
+                instructions.push(247 + modes[i].0);
+                write_address(&mut addresses, modes[i]);
+                i += 2;
+            }
+            (&Inst::Add(size), _) => {
+                if (1..=17).contains(&size) {
+                    instructions.push(1 + size as u8);
+                } else {
+                    instructions.push(1);
+                    write_varint(&mut instructions, size as u64);
+                }
+                i += 1;
+            }
+            (&Inst::Copy { size, .. }, _) => {
+                let opcode = 19 + modes[i].0 * 16;
+                if (4..=18).contains(&size) {
+                    instructions.push(opcode + size as u8 - 3);
+                } else {
+                    instructions.push(opcode);
+                    write_varint(&mut instructions, size as u64);
+                }
+                write_address(&mut addresses, modes[i]);
+                i += 1;
+            }
+        }
+    }
+    (instructions, addresses)
+}
+
+/// Same-mode addresses are a single byte, all others are variable-length integers.
+#[expect(clippy::cast_possible_truncation)]
+fn write_address(addresses: &mut Vec<u8>, (mode, value): (u8, u64)) {
+    if mode >= FIRST_SAME_MODE {
+        addresses.push(value as u8);
+    } else {
+        write_varint(addresses, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_table() {
+        let table = default_code_table();
+        assert_eq!(table.len(), 256);
+        let (first, second) = table[163];
+        assert!(first.kind == Kind::Add && first.size == 1);
+        assert!(second.kind == Kind::Copy && second.size == 4 && second.mode == 0);
+        let (first, second) = table[255];
+        assert!(first.kind == Kind::Copy && first.size == 4 && first.mode == 8);
+        assert!(second.kind == Kind::Add && second.size == 1);
+    }
+
+    #[test]
+    fn test_xdelta3_patch() {
+        // Produced by `xdelta3 -e -S none -A -s source target patch`
+        let source = b"hello world, this is the source file content";
+        let target = b"hello brave new world, this is the target file content!";
+        let patch = b"\xD6\xC3\xC4\x00\x00\x05\x2C\x00\x23\x37\x00\x10\x07\x03\x32\x0C\x13\xFBbrave newtarget!\x16\x0A\x13\x14\x07\x1D\x02\x00\x05\x1F";
+        assert_eq!(VcdiffDiffer::patch(source, patch).unwrap(), target);
+        assert!(VcdiffDiffer::test(patch, None));
+        assert!(!VcdiffDiffer::test(
+            patch,
+            Some(b"hello world, this is the source file CONTENT")
+        ));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let source: Vec<u8> = (0..100_000_u32)
+            .flat_map(|v| (v * 7 % 251).to_le_bytes())
+            .collect();
+        let mut target = source.clone();
+        target.splice(1000..1010, *b"0123456789abcdef");
+        target.extend(std::iter::repeat_n(0x55, 5000));
+        target.extend_from_slice(&source);
+        target.extend_from_slice(&source);
+        let patch = VcdiffDiffer::diff(&source, &target).unwrap();
+        assert!(patch.len() < 200, "patch is {} bytes", patch.len());
+        assert_eq!(VcdiffDiffer::patch(&source, &patch).unwrap(), target);
+        for (source, target) in [(&b""[..], &b""[..]), (b"", b"abcabcabc"), (b"abc", b"")] {
+            let patch = VcdiffDiffer::diff(source, target).unwrap();
+            assert_eq!(VcdiffDiffer::patch(source, &patch).unwrap(), target);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::io::{self, Write};
+
+use rusqlite::types::ValueRef;
+use rusqlite::Error::InvalidFunctionParameterType;
+use zstd::stream::read::Decoder;
+use zstd::stream::write::Encoder;
+
+use crate::common_diff::{register_differ, ConnLimits, Differ};
+use crate::error::CompressionError;
+use crate::rusqlite::{Connection, Result};
+
+/// Register the `zstd_diff` and `zstd_patch` SQL functions with the given `SQLite` connection.
+/// The `zstd_diff` function takes two arguments, and returns a [zstd](https://facebook.github.io/zstd/) frame (blob)
+/// of the target compressed with the source as a reference prefix, same as `zstd --patch-from=source target`.
+/// The optional third argument is either the compression level, which defaults to the zstd default level, or `'verified'`.
+/// The arguments can be either a string or a blob.
+/// The `zstd_patch_test` function checks that a patch is a single complete zstd frame, and if the source is given
+/// as the first argument, that the patch can be applied to it.
+/// The `zstd_patch_chain` aggregate function applies a series of patches in order to the base blob from the first row,
+/// e.g. `zstd_patch_chain(base, patch ORDER BY version)`.
+/// If any of the arguments are `NULL`, the result is `NULL`.
+///
+/// # Example
+///
+/// ```
+/// # use sqlite_compressions::rusqlite::{Connection, Result};
+/// # use sqlite_compressions::register_zstd_functions;
+/// # fn main() -> Result<()> {
+/// let db = Connection::open_in_memory()?;
+/// register_zstd_functions(&db)?;
+/// let result: Vec<u8> = db.query_row("SELECT zstd_patch('013479', zstd_diff('013479', '23456789'))", [], |r| r.get(0))?;
+/// let expected = b"23456789";
+/// assert_eq!(result, expected);
+/// let result: Vec<u8> = db.query_row("SELECT zstd_patch('013479', zstd_diff('013479', '23456789', 19))", [], |r| r.get(0))?;
+/// assert_eq!(result, expected);
+/// let result: bool = db.query_row("SELECT zstd_patch_test('013479', zstd_diff('013479', '23456789'))", [], |r| r.get(0))?;
+/// assert!(result);
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_zstd_functions(conn: &Connection) -> Result<()> {
+    register_zstd_functions_with_limits(conn, &ConnLimits::default())
+}
+
+pub(crate) fn register_zstd_functions_with_limits(
+    conn: &Connection,
+    limits: &ConnLimits,
+) -> Result<()> {
+    register_differ::<ZstdDiffer>(conn, limits)
+}
+
+/// Largest window supported by zstd, which limits the combined size of the source and the target.
+#[cfg(target_pointer_width = "64")]
+const WINDOW_LOG_MAX: u32 = 31;
+#[cfg(not(target_pointer_width = "64"))]
+const WINDOW_LOG_MAX: u32 = 30;
+const WINDOW_LOG_MIN: u32 = 10;
+/// Same threshold as `zstd --patch-from` for enabling long distance matching.
+const LDM_WINDOW_LOG: u32 = 27;
+
+pub struct ZstdDiffer;
+
+impl ZstdDiffer {
+    /// Compress `target` using `source` as a reference prefix with the given zstd compression level.
+    pub fn diff_level(
+        source: &[u8],
+        target: &[u8],
+        level: i32,
+    ) -> Result<Vec<u8>, CompressionError> {
+        Self::try_diff(source, target, level).map_err(|e| CompressionError::io("zstd", e))
+    }
+
+    fn try_diff(source: &[u8], target: &[u8], level: i32) -> io::Result<Vec<u8>> {
+        // The window must cover the source as well as the target for all of the source to be referenced
+        let size = source.len() as u64 + target.len() as u64;
+        let window_log = (u64::BITS - size.leading_zeros()).clamp(WINDOW_LOG_MIN, WINDOW_LOG_MAX);
+
+        let mut encoder = Encoder::with_ref_prefix(Vec::new(), level, source)?;
+        encoder.set_pledged_src_size(Some(target.len() as u64))?;
+        encoder.window_log(window_log)?;
+        encoder.long_distance_matching(window_log >= LDM_WINDOW_LOG)?;
+        encoder.write_all(target)?;
+        encoder.finish()
+    }
+
+    fn decoder<'a>(source: &'a [u8], patch: &'a [u8]) -> io::Result<Decoder<'a, &'a [u8]>> {
+        let mut decoder = Decoder::with_ref_prefix(patch, source)?.single_frame();
+        decoder.window_log_max(WINDOW_LOG_MAX)?;
+        Ok(decoder)
+    }
+}
+
+impl Differ for ZstdDiffer {
+    type Options = i32;
+
+    fn algorithm() -> &'static str {
+        "zstd"
+    }
+
+    fn diff_name() -> &'static str {
+        "zstd_diff"
+    }
+
+    fn patch_name() -> &'static str {
+        "zstd_patch"
+    }
+
+    fn test_name() -> &'static str {
+        "zstd_patch_test"
+    }
+
+    fn chain_name() -> &'static str {
+        "zstd_patch_chain"
+    }
+
+    fn prev_name() -> &'static str {
+        "zstd_diff_prev"
+    }
+
+    const DIFF_OPTIONS: bool = true;
+
+    fn diff(source: &[u8], target: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        Self::diff_level(source, target, zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+
+    fn parse_options(value: ValueRef<'_>) -> Result<i32> {
+        let ValueRef::Integer(level) = value else {
+            return Err(InvalidFunctionParameterType(2, value.data_type()));
+        };
+        let range = zstd::compression_level_range();
+        match i32::try_from(level) {
+            Ok(level) if range.contains(&level) => Ok(level),
+            _ => Err(CompressionError::invalid_level(
+                "zstd",
+                format!(
+                    "The optional third argument to zstd_diff() must be between {} and {}",
+                    range.start(),
+                    range.end()
+                ),
+            )
+            .into()),
+        }
+    }
+
+    fn diff_with_options(
+        source: &[u8],
+        target: &[u8],
+        level: &i32,
+    ) -> Result<Vec<u8>, CompressionError> {
+        Self::diff_level(source, target, *level)
+    }
+
+    fn patch(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        let mut target = Vec::new();
+        Self::patch_into(source, patch, &mut target)?;
+        Ok(target)
+    }
+
+    fn patch_into(
+        source: &[u8],
+        patch: &[u8],
+        target: &mut Vec<u8>,
+    ) -> Result<(), CompressionError> {
+        target.clear();
+        Self::decoder(source, patch)
+            .and_then(|mut decoder| io::copy(&mut decoder, target))
+            .map_err(|e| CompressionError::decode_io("zstd", e))?;
+        Ok(())
+    }
+
+    fn test(patch: &[u8], source: Option<&[u8]>) -> bool {
+        match source {
+            Some(source) => Self::decoder(source, patch)
+                .and_then(|mut decoder| io::copy(&mut decoder, &mut io::sink()))
+                .is_ok(),
+            None => zstd::zstd_safe::find_frame_compressed_size(patch) == Ok(patch.len()),
+        }
+    }
+}
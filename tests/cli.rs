@@ -0,0 +1,147 @@
+#![cfg(all(feature = "cli", feature = "brotli", feature = "gzip"))]
+#![expect(clippy::unwrap_used)]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use insta::assert_snapshot;
+use rusqlite::Connection;
+
+fn create_db(name: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.db"));
+    let _ = std::fs::remove_file(&path);
+    let db = Connection::open(&path).unwrap();
+    db.execute_batch(
+        "CREATE TABLE docs(id INTEGER PRIMARY KEY, body);
+         INSERT INTO docs(body) VALUES ('hello hello hello'), (NULL), (x'0123'), ('world');",
+    )
+    .unwrap();
+    path
+}
+
+fn run(args: &[&str]) -> (bool, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_sqlite-compressions"))
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+fn dump(path: &PathBuf) -> String {
+    let db = Connection::open(path).unwrap();
+    let mut stmt = db
+        .prepare("SELECT id, quote(body) FROM docs ORDER BY id")
+        .unwrap();
+    let rows = stmt
+        .query_map([], |r| {
+            Ok(format!(
+                "{}: {}",
+                r.get::<_, i64>(0)?,
+                r.get::<_, String>(1)?
+            ))
+        })
+        .unwrap();
+    rows.map(Result::unwrap).collect::<Vec<_>>().join("\n")
+}
+
+#[test]
+fn compress_recompress_decompress() {
+    let path = create_db("cli_compress");
+    let db = path.to_str().unwrap();
+
+    let (ok, _, stderr) = run(&["compress", db, "docs", "body", "gzip", "--batch-size", "2"]);
+    assert!(ok);
+    assert_snapshot!(stderr, @r"
+    Processed 2 of 3 rows (66.7%)
+    Processed 3 of 3 rows (100.0%)
+    ");
+    assert_snapshot!(dump(&path), @r"
+    1: X'1F8B08000000000000FFCB48CDC9C957C84090008088F9E511000000'
+    2: NULL
+    3: X'1F8B08000000000000FF63540600CC52A5FA02000000'
+    4: X'1F8B08000000000000FF2BCF2FCA4901004311773A05000000'
+    ");
+
+    let (ok, _, _) = run(&["recompress", db, "docs", "body", "gzip", "brotli"]);
+    assert!(ok);
+    assert_snapshot!(dump(&path), @r"
+    1: X'1B1000F88D946EDE445586966C206F014F1C601C'
+    2: NULL
+    3: X'8B0080012303'
+    4: X'0B0280776F726C6403'
+    ");
+
+    let (ok, _, stderr) = run(&["decompress", db, "docs", "body", "gzip"]);
+    assert!(!ok);
+    assert_snapshot!(stderr, @"Error: row with rowid 1: invalid gzip header");
+
+    let (ok, _, _) = run(&["decompress", db, "docs", "body", "brotli"]);
+    assert!(ok);
+    assert_snapshot!(dump(&path), @r"
+    1: X'68656C6C6F2068656C6C6F2068656C6C6F'
+    2: NULL
+    3: X'0123'
+    4: X'776F726C64'
+    ");
+}
+
+#[test]
+fn partial_failure() {
+    let path = create_db("cli_partial");
+    let db = path.to_str().unwrap();
+
+    let (ok, _, stderr) = run(&["compress", db, "docs", "body", "gzip", "--batch-size", "0"]);
+    assert!(!ok);
+    assert!(stderr.contains("0 is not in 1.."), "{stderr}");
+
+    let (ok, _, _) = run(&["compress", db, "docs", "body", "gzip"]);
+    assert!(ok);
+    let conn = Connection::open(&path).unwrap();
+    conn.execute("UPDATE docs SET body = x'0123' WHERE id = 4", [])
+        .unwrap();
+
+    let (ok, _, stderr) = run(&[
+        "decompress",
+        db,
+        "docs",
+        "body",
+        "gzip",
+        "--batch-size",
+        "1",
+    ]);
+    assert!(!ok);
+    assert_snapshot!(stderr, @r"
+    Processed 1 of 3 rows (33.3%)
+    Processed 2 of 3 rows (66.7%)
+    Error: row with rowid 4: unexpected end of file
+    The first 2 of 3 rows were already converted and committed, so the column is now only partially converted.
+    ");
+    assert_snapshot!(dump(&path), @r"
+    1: X'68656C6C6F2068656C6C6F2068656C6C6F'
+    2: NULL
+    3: X'0123'
+    4: X'0123'
+    ");
+}
+
+#[test]
+fn stats() {
+    let path = create_db("cli_stats");
+    let db = path.to_str().unwrap();
+
+    let (ok, stdout, _) = run(&["stats", db, "docs", "body", "gzip", "brotli"]);
+    assert!(ok);
+    assert_snapshot!(stdout, @r"
+    3 non-NULL values, 24 bytes
+        gzip: 75 bytes (312.5% of original, adds 51 bytes)
+      brotli: 35 bytes (145.8% of original, adds 11 bytes)
+    ");
+
+    let (ok, _, stderr) = run(&["stats", db, "docs", "body", "foo"]);
+    assert!(!ok);
+    assert!(stderr.contains("unknown algorithm 'foo'"));
+}
@@ -0,0 +1,117 @@
+#![cfg(all(feature = "bsdiff4", feature = "bsdiffraw", feature = "text"))]
+
+use std::time::Duration;
+
+use insta::assert_snapshot;
+use rusqlite::Connection;
+use sqlite_compressions::{
+    register_compression_functions, register_compression_functions_with_limits, BsdiffRawDiffer,
+    BsdiffRawOptions, DiffLimitError, DiffLimits, Differ,
+};
+
+fn q(db: &Connection, query: &str) -> String {
+    match db.query_row(&format!("SELECT {query}"), [], |r| {
+        r.get::<_, Option<Vec<u8>>>(0)
+    }) {
+        Ok(v) => v.map_or_else(|| "NULL".into(), |v| format!("{} bytes", v.len())),
+        Err(e) => e.to_string(),
+    }
+}
+
+fn limits(db: &Connection, query: &str) -> String {
+    db.query_row(&format!("SELECT {query}"), [], |r| r.get::<_, String>(0))
+        .unwrap_or_else(|e| e.to_string())
+}
+
+#[test]
+fn diff_limits_test() {
+    let db = Connection::open_in_memory().unwrap();
+    register_compression_functions(&db).unwrap();
+    db.execute_batch(
+        "CREATE TABLE t(a, b);
+         INSERT INTO t SELECT randomblob(300000), randomblob(300000);",
+    )
+    .unwrap();
+    assert_snapshot!(limits(&db, "diff_limits()"), @r#"{"max_input_size":null,"time_budget_ms":null}"#);
+
+    assert_snapshot!(limits(&db, "diff_limits(1000, NULL)"), @r#"{"max_input_size":1000,"time_budget_ms":null}"#);
+    assert_snapshot!(q(&db, "bsdiff4(zeroblob(1000), zeroblob(1000))"), @"136 bytes");
+    assert_snapshot!(q(&db, "bsdiff4(zeroblob(1001), zeroblob(10))"), @"bsdiff4() input of 1001 bytes exceeds the limit of 1000 bytes");
+    assert_snapshot!(q(&db, "bsdiffraw(zeroblob(10), zeroblob(2000))"), @"bsdiffraw() input of 2000 bytes exceeds the limit of 1000 bytes");
+    assert_snapshot!(q(&db, "text_diff(zeroblob(10), NULL)"), @"NULL");
+    // Patches are not limited
+    assert_snapshot!(q(&db, "bspatchraw(zeroblob(2000), bsdiffraw('', ''))"), @"0 bytes");
+
+    // Other connections have their own limits
+    let other = Connection::open_in_memory().unwrap();
+    register_compression_functions(&other).unwrap();
+    assert_snapshot!(q(&other, "bsdiff4(zeroblob(1001), zeroblob(10))"), @"131 bytes");
+
+    // bsdiffraw stops early, bsdiff4 can only be checked when it is done
+    assert_snapshot!(limits(&db, "diff_limits(NULL, 1)"), @r#"{"max_input_size":null,"time_budget_ms":1}"#);
+    assert_snapshot!(q(&db, "bsdiffraw(a, b) FROM t"), @"bsdiffraw() exceeded the time budget of 1ms");
+    assert_snapshot!(q(&db, "bsdiff4(a, b) FROM t"), @"bsdiff4() exceeded the time budget of 1ms");
+
+    // The message is the same as the error type used by the function
+    let err = DiffLimitError::TimeBudgetExceeded {
+        algorithm: "bsdiffraw",
+        function: "bsdiffraw",
+        budget: Duration::from_millis(1),
+    };
+    assert_eq!(q(&db, "bsdiffraw(a, b) FROM t"), err.to_string());
+
+    // The limits do not apply to the Rust API
+    let (a, b): (Vec<u8>, Vec<u8>) = db
+        .query_row("SELECT a, b FROM t", [], |r| Ok((r.get(0)?, r.get(1)?)))
+        .unwrap();
+    assert!(BsdiffRawDiffer::diff(&a, &b).is_ok());
+    let options = BsdiffRawOptions { versioned: true };
+    assert!(BsdiffRawDiffer::diff_with_options(&a, &b, &options).is_ok());
+
+    // A generous budget, so the test does not depend on the machine load
+    assert_snapshot!(limits(&db, "diff_limits(NULL, 10000)"), @r#"{"max_input_size":null,"time_budget_ms":10000}"#);
+    assert_snapshot!(q(&db, "bsdiffraw('abc', 'abd')"), @"27 bytes");
+
+    assert_snapshot!(limits(&db, "diff_limits(NULL, NULL)"), @r#"{"max_input_size":null,"time_budget_ms":null}"#);
+    let sql = "SELECT bspatchraw(a, bsdiffraw(a, b)) = b FROM t";
+    assert!(db.query_row(sql, [], |r| r.get::<_, bool>(0)).unwrap());
+
+    // errors
+    assert_snapshot!(limits(&db, "diff_limits(-1, NULL)"), @"diff_limits() max_input_size must be NULL or non-negative, got -1");
+    assert_snapshot!(limits(&db, "diff_limits(1)"), @"Wrong number of parameters passed to query. Got 1, needed 2");
+}
+
+#[test]
+fn diff_limits_at_registration() {
+    let db = Connection::open_in_memory().unwrap();
+    let diff_limits = DiffLimits {
+        max_input_size: Some(1000),
+        time_budget: None,
+    };
+    register_compression_functions_with_limits(&db, diff_limits).unwrap();
+    assert_snapshot!(q(&db, "bsdiff4(zeroblob(1001), zeroblob(10))"), @"bsdiff4() input of 1001 bytes exceeds the limit of 1000 bytes");
+    assert_snapshot!(q(&db, "p FROM (SELECT a, bsdiff4_prev(b) OVER (ORDER BY a) AS p FROM (SELECT 1 AS a, zeroblob(1001) AS b UNION SELECT 2, zeroblob(10))) WHERE a = 2"), @"bsdiff4() input of 1001 bytes exceeds the limit of 1000 bytes");
+    // Queries cannot change the limits
+    assert_snapshot!(limits(&db, "diff_limits(NULL, NULL)"), @"no such function: diff_limits");
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn compressed_diff_limits() {
+    let db = Connection::open_in_memory().unwrap();
+    let diff_limits = DiffLimits {
+        max_input_size: Some(1000),
+        time_budget: None,
+    };
+    register_compression_functions_with_limits(&db, diff_limits).unwrap();
+    // A small compressed blob that expands past the limit is not fully decompressed
+    let size: i64 = db
+        .query_row("SELECT length(gzip(zeroblob(10000000)))", [], |r| r.get(0))
+        .unwrap();
+    assert!(size < 20_000);
+    assert_snapshot!(q(&db, "compressed_diff(gzip(zeroblob(10000000)), gzip('a'), 'gzip', 'bsdiff4')"), @"compressed_diff() input of 1001 bytes exceeds the limit of 1000 bytes");
+    assert_snapshot!(q(&db, "compressed_patch(gzip(zeroblob(10000000)), bsdiff4('', ''), 'gzip', 'bsdiff4')"), @"compressed_patch() input of 1001 bytes exceeds the limit of 1000 bytes");
+    assert_snapshot!(q(&db, "compressed_diff(gzip(zeroblob(1000)), gzip('a'), 'gzip', 'bsdiff4')"), @"130 bytes");
+    // The diff function is checked before anything is decompressed
+    assert_snapshot!(q(&db, "compressed_diff(x'00', x'00', 'gzip', 'unknown')"), @"Unknown diff function 'unknown'");
+}
@@ -57,11 +57,87 @@ test_one "SELECT bzip2_decode(bzip2('12345', 9));"    "12345"
 test_one "SELECT bzip2_test(bzip2('12345'));"         "1"
 test_one "SELECT bzip2_test(x'123456');"              "0"
 
+test_one "SELECT hex(recompress(gzip('12345'), 'gzip', 'brotli'));"  "0B0280313233343503"
+test_one "SELECT recompress(NULL, 'gzip', 'brotli') IS NULL;"       "1"
+test_one "SELECT ncd('abcabcabc', 'abcabcabd', 'gzip') < ncd('abcabcabc', 'xyzuvwrst', 'gzip');"  "1"
+test_one "SELECT compressed_patch(gzip('013479'), compressed_diff(gzip('013479'), gzip('23456789'), 'gzip', 'bsdiff4'), 'gzip', 'bsdiff4') = gzip('23456789');"  "1"
+test_one "SELECT diff_limits(1000, 250);"  '{"max_input_size":1000,"time_budget_ms":250}'
+
+test_one "SELECT json_array_length(cdc_chunks(zeroblob(100000), 1024, 4096, 16384));"  "7"
+test_one "SELECT hex(cdc_reassemble(x)) FROM (SELECT x'0102' AS x UNION ALL SELECT x'03');"  "010203"
+
 test_one "SELECT hex(bsdiff4('013479', '23456789'));"      "42534449464634302E0000000000000025000000000000000800000000000000425A68363141592653596A17AE4F00000160006E80080020002188C08601CAD80622AF61772453850906A17AE4F0425A6836314159265359B1F7404B00000040004000200021184682EE48A70A12163EE80960425A6836314159265359F715663B00000008001FC02000310C00C4C265CE5DE2EE48A70A121EE2ACC760"
 test_one "SELECT bspatch4('013479', bsdiff4('013479', '23456789'));"       "23456789"
+test_one "SELECT bspatch4('013479', bsdiff4('013479', '23456789', '{\"level\":9,\"parallel_chunk\":0}'));"  "23456789"
+test_one "SELECT bspatch4_test(bsdiff4('013479', '23456789'));"            "1"
+test_one "SELECT bspatch4_test('013479', bsdiff4('013479', '23456789'));"  "1"
+test_one "SELECT bspatch4_test(x'123456');"                                "0"
+test_one "SELECT bsdiff4_controls(bsdiff4('1234', '5678349A'));"           '[{"add":0,"copy":8,"seek":4}]'
+test_one "SELECT bsdiff4_best_base_id(b), bspatch4('hello world, hello world!', bsdiff4_best_base_patch(b)) FROM (SELECT bsdiff4_best_base(id, d, 'hello world, hello world') AS b FROM (SELECT 1 AS id, 'zzzz' AS d UNION ALL SELECT 2, 'hello world, hello world!'));"  "2|hello world, hello world"
+test_one "SELECT bspatch4_chain('0', p) FROM (SELECT bsdiff4('0', '01') AS p UNION ALL SELECT bsdiff4('01', '012'));"  "012"
+test_one "SELECT bsdiff4_info(bsdiff4('', ''))->>'target_size';"           "0"
+test_one "SELECT hex(bsdiff43('013479', '23456789'));"     "454E44534C45592F42534449464634330800000000000000425A68393141592653594E901A7800000168006E8004001FC0200022000010000199D54007885E0CE6AF37BC5DC914E142413A4069E0"
+test_one "SELECT bspatch43('013479', bsdiff43('013479', '23456789'));"      "23456789"
+test_one "SELECT bspatch4('013479', bsdiff43('013479', '23456789'));"       "23456789"
+test_one "SELECT bspatch43_test(bsdiff43('013479', '23456789'));"           "1"
+test_one "SELECT bspatch43_test('013479', bsdiff43('013479', '23456789'));" "1"
+test_one "SELECT bspatch43_test(x'123456');"                                "0"
+test_one "SELECT bspatch43_chain('0', p) FROM (SELECT bsdiff43('0', '01') AS p UNION ALL SELECT bsdiff43('01', '012'));"  "012"
 
 test_one "SELECT hex(bsdiffraw('013479', '23456789'));"    "0000000000000000080000000000000005000000000000003233343536373839"
 test_one "SELECT bspatchraw('013479', bsdiffraw('013479', '23456789'));"   "23456789"
+test_one "SELECT bspatchraw_test(bsdiffraw('013479', '23456789'));"            "1"
+test_one "SELECT bspatchraw_test('013479', bsdiffraw('013479', '23456789'));"  "1"
+test_one "SELECT bspatchraw('013479', bsdiffraw('013479', '23456789', 'verified'));"  "23456789"
+test_one "SELECT bspatchraw('013479', bsdiffraw('013479', '23456789', 'versioned'));"  "23456789"
+test_one "SELECT group_concat(quote(p), ',') FROM (SELECT bsdiffraw_prev(d) OVER (ORDER BY d) AS p FROM (SELECT 'a' AS d UNION ALL SELECT 'ab'));"  "NULL,X'0100000000000000010000000000000001000000000000800062'"
+test_one "SELECT bspatchraw_test(x'123456');"                                  "0"
+test_one "SELECT bspatchraw_chain('0', p) FROM (SELECT bsdiffraw('0', '01') AS p UNION ALL SELECT bsdiffraw('01', '012'));"  "012"
+
+test_one "SELECT hex(vcdiff_diff('013479', '23456789'));"  "D6C3C400000106000E0800080100323334353637383909"
+test_one "SELECT vcdiff_patch('013479', vcdiff_diff('013479', '23456789'));"       "23456789"
+test_one "SELECT vcdiff_patch_test(vcdiff_diff('013479', '23456789'));"            "1"
+test_one "SELECT vcdiff_patch_test('013479', vcdiff_diff('013479', '23456789'));"  "1"
+test_one "SELECT vcdiff_patch_test(x'123456');"                                    "0"
+test_one "SELECT vcdiff_patch_chain('0', p) FROM (SELECT vcdiff_diff('0', '01') AS p UNION ALL SELECT vcdiff_diff('01', '012'));"  "012"
+
+test_one "SELECT zstd_patch('013479', zstd_diff('013479', '23456789'));"       "23456789"
+test_one "SELECT zstd_patch('013479', zstd_diff('013479', '23456789', 19));"   "23456789"
+test_one "SELECT zstd_patch_test(zstd_diff('013479', '23456789'));"            "1"
+test_one "SELECT zstd_patch_test('013479', zstd_diff('013479', '23456789'));"  "1"
+test_one "SELECT zstd_patch_test(x'123456');"                                  "0"
+test_one "SELECT zstd_patch_chain('0', p) FROM (SELECT zstd_diff('0', '01') AS p UNION ALL SELECT zstd_diff('01', '012'));"  "012"
+
+test_one "SELECT delta_apply('013479', delta_create('013479', '23456789'));"       "23456789"
+test_one "SELECT delta_output_size(delta_create('013479', '23456789'));"           "8"
+test_one "SELECT delta_apply_test(delta_create('013479', '23456789'));"            "1"
+test_one "SELECT delta_apply_test('013479', delta_create('013479', '23456789'));"  "1"
+test_one "SELECT delta_apply_test(x'123456');"                                     "0"
+test_one "SELECT delta_apply_chain('0', p) FROM (SELECT delta_create('0', '01') AS p UNION ALL SELECT delta_create('01', '012'));"  "012"
+
+test_one "SELECT json_diff('{\"a\":1}', '{\"a\":2}');"                                      '[{"op":"replace","path":"/a","value":2}]'
+test_one "SELECT json_patch_apply('{\"a\":1}', json_diff('{\"a\":1}', '{\"b\":2}'));"            '{"b":2}'
+test_one "SELECT json_patch_test('{\"a\":1}', json_diff('{\"a\":1}', '{\"b\":2}'));"             "1"
+test_one "SELECT json_patch_test('{}');"                                                          "0"
+test_one "SELECT json_merge_patch('{\"a\":1}', json_merge_diff('{\"a\":1}', '{\"b\":2}'));"     '{"b":2}'
+test_one "SELECT json_merge_patch_chain('{}', p) FROM (SELECT '{\"a\":1}' AS p UNION ALL SELECT '{\"b\":2}');"  '{"a":1,"b":2}'
+
+test_one "SELECT hex(rsync_signature('013479', 4));"  "727301360000000400000010032301449F6254A89E415A025BA93CFC0DEE0B3D010400AECC63CD0C07B3885E0BFB7E08FB1874A5"
+test_one "SELECT rsync_patch('013479', rsync_delta(rsync_signature('013479'), '23456789'));"  "23456789"
+test_one "SELECT rsync_patch('013479', rsync_diff('013479', '23456789'));"       "23456789"
+test_one "SELECT rsync_patch_test(rsync_diff('013479', '23456789'));"            "1"
+test_one "SELECT rsync_patch_test('013479', rsync_diff('013479', '23456789'));"  "1"
+test_one "SELECT rsync_patch_test(x'123456');"                                   "0"
+test_one "SELECT rsync_patch_chain('0', p) FROM (SELECT rsync_diff('0', '01') AS p UNION ALL SELECT rsync_diff('01', '012'));"  "012"
+
+test_one "SELECT hex(text_diff('a', 'b'));"                                     "4040202D31202B312040400A2D610A5C204E6F206E65776C696E6520617420656E64206F662066696C650A2B620A5C204E6F206E65776C696E6520617420656E64206F662066696C650A"
+test_one "SELECT text_patch('013479', text_diff('013479', '23456789'));"       "23456789"
+test_one "SELECT text_patch_test(text_diff('013479', '23456789'));"            "1"
+test_one "SELECT text_patch_test('013479', text_diff('013479', '23456789'));"  "1"
+test_one "SELECT text_patch_test(x'123456');"                                  "0"
+test_one "SELECT text_patch_chain('0', p) FROM (SELECT text_diff('0', '01') AS p UNION ALL SELECT text_diff('01', '012'));"  "012"
+test_one "SELECT text_merge('a', 'a', 'b');"                                   "b"
+test_one "SELECT text_merge_conflicts('a', 'b', 'c');"                         '[{"line":1,"base":"a","ours":"b","theirs":"c"}]'
 
 
 echo "------------------------------"
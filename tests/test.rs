@@ -40,6 +40,14 @@ impl Conn {
         }
     }
 
+    pub fn text(&self, query: &str) -> String {
+        let query = format!("SELECT {query}");
+        match self.sql::<Option<String>>(&query) {
+            Ok(v) => v.unwrap_or_else(|| "NULL".into()),
+            Err(e) => e.to_string(),
+        }
+    }
+
     pub fn bool(&self, func: &str, param: &str) -> String {
         let query = format!("SELECT {}", param.replace('%', func));
         match self.sql::<Option<bool>>(&query) {
@@ -158,6 +166,58 @@ fn bsdiff4() {
     assert_snapshot!(c.q("bspatch4('1234', bsdiff4('1234', '5678349A'))"), @"3536373833343941");
     assert_snapshot!(c.q("bspatch4(x'1234', bsdiff4(x'1234', x'5678349A'))"), @"5678349a");
 
+    // testing
+    assert_snapshot!(c.bool("bspatch4", "%_test(bsdiff4('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("bspatch4", "%_test('1234', bsdiff4('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("bspatch4", "%_test(bsdiff4('', ''))"), @"true");
+    assert_snapshot!(c.bool("bspatch4", "%_test('', bsdiff4('123456789', '123456789'))"), @"false");
+    assert_snapshot!(c.bool("bspatch4", "%_test(substr(bsdiff4('1234', '5678349A'), 1, 30))"), @"false");
+    assert_snapshot!(c.bool("bspatch4", "%_test(x'0123456789abcdef')"), @"false");
+    assert_snapshot!(c.bool("bspatch4", "%_test(NULL)"), @"NULL");
+    assert_snapshot!(c.bool("bspatch4", "%_test(NULL, bsdiff4('1234', '5678349A'))"), @"NULL");
+    assert_snapshot!(c.bool("bspatch4", "%_test('1234', NULL)"), @"NULL");
+    assert_snapshot!(c.bool("bspatch4", "%_test()"), @"Wrong number of parameters passed to query. Got 0, needed 1");
+    assert_snapshot!(c.bool("bspatch4", "%_test('a', 'b', 'c')"), @"Wrong number of parameters passed to query. Got 3, needed 1");
+    assert_snapshot!(c.bool("bspatch4", "%_test(1)"), @"Invalid function parameter type Integer at index 0");
+    #[cfg(feature = "bzip2")]
+    {
+        // A tiny diff block that decompresses to far more than the header promises is rejected
+        let empty: Vec<u8> = c.sql("SELECT bzip2(x'')").unwrap();
+        let zeros: Vec<u8> = c.sql("SELECT bzip2(zeroblob(10000000))").unwrap();
+        let mut patch = b"BSDIFF40".to_vec();
+        patch.extend((empty.len() as u64).to_le_bytes());
+        patch.extend((zeros.len() as u64).to_le_bytes());
+        patch.extend(0_u64.to_le_bytes());
+        patch.extend([empty.as_slice(), &zeros, &empty].concat());
+        let valid: bool =
+            c.0.query_row("SELECT bspatch4_test(?1)", [patch], |r| r.get(0))
+                .unwrap();
+        assert!(!valid);
+    }
+
+    // inspection
+    assert_snapshot!(c.text("bsdiff4_info(bsdiff4('', ''))"), @r#"{"target_size":0,"control_size":14,"diff_size":14,"extra_size":14}"#);
+    assert_snapshot!(c.text("bsdiff4_info(bsdiff4('1234', '5678349A'))"), @r#"{"target_size":8,"control_size":42,"diff_size":14,"extra_size":45}"#);
+    assert_snapshot!(c.text("bsdiff4_controls(bsdiff4('', ''))"), @"[]");
+    assert_snapshot!(c.text("bsdiff4_controls(bsdiff4('1234', '5678349A'))"), @r#"[{"add":0,"copy":8,"seek":4}]"#);
+    assert_snapshot!(c.text("bsdiff4_controls(bsdiff4('abc013479zz', 'abc23456789zzf'))"), @r#"[{"add":3,"copy":11,"seek":8}]"#);
+    assert_snapshot!(c.text("bsdiff4_info(NULL)"), @"NULL");
+    assert_snapshot!(c.text("bsdiff4_controls(NULL)"), @"NULL");
+    assert_snapshot!(c.text("bsdiff4_info(x'0123')"), @"not a valid BSDIFF40 patch");
+    assert_snapshot!(c.text("bsdiff4_controls(substr(bsdiff4('1234', '5678349A'), 1, 50))"), @"not a valid BSDIFF40 patch");
+    assert_snapshot!(c.text("bsdiff4_info(1)"), @"Invalid function parameter type Integer at index 0");
+
+    // chain
+    let versions = "SELECT 1 AS v, 'abc' AS d UNION SELECT 2, 'abc013479zz' UNION SELECT 3, 'abc23456789zzf' UNION SELECT 4, NULL";
+    let patches =
+        format!("SELECT v, bsdiff4(lag(d) OVER (ORDER BY v), d) AS p FROM ({versions}) ORDER BY v");
+    assert_snapshot!(c.q(&format!("bspatch4_chain('abc', p) FROM ({patches})")), @"61626332333435363738397a7a66");
+    assert_snapshot!(c.q(&format!("bspatch4_chain('abc', p) FROM ({patches}) WHERE v < 3")), @"6162633031333437397a7a");
+    assert_snapshot!(c.q(&format!("bspatch4_chain(NULL, p) FROM ({patches})")), @"NULL");
+    assert_snapshot!(c.q(&format!("bspatch4_chain('abc', p) FROM ({patches}) WHERE v > 10")), @"NULL");
+    assert_snapshot!(c.q("bspatch4_chain('abc', x'0123')"), @"not a valid patch");
+    assert_snapshot!(c.q("bspatch4_chain('abc')"), @"wrong number of arguments to function bspatch4_chain()");
+
     // nulls
     assert_snapshot!(c.q("bsdiff4(NULL, NULL)"), @"NULL");
     assert_snapshot!(c.q("bsdiff4('abc', NULL)"), @"NULL");
@@ -168,11 +228,152 @@ fn bsdiff4() {
 
     // errors
     assert_snapshot!(c.q("bsdiff4(x'0123')"), @"wrong number of arguments to function bsdiff4()");
-    assert_snapshot!(c.q("bsdiff4(x'0123', x'4567', x'89')"), @"wrong number of arguments to function bsdiff4()");
+    assert_snapshot!(c.q("bsdiff4(x'0123', x'4567', 9, x'89')"), @"wrong number of arguments to function bsdiff4()");
     assert_snapshot!(c.q("bspatch4(x'0123')"), @"wrong number of arguments to function bspatch4()");
     assert_snapshot!(c.q("bspatch4(x'0123', x'4567', x'89')"), @"wrong number of arguments to function bspatch4()");
 }
 
+#[test]
+#[cfg(feature = "bsdiff4")]
+fn bsdiff4_options() {
+    let c = Conn::default();
+    let big = "WITH s(b) AS MATERIALIZED (SELECT randomblob(600000)) SELECT b AS a, CAST(substr(b, 1, 300000) || x'00' || substr(b, 300001) AS BLOB) AS b FROM s";
+    assert_snapshot!(c.bool("bsdiff4", "%('1234', '5678349A', NULL) = %('1234', '5678349A')"), @"true");
+    assert_snapshot!(c.bool("bsdiff4", "%('1234', '5678349A', '{}') = %('1234', '5678349A')"), @"true");
+    assert_snapshot!(c.bool("bsdiff4", "%('1234', '5678349A', 6) = %('1234', '5678349A')"), @"true");
+    assert_snapshot!(c.bool("bsdiff4", r#"%('1234', '5678349A', '{"level":6,"buffer_size":4096}') = %('1234', '5678349A')"#), @"true");
+    assert_snapshot!(c.q(r#"bspatch4('1234', bsdiff4('1234', '5678349A', '{"level":1,"buffer_size":1,"parallel_chunk":0}'))"#), @"3536373833343941");
+    assert_snapshot!(c.q("bspatch4('1234', bsdiff4('1234', '5678349A', 9))"), @"3536373833343941");
+    for options in [
+        r#"'{"parallel_chunk":0}'"#,
+        r#"'{"parallel_chunk":1}'"#,
+        r#"'{"parallel_chunk":262144}'"#,
+        r#"'{"level":9,"buffer_size":65536}'"#,
+    ] {
+        let sql = format!("bspatch4(a, bsdiff4(a, b, {options})) = b FROM ({big})");
+        assert_eq!(c.bool("bsdiff4", &sql), "true", "{options}");
+    }
+    // verified patches can use the other options too
+    assert_snapshot!(c.text(r#"hex(substr(bsdiff4('1234', '5678349A', '{"level":9,"verified":true}'), 1, 8))"#), @"53514C4344563100");
+    assert_snapshot!(c.bool("bsdiff4", r#"substr(%('1234', '5678349A', '{"level":9,"verified":true}'), 73) = %('1234', '5678349A', 9)"#), @"true");
+    assert_snapshot!(c.q(r#"bspatch4('1234', bsdiff4('1234', '5678349A', '{"level":9,"verified":true}'))"#), @"3536373833343941");
+    assert_snapshot!(c.bool("bsdiff4", r#"%('1234', '5678349A', '{"level":9,"verified":false}') = %('1234', '5678349A', 9)"#), @"true");
+    assert_snapshot!(c.bool("bspatch4", r#"%_test('5678', bsdiff4('1234', '5678349A', '{"verified":true}'))"#), @"false");
+
+    // errors
+    assert_snapshot!(c.q("bsdiff4('1234', '5678', 0)"), @"The optional third argument to bsdiff4() must be a compression level between 1 and 9, or a JSON object");
+    assert_snapshot!(c.q("bsdiff4('1234', '5678', 10)"), @"The optional third argument to bsdiff4() must be a compression level between 1 and 9, or a JSON object");
+    assert_snapshot!(c.q("bsdiff4('1234', '5678', -1)"), @"The optional third argument to bsdiff4() must be a compression level between 1 and 9, or a JSON object");
+    assert_snapshot!(c.q("bsdiff4('1234', '5678', 1.5)"), @"Invalid function parameter type Real at index 2");
+    assert_snapshot!(c.q("bsdiff4('1234', '5678', x'00')"), @"Invalid function parameter type Blob at index 2");
+    assert_snapshot!(c.q("bsdiff4('1234', '5678', 'level')"), @"Invalid bsdiff4() options: expected a JSON object");
+    assert_snapshot!(c.q("bsdiff4('1234', '5678', '[9]')"), @"Invalid bsdiff4() options: expected a JSON object");
+    assert_snapshot!(c.q(r#"bsdiff4('1234', '5678', '{"level":10}')"#), @r#"Invalid bsdiff4() options: "level" must be between 1 and 9"#);
+    assert_snapshot!(c.q(r#"bsdiff4('1234', '5678', '{"level":"9"}')"#), @r#"Invalid bsdiff4() options: "level" must be a non-negative integer"#);
+    assert_snapshot!(c.q(r#"bsdiff4('1234', '5678', '{"buffer_size":-1}')"#), @r#"Invalid bsdiff4() options: "buffer_size" must be a non-negative integer"#);
+    assert_snapshot!(c.q(r#"bsdiff4('1234', '5678', '{"threads":4}')"#), @r#"Invalid bsdiff4() options: unknown option "threads""#);
+    assert_snapshot!(c.q(r#"bsdiff4('1234', '5678', '{"verified":1}')"#), @r#"Invalid bsdiff4() options: "verified" must be a boolean"#);
+    let err = sqlite_compressions::Bsdiff4Options::from_json(br#"{"threads":4}"#).unwrap_err();
+    assert_snapshot!(format!("{err:?}"), @r#"InvalidOption { algorithm: "bsdiff4", message: "Invalid bsdiff4() options: unknown option \"threads\"" }"#);
+    let err = sqlite_compressions::Bsdiff4Options::from_json(br#"{"buffer_size":-1}"#).unwrap_err();
+    assert_snapshot!(format!("{err:?}"), @r#"InvalidOption { algorithm: "bsdiff4", message: "Invalid bsdiff4() options: \"buffer_size\" must be a non-negative integer" }"#);
+}
+
+#[test]
+#[cfg(feature = "bsdiff4")]
+fn bsdiff4_best_base() {
+    let c = Conn::default();
+    let data = "WITH r(x) AS MATERIALIZED (SELECT randomblob(4000)), \
+        t(target) AS MATERIALIZED (SELECT CAST(substr(x, 1, 3000) || x'00' || substr(x, 3001) AS BLOB) FROM r), \
+        c(id, data) AS MATERIALIZED (SELECT 1, randomblob(4000) UNION ALL SELECT 2, substr(x, 1, 1000) FROM r \
+        UNION ALL SELECT 3, randomblob(5000) UNION ALL SELECT 4, zeroblob(4000) UNION ALL SELECT 5, x FROM r \
+        UNION ALL SELECT 6, NULL UNION ALL SELECT 7, randomblob(10))";
+    let best = |expr: &str| {
+        c.text(&format!("({data} SELECT {expr} FROM (SELECT bsdiff4_best_base(id, data, target) AS b FROM c, t), c, t WHERE c.id = bsdiff4_best_base_id(b))"))
+    };
+    assert_snapshot!(best("CAST(bsdiff4_best_base_id(b) AS TEXT)"), @"5");
+    assert_snapshot!(best("CAST(bsdiff4_best_base_patch(b) = bsdiff4(data, target) AS TEXT)"), @"1");
+    assert_snapshot!(best("CAST(bspatch4(data, bsdiff4_best_base_patch(b)) = target AS TEXT)"), @"1");
+    assert_snapshot!(c.text(&format!("({data} SELECT bsdiff4_best_base_id(bsdiff4_best_base('v' || id, data, target)) FROM c, t)")), @"v5");
+    assert_snapshot!(c.text(&format!("({data} SELECT CAST(bsdiff4_best_base_id(bsdiff4_best_base(id, data, target)) AS TEXT) FROM c, t WHERE id < 5)")), @"2");
+    assert_snapshot!(c.text("typeof(bsdiff4_best_base_id(bsdiff4_best_base(1, '013479', '23456789')))"), @"integer");
+    assert_snapshot!(c.text(r#"bsdiff4_best_base_id(bsdiff4_best_base('a"\n', '013479', '23456789'))"#), @r#"a"\n"#);
+    assert_snapshot!(c.bool("bsdiff4", "bsdiff4_best_base_patch(bsdiff4_best_base(1, '013479', '23456789')) = %('013479', '23456789')"), @"true");
+    // Small targets are compared on every window, so an exact copy wins even after other candidates
+    assert_snapshot!(c.text("CAST(bsdiff4_best_base_id(bsdiff4_best_base(id, d, 'version 1 of doc')) AS TEXT) FROM (SELECT 1 AS id, 'version 2 of doc' AS d UNION ALL SELECT 2, 'version 3 of doc' UNION ALL SELECT 3, 'version 4 of doc' UNION ALL SELECT 4, 'version 1 of doc')"), @"4");
+
+    // nulls
+    assert_snapshot!(c.q("bsdiff4_best_base(1, NULL, 'abc')"), @"NULL");
+    assert_snapshot!(c.q("bsdiff4_best_base(1, 'abc', NULL)"), @"NULL");
+    assert_snapshot!(c.q("bsdiff4_best_base(id, 'abc', 'abd') FROM (SELECT 1 AS id) WHERE id > 1"), @"NULL");
+    assert_snapshot!(c.q("bsdiff4_best_base(NULL, 'abc', 'abd')"), @"NULL");
+    assert_snapshot!(c.text("CAST(bsdiff4_best_base_id(bsdiff4_best_base(id, d, 'abcdef')) AS TEXT) FROM (SELECT NULL AS id, 'abcdef' AS d UNION ALL SELECT 2, 'zzzz')"), @"2");
+    assert_snapshot!(c.q("bsdiff4_best_base_id(NULL)"), @"NULL");
+    assert_snapshot!(c.q("bsdiff4_best_base_patch(NULL)"), @"NULL");
+
+    // errors
+    assert_snapshot!(c.q("bsdiff4_best_base(id, 'abc', t) FROM (SELECT 1 AS id, 'a' AS t UNION ALL SELECT 2, 'b')"), @"bsdiff4_best_base() target must be the same in all rows");
+    assert_snapshot!(c.q("bsdiff4_best_base(x'01', 'abc', 'abd')"), @"Invalid function parameter type Blob at index 0");
+    assert_snapshot!(c.q("bsdiff4_best_base(1, 'abc')"), @"wrong number of arguments to function bsdiff4_best_base()");
+    assert_snapshot!(c.q("bsdiff4_best_base_patch(bsdiff4('abc', 'abd'))"), @"not a bsdiff4_best_base() result");
+    assert_snapshot!(c.q("bsdiff4_best_base_id(substr(bsdiff4_best_base(1, 'abc', 'abd'), 1, 9))"), @"not a bsdiff4_best_base() result");
+}
+
+#[test]
+#[cfg(feature = "bsdiff4")]
+fn bsdiff43() {
+    let c = Conn::default();
+    assert_snapshot!(c.q("bsdiff43('', '')"), @"454e44534c45592f42534449464634330000000000000000425a683917724538509000000000");
+    assert_snapshot!(c.q("bsdiff43('1234', '5678349A')"), @"454e44534c45592f42534449464634330800000000000000425a6839314159265359a7b74deb0000014c004c400fe020002000310340d020680d00705548606a3349ef177245385090a7b74deb");
+    assert_snapshot!(c.q("bspatch43('', bsdiff43('', ''))"), @"");
+    assert_snapshot!(c.q("bspatch43('a', bsdiff43('a', ''))"), @"");
+    assert_snapshot!(c.q("bspatch43('123456789', bsdiff43('123456789', '123456789'))"), @"313233343536373839");
+    assert_snapshot!(c.q("bspatch43('1234', bsdiff43('1234', '5678349A'))"), @"3536373833343941");
+    assert_snapshot!(c.q("bspatch43(x'1234', bsdiff43(x'1234', x'5678349A'))"), @"5678349a");
+    assert_snapshot!(c.q("bspatch43('abc013479zz', bsdiff43('abc013479zz', 'abc23456789zzf'))"), @"61626332333435363738397a7a66");
+    // patch created by a separate implementation of the format
+    let hello = "x'454e44534c45592f42534449464634331500000000000000425a6839314159265359647f851a00000771804b1020404000320111802000314c0000d43d23d419a86208b04b443f575be36be2ee48a70a120c8ff0a340'";
+    assert_snapshot!(c.text(&format!("CAST(bspatch43('hello world', {hello}) AS TEXT)")), @"hello brave new world");
+    // the format is detected by its header
+    assert_snapshot!(c.text(&format!("CAST(bspatch4('hello world', {hello}) AS TEXT)")), @"hello brave new world");
+    assert_snapshot!(c.q("bspatch43('1234', bsdiff4('1234', '5678349A'))"), @"3536373833343941");
+    assert_snapshot!(c.q("bspatch4('1234', bsdiff43('1234', '5678349A'))"), @"3536373833343941");
+
+    // testing
+    assert_snapshot!(c.bool("bspatch43", "%_test(bsdiff43('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("bspatch43", "%_test('1234', bsdiff43('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("bspatch43", "%_test(bsdiff43('', ''))"), @"true");
+    assert_snapshot!(c.bool("bspatch43", &format!("%_test({hello})")), @"true");
+    assert_snapshot!(c.bool("bspatch43", &format!("%_test('hello', {hello})")), @"false");
+    assert_snapshot!(c.bool("bspatch43", "%_test('', bsdiff43('123456789', '123456789'))"), @"false");
+    assert_snapshot!(c.bool("bspatch43", "%_test(substr(bsdiff43('1234', '5678349A'), 1, 30))"), @"false");
+    assert_snapshot!(c.bool("bspatch43", "%_test(x'0123456789abcdef')"), @"false");
+    assert_snapshot!(c.bool("bspatch43", "%_test(bsdiff4('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("bspatch4", "%_test(bsdiff43('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("bspatch4", "%_test('', bsdiff43('123456789', '123456789'))"), @"false");
+    assert_snapshot!(c.bool("bspatch43", "%_test(NULL)"), @"NULL");
+    assert_snapshot!(c.bool("bspatch43", "%_test(NULL, bsdiff43('1234', '5678349A'))"), @"NULL");
+
+    // chain
+    let versions = "SELECT 1 AS v, 'abc' AS d UNION SELECT 2, 'abc013479zz' UNION SELECT 3, 'abc23456789zzf' UNION SELECT 4, NULL";
+    let patches = format!(
+        "SELECT v, bsdiff43(lag(d) OVER (ORDER BY v), d) AS p FROM ({versions}) ORDER BY v"
+    );
+    assert_snapshot!(c.q(&format!("bspatch43_chain('abc', p) FROM ({patches})")), @"61626332333435363738397a7a66");
+    assert_snapshot!(c.q(&format!("bspatch43_chain('abc', p) FROM ({patches}) WHERE v < 3")), @"6162633031333437397a7a");
+    assert_snapshot!(c.q(&format!("bspatch4_chain('abc', p) FROM ({patches})")), @"61626332333435363738397a7a66");
+
+    // nulls
+    assert_snapshot!(c.q("bsdiff43(NULL, 'abc')"), @"NULL");
+    assert_snapshot!(c.q("bspatch43('abc', NULL)"), @"NULL");
+
+    // errors
+    assert_snapshot!(c.q("bsdiff43(x'0123')"), @"wrong number of arguments to function bsdiff43()");
+    assert_snapshot!(c.q("bspatch43('abc', x'0123')"), @"not a valid BSDIFF43 patch");
+    assert_snapshot!(c.q("bspatch43('', bsdiff43('123456789', '123456789'))"), @"not a valid BSDIFF43 patch");
+    assert_snapshot!(c.q("bspatch43('1234', substr(bsdiff43('1234', '5678349A'), 1, 30))"), @"decompression not finished but EOF reached");
+}
+
 #[test]
 #[cfg(feature = "bsdiffraw")]
 fn bsdiffraw() {
@@ -195,6 +396,42 @@ fn bsdiffraw() {
     assert_snapshot!(c.q("bspatchraw('1234', bsdiffraw('1234', '5678349A'))"), @"3536373833343941");
     assert_snapshot!(c.q("bspatchraw(x'1234', bsdiffraw(x'1234', x'5678349A'))"), @"5678349a");
 
+    // testing
+    assert_snapshot!(c.bool("bspatchraw", "%_test(bsdiffraw('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("bspatchraw", "%_test('1234', bsdiffraw('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("bspatchraw", "%_test(bsdiffraw('', ''))"), @"true");
+    assert_snapshot!(c.bool("bspatchraw", "%_test('', bsdiffraw('123456789', '123456789'))"), @"false");
+    assert_snapshot!(c.bool("bspatchraw", "%_test(substr(bsdiffraw('1234', '5678349A'), 1, 30))"), @"false");
+    assert_snapshot!(c.bool("bspatchraw", "%_test(x'0123456789abcdef')"), @"false");
+    assert_snapshot!(c.bool("bspatchraw", "%_test(NULL)"), @"NULL");
+    assert_snapshot!(c.bool("bspatchraw", "%_test(NULL, bsdiffraw('1234', '5678349A'))"), @"NULL");
+    assert_snapshot!(c.bool("bspatchraw", "%_test('1234', NULL)"), @"NULL");
+    assert_snapshot!(c.bool("bspatchraw", "%_test()"), @"Wrong number of parameters passed to query. Got 0, needed 1");
+    assert_snapshot!(c.bool("bspatchraw", "%_test('a', 'b', 'c')"), @"Wrong number of parameters passed to query. Got 3, needed 1");
+    assert_snapshot!(c.bool("bspatchraw", "%_test(1)"), @"Invalid function parameter type Integer at index 0");
+
+    // chain
+    let versions = "SELECT 1 AS v, 'abc' AS d UNION SELECT 2, 'abc013479zz' UNION SELECT 3, 'abc23456789zzf' UNION SELECT 4, NULL";
+    let patches = format!(
+        "SELECT v, bsdiffraw(lag(d) OVER (ORDER BY v), d) AS p FROM ({versions}) ORDER BY v"
+    );
+    assert_snapshot!(c.q(&format!("bspatchraw_chain('abc', p) FROM ({patches})")), @"61626332333435363738397a7a66");
+    assert_snapshot!(c.q(&format!("bspatchraw_chain('abc', p) FROM ({patches}) WHERE v < 3")), @"6162633031333437397a7a");
+    assert_snapshot!(c.q(&format!("bspatchraw_chain(NULL, p) FROM ({patches})")), @"NULL");
+    assert_snapshot!(c.q(&format!("bspatchraw_chain('abc', p) FROM ({patches}) WHERE v > 10")), @"NULL");
+    assert_snapshot!(c.q("bspatchraw_chain('abc', x'0123')"), @"unexpected end of file");
+    assert_snapshot!(c.q("bspatchraw_chain('abc')"), @"wrong number of arguments to function bspatchraw_chain()");
+
+    // versioned
+    assert_snapshot!(c.q("bsdiffraw('1234', '5678349A', 'versioned')"), @"425344494646524157010000000000000000080000000000000003000000000000003536373833343941");
+    assert_snapshot!(c.q("bspatchraw('1234', bsdiffraw('1234', '5678349A', 'versioned'))"), @"3536373833343941");
+    assert_snapshot!(c.q("bspatchraw('1234', bsdiffraw('1234', '5678349A', NULL))"), @"3536373833343941");
+    assert_snapshot!(c.bool("bspatchraw", "%_test('1234', bsdiffraw('1234', '5678349A', 'versioned'))"), @"true");
+    assert_snapshot!(c.bool("bspatchraw", "%_test(CAST('BSDIFFRAW' || x'02' AS BLOB))"), @"false");
+    assert_snapshot!(c.q("bspatchraw('1234', CAST('BSDIFFRAW' || x'02' AS BLOB))"), @"Unsupported bsdiffraw patch format version 2");
+    assert_snapshot!(c.q("bspatchraw('1234', CAST('BSDIFFRAW' AS BLOB))"), @"Truncated bsdiffraw patch header");
+    assert_snapshot!(c.q("bspatchraw_chain('1234', bsdiffraw('1234', '5678349A', 'versioned'))"), @"3536373833343941");
+
     // nulls
     assert_snapshot!(c.q("bsdiffraw(NULL, NULL)"), @"NULL");
     assert_snapshot!(c.q("bsdiffraw('abc', NULL)"), @"NULL");
@@ -205,7 +442,944 @@ fn bsdiffraw() {
 
     // errors
     assert_snapshot!(c.q("bsdiffraw(x'0123')"), @"wrong number of arguments to function bsdiffraw()");
-    assert_snapshot!(c.q("bsdiffraw(x'0123', x'4567', x'89')"), @"wrong number of arguments to function bsdiffraw()");
+    assert_snapshot!(c.q("bsdiffraw(x'0123', x'4567', x'89', x'00')"), @"wrong number of arguments to function bsdiffraw()");
+    assert_snapshot!(c.q("bsdiffraw(x'0123', x'4567', 'verify')"), @"The optional third argument to bsdiffraw() must be 'versioned' or 'verified'");
     assert_snapshot!(c.q("bspatchraw(x'0123')"), @"wrong number of arguments to function bspatchraw()");
     assert_snapshot!(c.q("bspatchraw(x'0123', x'4567', x'89')"), @"wrong number of arguments to function bspatchraw()");
 }
+
+#[test]
+#[cfg(feature = "json")]
+fn json() {
+    let c = Conn::default();
+    let a = r#"'{"name":"a","tags":["x","y"],"size":{"w":1,"h":2}}'"#;
+    let b = r#"'{"name":"b","tags":["x"],"size":{"w":1,"h":3},"new":null}'"#;
+    assert_snapshot!(c.text(&format!("json_diff({a}, {b})")), @r#"[{"op":"replace","path":"/name","value":"b"},{"op":"remove","path":"/tags/1"},{"op":"replace","path":"/size/h","value":3},{"op":"add","path":"/new","value":null}]"#);
+    assert_snapshot!(c.text(&format!("typeof(json_diff({a}, {b}))")), @"text");
+    assert_snapshot!(c.text(&format!("json_patch_apply({a}, json_diff({a}, {b}))")), @r#"{"name":"b","tags":["x"],"size":{"w":1,"h":3},"new":null}"#);
+    assert_snapshot!(c.text(&format!("json_patch_apply({a}, json_diff({a}, {a}))")), @r#"{"name":"a","tags":["x","y"],"size":{"w":1,"h":2}}"#);
+    assert_snapshot!(c.text("json_diff('[1,2]', '{\"a\":1}')"), @r#"[{"op":"replace","path":"","value":{"a":1}}]"#);
+    assert_snapshot!(c.text("json_diff('1', '1')"), @"[]");
+    assert_snapshot!(c.text("json_diff(' { \"a\" : 1 } ', '{\"a\":1}')"), @"[]");
+    // object member order is preserved
+    assert_snapshot!(c.text(r#"json_patch_apply('{"z":1,"a":2}', '[{"op":"add","path":"/m","value":3}]')"#), @r#"{"z":1,"a":2,"m":3}"#);
+    assert_snapshot!(c.text(r#"json_patch_apply('{"a":[1,2]}', '[{"op":"test","path":"/a/0","value":1},{"op":"move","from":"/a","path":"/b"}]')"#), @r#"{"b":[1,2]}"#);
+
+    // merge patch
+    assert_snapshot!(c.text(r#"json_merge_diff('{"a":1,"b":{"c":2,"d":3}}', '{"b":{"c":2,"d":4},"e":[null]}')"#), @r#"{"a":null,"b":{"d":4},"e":[null]}"#);
+    assert_snapshot!(c.text(r#"json_merge_patch('{"a":1,"b":{"c":2,"d":3}}', json_merge_diff('{"a":1,"b":{"c":2,"d":3}}', '{"b":{"c":2,"d":4},"e":[null]}'))"#), @r#"{"b":{"c":2,"d":4},"e":[null]}"#);
+    assert_snapshot!(c.text(r#"json_merge_diff('{"a":1}', '[1]')"#), @"[1]");
+    assert_snapshot!(c.text(r#"json_merge_diff('{"a":1}', '{"a":1}')"#), @"{}");
+    // compatible with the built-in SQLite function
+    assert_snapshot!(c.text(&format!("json_patch({a}, json_merge_diff({a}, '{{\"name\":\"b\"}}'))")), @r#"{"name":"b"}"#);
+    assert_snapshot!(c.text(r#"json_merge_patch('{"a":1,"b":2}', '{"a":null,"c":{"d":null}}')"#), @r#"{"b":2,"c":{}}"#);
+
+    // testing
+    assert_snapshot!(c.bool("json_patch", &format!("%_test(json_diff({a}, {b}))")), @"true");
+    assert_snapshot!(c.bool("json_patch", &format!("%_test({a}, json_diff({a}, {b}))")), @"true");
+    assert_snapshot!(c.bool("json_patch", &format!("%_test({b}, json_diff({a}, {b}))")), @"false");
+    assert_snapshot!(c.bool("json_patch", r#"%_test('{"a":1}', '[{"op":"test","path":"/a","value":2}]')"#), @"false");
+    assert_snapshot!(c.bool("json_patch", r#"%_test('[{"op":"jump","path":"/a"}]')"#), @"false");
+    assert_snapshot!(c.bool("json_patch", "%_test('{}')"), @"false");
+    assert_snapshot!(c.bool("json_patch", "%_test('not json', '[]')"), @"false");
+    assert_snapshot!(c.bool("json_patch", "%_test(NULL)"), @"NULL");
+    assert_snapshot!(c.bool("json_merge_patch", r#"%_test('{"a":null}')"#), @"true");
+    assert_snapshot!(c.bool("json_merge_patch", r#"%_test('[1]', '{"a":null}')"#), @"true");
+    assert_snapshot!(c.bool("json_merge_patch", "%_test('{')"), @"false");
+    assert_snapshot!(c.bool("json_merge_patch", "%_test(NULL, '{}')"), @"NULL");
+
+    // chain
+    let versions = r#"SELECT 1 AS v, '{"a":1}' AS d UNION SELECT 2, '{"a":2,"b":[]}' UNION SELECT 3, '{"b":[3]}' UNION SELECT 4, NULL"#;
+    let patches =
+        format!("SELECT v, % (lag(d) OVER (ORDER BY v), d) AS p FROM ({versions}) ORDER BY v");
+    assert_snapshot!(c.text(&format!(r#"json_patch_chain('{{"a":1}}', p) FROM ({})"#, patches.replace('%', "json_diff"))), @r#"{"b":[3]}"#);
+    assert_snapshot!(c.text(&format!(r#"json_merge_patch_chain('{{"a":1}}', p) FROM ({})"#, patches.replace('%', "json_merge_diff"))), @r#"{"b":[3]}"#);
+    assert_snapshot!(c.text(&format!(r#"typeof(json_patch_chain('{{"a":1}}', p)) FROM ({})"#, patches.replace('%', "json_diff"))), @"text");
+
+    // nulls
+    assert_snapshot!(c.text("json_diff(NULL, '{}')"), @"NULL");
+    assert_snapshot!(c.text("json_patch_apply('{}', NULL)"), @"NULL");
+    assert_snapshot!(c.text("json_merge_diff('{}', NULL)"), @"NULL");
+    assert_snapshot!(c.text("json_merge_patch(NULL, '{}')"), @"NULL");
+
+    // errors
+    assert_snapshot!(c.text("json_diff('{', '{}')"), @"EOF while parsing an object at line 1 column 1");
+    assert_snapshot!(c.text("json_diff('{}', 1)"), @"Invalid function parameter type Integer at index 1");
+    assert_snapshot!(c.text(r#"json_patch_apply('{}', '{"op":"add"}')"#), @"invalid type: map, expected a sequence at line 1 column 0");
+    assert_snapshot!(c.text(r#"json_patch_apply('{}', '[{"op":"remove","path":"/a"}]')"#), @"operation '/0' failed at path '/a': path is invalid");
+    assert_snapshot!(c.text(r#"json_patch_apply('{"a":1}', '[{"op":"test","path":"/a","value":2}]')"#), @"operation '/0' failed at path '/a': value did not match");
+    assert_snapshot!(c.text(r#"json_merge_diff('{}', '{"a":null}')"#), @"json_merge_diff() cannot set an object member to null, use json_diff() instead");
+    assert_snapshot!(c.text(r#"json_merge_diff('{"a":1}', '{"a":null}')"#), @"json_merge_diff() cannot set an object member to null, use json_diff() instead");
+    assert_snapshot!(c.text(r#"json_merge_diff('{"a":1}', '{"a":{"b":{"c":null}}}')"#), @"json_merge_diff() cannot set an object member to null, use json_diff() instead");
+}
+
+#[test]
+#[cfg(feature = "rsync")]
+fn rsync() {
+    let c = Conn::default();
+    assert_snapshot!(c.q("rsync_signature('')"), @"727301360000080000000010");
+    assert_snapshot!(c.q("rsync_signature('013479', 4)"), @"727301360000000400000010032301449f6254a89e415a025ba93cfc0dee0b3d010400aecc63cd0c07b3885e0bfb7e08fb1874a5");
+    assert_snapshot!(c.bool("rsync_signature", "%('013479', NULL) = %('013479')"), @"true");
+    assert_snapshot!(c.q("rsync_delta(rsync_signature(''), '')"), @"7273023600");
+    assert_snapshot!(c.q("rsync_delta(rsync_signature('013479', 2), '0134789')"), @"727302364500040337383900");
+    assert_snapshot!(c.q("rsync_patch('', rsync_diff('', ''))"), @"");
+    assert_snapshot!(c.q("rsync_patch('a', rsync_diff('a', ''))"), @"");
+    assert_snapshot!(c.q("rsync_patch('123456789', rsync_diff('123456789', '123456789'))"), @"313233343536373839");
+    assert_snapshot!(c.q("rsync_patch('1234', rsync_diff('1234', '5678349A'))"), @"3536373833343941");
+    assert_snapshot!(c.q("rsync_patch(x'1234', rsync_delta(rsync_signature(x'1234', 1), x'5678349A'))"), @"5678349a");
+    // only the signature of the source is needed, and the delta references its blocks
+    let blobs = "WITH s(b) AS MATERIALIZED (SELECT randomblob(102400)), t(b, t) AS (SELECT b, CAST(b || x'00' AS BLOB) FROM s)";
+    assert_snapshot!(c.bool("rsync_delta", &format!("({blobs} SELECT length(%(rsync_signature(b), t)) < 1000 FROM t)")), @"true");
+    assert_snapshot!(c.bool("rsync_patch", &format!("({blobs} SELECT %(b, rsync_delta(rsync_signature(b, 512), t)) = t FROM t)")), @"true");
+    // a delta that copies the whole source many times may only grow it up to 256 times, plus the delta size
+    let copies = |sql: &str, n: usize| {
+        let delta = [&[0x72, 0x73, 0x02, 0x36][..], &[0x45, 0, 4].repeat(n), &[0]].concat();
+        c.0.query_row(sql, [delta], |r| r.get::<_, i64>(0))
+            .map_or_else(|e| e.to_string(), |v| v.to_string())
+    };
+    assert_snapshot!(copies("SELECT length(rsync_patch('1234', ?1))", 1000), @"4000");
+    assert_snapshot!(copies("SELECT rsync_patch_test('1234', ?1)", 1000), @"1");
+    assert_snapshot!(copies("SELECT length(rsync_patch('1234', ?1))", 2000), @"exceeded output size limit when writing copy (wanted=4, available=1)");
+    assert_snapshot!(copies("SELECT rsync_patch_test('1234', ?1)", 2000), @"0");
+
+    // testing
+    assert_snapshot!(c.bool("rsync_patch", "%_test(rsync_diff('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("rsync_patch", "%_test('1234', rsync_diff('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("rsync_patch", "%_test(rsync_diff('', ''))"), @"true");
+    assert_snapshot!(c.bool("rsync_patch", "%_test('', rsync_delta(rsync_signature('01234567', 4), '01234567'))"), @"false");
+    assert_snapshot!(c.bool("rsync_patch", "%_test(substr(rsync_diff('1234', '5678349A'), 1, 7))"), @"false");
+    assert_snapshot!(c.bool("rsync_patch", "%_test(rsync_diff('1234', '5678349A') || x'00')"), @"false");
+    assert_snapshot!(c.bool("rsync_patch", "%_test(x'0123456789abcdef')"), @"false");
+    assert_snapshot!(c.bool("rsync_patch", "%_test(NULL)"), @"NULL");
+    assert_snapshot!(c.bool("rsync_patch", "%_test(NULL, rsync_diff('1234', '5678349A'))"), @"NULL");
+
+    // chain
+    let versions = "SELECT 1 AS v, 'abc' AS d UNION SELECT 2, 'abc013479zz' UNION SELECT 3, 'abc23456789zzf' UNION SELECT 4, NULL";
+    let patches = format!(
+        "SELECT v, rsync_diff(lag(d) OVER (ORDER BY v), d) AS p FROM ({versions}) ORDER BY v"
+    );
+    assert_snapshot!(c.q(&format!("rsync_patch_chain('abc', p) FROM ({patches})")), @"61626332333435363738397a7a66");
+    assert_snapshot!(c.q(&format!("rsync_patch_chain('abc', p) FROM ({patches}) WHERE v < 3")), @"6162633031333437397a7a");
+
+    // nulls
+    assert_snapshot!(c.q("rsync_signature(NULL)"), @"NULL");
+    assert_snapshot!(c.q("rsync_delta(NULL, 'abc')"), @"NULL");
+    assert_snapshot!(c.q("rsync_delta(rsync_signature('abc'), NULL)"), @"NULL");
+    assert_snapshot!(c.q("rsync_diff(NULL, 'abc')"), @"NULL");
+    assert_snapshot!(c.q("rsync_patch('abc', NULL)"), @"NULL");
+
+    // errors
+    assert_snapshot!(c.q("rsync_signature('abc', 0)"), @"The optional second argument to rsync_signature() must be between 1 and 4294967295");
+    assert_snapshot!(c.q("rsync_signature('abc', 'big')"), @"Invalid function parameter type Text at index 1");
+    assert_snapshot!(c.q("rsync_signature('abc', 1, 2)"), @"wrong number of arguments to function rsync_signature()");
+    assert_snapshot!(c.q("rsync_delta(x'0123', 'abc')"), @"invalid or unsupported signature");
+    assert_snapshot!(c.q("rsync_delta(x'72730137000008000000002000000000', 'abc')"), @"invalid or unsupported signature");
+    assert_snapshot!(c.q("rsync_patch('abc', x'0123')"), @"unexpected end of input when reading magic (expected=4, available=2)");
+    assert_snapshot!(c.q("rsync_patch('', rsync_delta(rsync_signature('01234567', 4), '01234567'))"), @"requested copy is out of bounds (offset=0, len=8, data_len=0)");
+}
+
+#[test]
+#[cfg(feature = "text")]
+fn text() {
+    let c = Conn::default();
+    let a = "'a\nb\nc\nd\ne\nf\ng\nh\n'";
+    let b = "'a\nb\nC\nd\ne\nf\ng\nh\ni'";
+    assert_snapshot!(c.text(&format!("text_diff({a}, {b})")), @r"
+    @@ -1,8 +1,9 @@
+     a
+     b
+    -c
+    +C
+     d
+     e
+     f
+     g
+     h
+    +i
+    \ No newline at end of file
+    ");
+    assert_snapshot!(c.text(&format!("text_diff({a}, {b}, 0)")), @r"
+    @@ -3 +3 @@
+    -c
+    +C
+    @@ -8,0 +9 @@
+    +i
+    \ No newline at end of file
+    ");
+    assert_snapshot!(c.text(&format!("text_diff({a}, {b}, NULL)")), @r"
+    @@ -1,8 +1,9 @@
+     a
+     b
+    -c
+    +C
+     d
+     e
+     f
+     g
+     h
+    +i
+    \ No newline at end of file
+    ");
+    assert_snapshot!(c.text("text_diff('', 'a')"), @r"
+    @@ -0,0 +1 @@
+    +a
+    \ No newline at end of file
+    ");
+    assert_snapshot!(c.text("text_diff('a\n', '')"), @r"
+    @@ -1 +0,0 @@
+    -a
+    ");
+    assert_snapshot!(c.text("text_diff('a\nb\n', 'a\nb\n')"), @"");
+    assert_snapshot!(c.text(&format!("text_patch({a}, text_diff({a}, {b}))")), @r"
+    a
+    b
+    C
+    d
+    e
+    f
+    g
+    h
+    i
+    ");
+    assert_snapshot!(c.text(&format!("text_patch({a}, text_diff({a}, {b}, 0))")), @r"
+    a
+    b
+    C
+    d
+    e
+    f
+    g
+    h
+    i
+    ");
+    assert_snapshot!(c.text("text_patch('', text_diff('', ''))"), @"");
+    assert_snapshot!(c.q("text_patch(x'00ff0a01', text_diff(x'00ff0a01', x'00fe0a01'))"), @"00fe0a01");
+    assert_snapshot!(c.text(&format!("typeof(text_diff({a}, {b})) || typeof(text_patch({a}, text_diff({a}, {b})))")), @"texttext");
+    assert_snapshot!(c.text("typeof(text_diff(x'00ff0a01', x'00fe0a01'))"), @"blob");
+}
+
+#[test]
+#[cfg(feature = "text")]
+fn text_fuzz() {
+    let c = Conn::default();
+    let a = "'a\nb\nc\nd\ne\nf\ng\nh\n'";
+    let b = "'a\nb\nC\nd\ne\nf\ng\nh\ni'";
+    // offset: the hunk is found even if the lines moved
+    assert_snapshot!(c.text(&format!("CAST(text_patch('0\n1\n' || {a}, text_diff({a}, {b})) AS TEXT)")), @r"
+    0
+    1
+    a
+    b
+    C
+    d
+    e
+    f
+    g
+    h
+    i
+    ");
+    // fuzz: up to two context lines at each end of a hunk may differ
+    assert_snapshot!(c.text(&format!("CAST(text_patch('A\nb\nc\nd\ne\nf\ng\nh\n', text_diff({a}, {b})) AS TEXT)")), @r"
+    A
+    b
+    C
+    d
+    e
+    f
+    g
+    h
+    i
+    ");
+    assert_snapshot!(c.text(&format!("CAST(text_patch('a\nB\nc\nd\ne\nf\ng\nh\n', text_diff({a}, {b})) AS TEXT)")), @r"
+    a
+    B
+    C
+    d
+    e
+    f
+    g
+    h
+    i
+    ");
+    // unified diff with a file name header, as created by `diff -u`
+    assert_snapshot!(c.text("CAST(text_patch('a\nb\n', '--- old.txt\n+++ new.txt\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n') AS TEXT)"), @r"
+    a
+    c
+    ");
+}
+
+#[test]
+#[cfg(feature = "text")]
+fn text_test_chain() {
+    let c = Conn::default();
+    let a = "'a\nb\nc\nd\ne\nf\ng\nh\n'";
+    let b = "'a\nb\nC\nd\ne\nf\ng\nh\ni'";
+    // testing
+    assert_snapshot!(c.bool("text_patch", &format!("%_test(text_diff({a}, {b}))")), @"true");
+    assert_snapshot!(c.bool("text_patch", &format!("%_test({a}, text_diff({a}, {b}))")), @"true");
+    assert_snapshot!(c.bool("text_patch", "%_test(text_diff('', ''))"), @"true");
+    assert_snapshot!(c.bool("text_patch", &format!("%_test('x\ny\nz\n', text_diff({a}, {b}))")), @"false");
+    assert_snapshot!(c.bool("text_patch", "%_test('@@ -1 +1 @@\n-a\n')"), @"false");
+    assert_snapshot!(c.bool("text_patch", "%_test('@@ -1 +1 @@\n-a\n+b\n')"), @"true");
+    assert_snapshot!(c.bool("text_patch", "%_test('@@ -1 +1 @@\n-a\n+b\nc\n')"), @"false");
+    assert_snapshot!(c.bool("text_patch", "%_test(x'0123456789abcdef')"), @"false");
+    assert_snapshot!(c.bool("text_patch", "%_test(NULL)"), @"NULL");
+    assert_snapshot!(c.bool("text_patch", &format!("%_test(NULL, text_diff({a}, {b}))")), @"NULL");
+
+    // chain
+    let versions = "SELECT 1 AS v, 'a\nb\n' AS d UNION SELECT 2, 'a\nb\nc\n' UNION SELECT 3, 'b\nc\nd' UNION SELECT 4, NULL";
+    let patches = format!(
+        "SELECT v, text_diff(lag(d) OVER (ORDER BY v), d) AS p FROM ({versions}) ORDER BY v"
+    );
+    assert_snapshot!(c.text(&format!("CAST(text_patch_chain('a\nb\n', p) AS TEXT) FROM ({patches})")), @r"
+    b
+    c
+    d
+    ");
+    assert_snapshot!(c.text(&format!("CAST(text_patch_chain('a\nb\n', p) AS TEXT) FROM ({patches}) WHERE v < 3")), @r"
+    a
+    b
+    c
+    ");
+
+    // nulls
+    assert_snapshot!(c.q("text_diff(NULL, 'abc')"), @"NULL");
+    assert_snapshot!(c.q("text_diff('abc', NULL)"), @"NULL");
+    assert_snapshot!(c.q("text_patch('abc', NULL)"), @"NULL");
+
+    // errors
+    assert_snapshot!(c.q("text_diff('abc')"), @"wrong number of arguments to function text_diff()");
+    assert_snapshot!(c.q("text_diff('abc', 'abd', -1)"), @"The optional third argument to text_diff() must be a non-negative number of lines");
+    assert_snapshot!(c.q("text_diff('abc', 'abd', 'all')"), @"Invalid function parameter type Text at index 2");
+    assert_snapshot!(c.q("text_patch('abc', 'not a diff')"), @"not a valid unified diff");
+    assert_snapshot!(c.q(&format!("text_patch('x\ny\nz\n', text_diff({a}, {b}))")), @"text_patch() failed to apply hunk #1");
+}
+
+#[test]
+#[cfg(feature = "text")]
+fn text_merge() {
+    let c = Conn::default();
+    let base = "'a\nb\nc\nd\ne\n'";
+    assert_snapshot!(c.text(&format!("text_merge({base}, 'A\nb\nc\nd\ne\n', 'a\nb\nc\nd\nE\n')")), @r"
+    A
+    b
+    c
+    d
+    E
+    ");
+    assert_snapshot!(c.text(&format!("text_merge({base}, 'a\nb\nc\nd\ne\n', 'x\na\nc\nd\ne\nz')")), @r"
+    x
+    a
+    c
+    d
+    e
+    z
+    ");
+    assert_snapshot!(c.text(&format!("text_merge({base}, 'a\nB\nc\nd\ne\n', 'a\nB\nc\nd\ne\n')")), @r"
+    a
+    B
+    c
+    d
+    e
+    ");
+    assert_snapshot!(c.text(&format!("text_merge({base}, 'a\nb\nX\nd\ne\n', 'a\nb\nY\nd\ne\n')")), @r"
+    a
+    b
+    <<<<<<< ours
+    X
+    ||||||| base
+    c
+    =======
+    Y
+    >>>>>>> theirs
+    d
+    e
+    ");
+    // adjacent changes conflict, same as diff3
+    assert_snapshot!(c.text(&format!("text_merge({base}, 'a\nB\nc\nd\ne\n', 'a\nb\nC\nd\ne\n')")), @r"
+    a
+    <<<<<<< ours
+    B
+    c
+    ||||||| base
+    b
+    c
+    =======
+    b
+    C
+    >>>>>>> theirs
+    d
+    e
+    ");
+    assert_snapshot!(c.text(&format!("text_merge({base}, 'a\nb\nc\nd\ne\nx', 'a\nb\nc\nd\ne\ny')")), @r"
+    a
+    b
+    c
+    d
+    e
+    <<<<<<< ours
+    x
+    ||||||| base
+    =======
+    y
+    >>>>>>> theirs
+    ");
+    assert_snapshot!(c.text(&format!("text_merge({base}, 'a\nb\nd\ne\n', {base})")), @r"
+    a
+    b
+    d
+    e
+    ");
+    assert_snapshot!(c.text("text_merge('', '', '')"), @"");
+    assert_snapshot!(c.text("text_merge('', 'a', 'b')"), @r"
+    <<<<<<< ours
+    a
+    ||||||| base
+    =======
+    b
+    >>>>>>> theirs
+    ");
+
+    // conflicts
+    assert_snapshot!(c.text(&format!("text_merge_conflicts({base}, 'A\nb\nc\nd\ne\n', 'a\nb\nc\nd\nE\n')")), @"[]");
+    assert_snapshot!(c.text(&format!("text_merge_conflicts({base}, 'a\nb\nX\nd\ne\n', 'a\nb\nY\nd\ne\n')")), @r#"[{"line":3,"base":"c\n","ours":"X\n","theirs":"Y\n"}]"#);
+    assert_snapshot!(c.text(&format!("text_merge_conflicts({base}, 'a\nB\nc\nd\ne\nx', 'a\nb\nc\nd\ne\n\"\t\\')")), @r#"[{"line":6,"base":"","ours":"x","theirs":"\"\t\\"}]"#);
+    assert_snapshot!(c.text("text_merge_conflicts('', x'ff', x'01')"), @r#"[{"line":1,"base":"","ours":"�","theirs":"\u0001"}]"#);
+    assert_snapshot!(c.text(&format!("typeof(text_merge_conflicts({base}, {base}, {base}))")), @"text");
+
+    // nulls
+    assert_snapshot!(c.q("text_merge(NULL, 'a', 'b')"), @"NULL");
+    assert_snapshot!(c.q("text_merge('a', NULL, 'b')"), @"NULL");
+    assert_snapshot!(c.text("text_merge_conflicts('a', 'b', NULL)"), @"NULL");
+
+    // errors
+    assert_snapshot!(c.q("text_merge('a', 'b')"), @"wrong number of arguments to function text_merge()");
+    assert_snapshot!(c.q("text_merge('a', 'b', 1)"), @"Invalid function parameter type Integer at index 2");
+    assert_snapshot!(c.text(&format!("typeof(text_merge({base}, {base}, 'x'))")), @"text");
+    assert_snapshot!(c.text("typeof(text_merge(x'ff', x'ff', x'fe'))"), @"blob");
+}
+
+#[test]
+#[cfg(feature = "vcdiff")]
+fn vcdiff() {
+    let c = Conn::default();
+    assert_snapshot!(c.q("vcdiff_diff('', '')"), @"d6c3c4000000050000000000");
+    assert_snapshot!(c.q("vcdiff_diff('a', '')"), @"d6c3c40000010100050000000000");
+    assert_snapshot!(c.q("vcdiff_diff('123456789', '123456789')"), @"d6c3c400000109000709000001017900");
+    assert_snapshot!(c.q("vcdiff_diff('1234', '5678349A')"), @"d6c3c400000104000e0800080100353637383334394109");
+    assert_snapshot!(c.q("vcdiff_diff('', 'abcabcabcabc')"), @"d6c3c40000000b0c00030201616263047900");
+    assert_snapshot!(c.q("vcdiff_diff(x'00', zeroblob(20))"), @"d6c3c400000101000a14000103010002131301");
+
+    assert_snapshot!(c.q("vcdiff_patch('', vcdiff_diff('', ''))"), @"");
+    assert_snapshot!(c.q("vcdiff_patch('a', vcdiff_diff('a', ''))"), @"");
+    assert_snapshot!(c.q("vcdiff_patch('123456789', vcdiff_diff('123456789', '123456789'))"), @"313233343536373839");
+    assert_snapshot!(c.q("vcdiff_patch('1234', vcdiff_diff('1234', '5678349A'))"), @"3536373833343941");
+    assert_snapshot!(c.q("vcdiff_patch('', vcdiff_diff('', 'abcabcabcabc'))"), @"616263616263616263616263");
+    assert_snapshot!(c.bool("vcdiff_patch", "%(x'00', vcdiff_diff(x'00', zeroblob(20))) = zeroblob(20)"), @"true");
+    // produced by `xdelta3 -e -S none -s source target patch`, including the application header and checksum
+    assert_snapshot!(c.q("vcdiff_patch('hello world', x'd6c3c40004106877322e62696e2f2f68772e62696e2f0506001b15000f0201572a07f76272617665206e657720776f726c64161000')"), @"68656c6c6f206272617665206e657720776f726c64");
+
+    // testing
+    assert_snapshot!(c.bool("vcdiff_patch", "%_test(vcdiff_diff('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("vcdiff_patch", "%_test('1234', vcdiff_diff('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("vcdiff_patch", "%_test(vcdiff_diff('', ''))"), @"true");
+    assert_snapshot!(c.bool("vcdiff_patch", "%_test('', vcdiff_diff('123456789', '123456789'))"), @"false");
+    assert_snapshot!(c.bool("vcdiff_patch", "%_test(substr(vcdiff_diff('1234', '5678349A'), 1, 10))"), @"false");
+    assert_snapshot!(c.bool("vcdiff_patch", "%_test(x'0123456789abcdef')"), @"false");
+    assert_snapshot!(c.bool("vcdiff_patch", "%_test(NULL)"), @"NULL");
+    assert_snapshot!(c.bool("vcdiff_patch", "%_test(NULL, vcdiff_diff('1234', '5678349A'))"), @"NULL");
+    assert_snapshot!(c.bool("vcdiff_patch", "%_test()"), @"Wrong number of parameters passed to query. Got 0, needed 1");
+
+    // chain
+    let versions = "SELECT 1 AS v, 'abc' AS d UNION SELECT 2, 'abc013479zz' UNION SELECT 3, 'abc23456789zzf' UNION SELECT 4, NULL";
+    let patches = format!(
+        "SELECT v, vcdiff_diff(lag(d) OVER (ORDER BY v), d) AS p FROM ({versions}) ORDER BY v"
+    );
+    assert_snapshot!(c.q(&format!("vcdiff_patch_chain('abc', p) FROM ({patches})")), @"61626332333435363738397a7a66");
+    assert_snapshot!(c.q(&format!("vcdiff_patch_chain('abc', p) FROM ({patches}) WHERE v < 3")), @"6162633031333437397a7a");
+    assert_snapshot!(c.q(&format!("vcdiff_patch_chain(NULL, p) FROM ({patches})")), @"NULL");
+
+    // nulls
+    assert_snapshot!(c.q("vcdiff_diff(NULL, 'abc')"), @"NULL");
+    assert_snapshot!(c.q("vcdiff_patch('abc', NULL)"), @"NULL");
+
+    // errors
+    assert_snapshot!(c.q("vcdiff_diff(x'0123')"), @"wrong number of arguments to function vcdiff_diff()");
+    assert_snapshot!(c.q("vcdiff_patch('abc', x'0123')"), @"not a valid VCDIFF patch");
+    assert_snapshot!(c.q("vcdiff_patch('abc', vcdiff_diff('abcd', 'abcdabcd'))"), @"not a valid VCDIFF patch");
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn zstd() {
+    let c = Conn::default();
+    assert_snapshot!(c.q("zstd_patch('', zstd_diff('', ''))"), @"");
+    assert_snapshot!(c.q("zstd_patch('a', zstd_diff('a', ''))"), @"");
+    assert_snapshot!(c.q("zstd_patch('123456789', zstd_diff('123456789', '123456789'))"), @"313233343536373839");
+    assert_snapshot!(c.q("zstd_patch('1234', zstd_diff('1234', '5678349A'))"), @"3536373833343941");
+    assert_snapshot!(c.q("zstd_patch(x'1234', zstd_diff(x'1234', x'5678349A', 19))"), @"5678349a");
+    assert_snapshot!(c.q("zstd_patch(x'1234', zstd_diff(x'1234', x'5678349A', -5))"), @"5678349a");
+    assert_snapshot!(c.q("zstd_patch(x'1234', zstd_diff(x'1234', x'5678349A', NULL))"), @"5678349a");
+    // the source is referenced instead of being stored in the patch
+    assert_snapshot!(c.bool("zstd_diff", "length(%(randomblob(10000), randomblob(10000))) > 10000"), @"true");
+    assert_snapshot!(c.bool("zstd_diff", "(SELECT length(%(b, b || x'00')) < 100 FROM (SELECT randomblob(10000) AS b))"), @"true");
+
+    // testing
+    assert_snapshot!(c.bool("zstd_patch", "%_test(zstd_diff('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("zstd_patch", "%_test('1234', zstd_diff('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("zstd_patch", "%_test(zstd_diff('', ''))"), @"true");
+    assert_snapshot!(c.bool("zstd_patch", "%_test('', zstd_diff('abcdefghijklmnop', 'abcdefghijklmnop!'))"), @"false");
+    assert_snapshot!(c.bool("zstd_patch", "%_test(substr(zstd_diff('1234', '5678349A'), 1, 10))"), @"false");
+    assert_snapshot!(c.bool("zstd_patch", "%_test(x'0123456789abcdef')"), @"false");
+    assert_snapshot!(c.bool("zstd_patch", "%_test(NULL)"), @"NULL");
+    assert_snapshot!(c.bool("zstd_patch", "%_test(NULL, zstd_diff('1234', '5678349A'))"), @"NULL");
+
+    // chain
+    let versions = "SELECT 1 AS v, 'abc' AS d UNION SELECT 2, 'abc013479zz' UNION SELECT 3, 'abc23456789zzf' UNION SELECT 4, NULL";
+    let patches = format!(
+        "SELECT v, zstd_diff(lag(d) OVER (ORDER BY v), d) AS p FROM ({versions}) ORDER BY v"
+    );
+    assert_snapshot!(c.q(&format!("zstd_patch_chain('abc', p) FROM ({patches})")), @"61626332333435363738397a7a66");
+    assert_snapshot!(c.q(&format!("zstd_patch_chain('abc', p) FROM ({patches}) WHERE v < 3")), @"6162633031333437397a7a");
+
+    // nulls
+    assert_snapshot!(c.q("zstd_diff(NULL, 'abc')"), @"NULL");
+    assert_snapshot!(c.q("zstd_diff(NULL, 'abc', 3)"), @"NULL");
+    assert_snapshot!(c.q("zstd_patch('abc', NULL)"), @"NULL");
+
+    // errors
+    assert_snapshot!(c.q("zstd_diff(x'0123')"), @"wrong number of arguments to function zstd_diff()");
+    assert_snapshot!(c.q("zstd_diff(x'0123', x'4567', 1, 2)"), @"wrong number of arguments to function zstd_diff()");
+    assert_snapshot!(c.q("zstd_diff(x'0123', x'4567', 23)"), @"The optional third argument to zstd_diff() must be between -131072 and 22");
+    assert_snapshot!(c.q("zstd_diff(x'0123', x'4567', 'fast')"), @"Invalid function parameter type Text at index 2");
+    assert_snapshot!(c.q("zstd_patch('abc', x'0123')"), @"Unknown frame descriptor");
+    assert_snapshot!(c.q("zstd_patch('', zstd_diff('abcdefghijklmnop', 'abcdefghijklmnop!'))"), @"Data corruption detected");
+}
+
+#[test]
+#[cfg(feature = "fossil")]
+fn fossil() {
+    let c = Conn::default();
+    assert_snapshot!(c.text("CAST(delta_create('', '') AS TEXT)"), @r"
+    0
+    0:0;
+    ");
+    assert_snapshot!(c.text("CAST(delta_create('hello world', 'hello brave new world') AS TEXT)"), @r"
+    L
+    L:hello brave new world22pHrA;
+    ");
+    assert_snapshot!(c.text("CAST(delta_create('abcdefghijklmnopqrstuvwxyz', 'abcdefghijklmnopqrstuvwxyz!') AS TEXT)"), @r"
+    R
+    Q@0,1:!3z1B6K;
+    ");
+    assert_snapshot!(c.q("delta_apply('', delta_create('', ''))"), @"");
+    assert_snapshot!(c.q("delta_apply('a', delta_create('a', ''))"), @"");
+    assert_snapshot!(c.q("delta_apply('123456789', delta_create('123456789', '123456789'))"), @"313233343536373839");
+    assert_snapshot!(c.q("delta_apply('1234', delta_create('1234', '5678349A'))"), @"3536373833343941");
+    assert_snapshot!(c.q("delta_apply(x'1234', delta_create(x'1234', x'5678349A'))"), @"5678349a");
+    assert_snapshot!(c.bool("delta_apply", "(WITH s(b) AS MATERIALIZED (SELECT randomblob(10000)), t(b, t) AS MATERIALIZED (SELECT b, CAST(substr(b, 100, 5000) || randomblob(10) || substr(b, 1, 3000) AS BLOB) FROM s) SELECT %(b, delta_create(b, t)) = t FROM t)"), @"true");
+    // the source is referenced instead of being stored in the delta
+    assert_snapshot!(c.bool("delta_create", "(SELECT length(%(b, b || x'00')) < 100 FROM (SELECT randomblob(10000) AS b))"), @"true");
+    assert_snapshot!(c.text("CAST(delta_output_size(delta_create('1234', '5678349A')) AS TEXT)"), @"8");
+    assert_snapshot!(c.text("CAST(delta_output_size(delta_create(randomblob(100), zeroblob(100000))) AS TEXT)"), @"100000");
+
+    // testing
+    assert_snapshot!(c.bool("delta_apply", "%_test(delta_create('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("delta_apply", "%_test('1234', delta_create('1234', '5678349A'))"), @"true");
+    assert_snapshot!(c.bool("delta_apply", "%_test(delta_create('', ''))"), @"true");
+    assert_snapshot!(c.bool("delta_apply", "%_test('', delta_create('abcdefghijklmnopqrstuvwxyz', 'abcdefghijklmnopqrstuvwxyz!'))"), @"false");
+    assert_snapshot!(c.bool("delta_apply", "%_test(substr(delta_create('1234', '5678349A'), 1, 10))"), @"false");
+    assert_snapshot!(c.bool("delta_apply", "%_test(x'0123456789abcdef')"), @"false");
+    assert_snapshot!(c.bool("delta_apply", "%_test(NULL)"), @"NULL");
+    assert_snapshot!(c.bool("delta_apply", "%_test(NULL, delta_create('1234', '5678349A'))"), @"NULL");
+
+    // chain
+    let versions = "SELECT 1 AS v, 'abc' AS d UNION SELECT 2, 'abc013479zz' UNION SELECT 3, 'abc23456789zzf' UNION SELECT 4, NULL";
+    let patches = format!(
+        "SELECT v, delta_create(lag(d) OVER (ORDER BY v), d) AS p FROM ({versions}) ORDER BY v"
+    );
+    assert_snapshot!(c.q(&format!("delta_apply_chain('abc', p) FROM ({patches})")), @"61626332333435363738397a7a66");
+    assert_snapshot!(c.q(&format!("delta_apply_chain('abc', p) FROM ({patches}) WHERE v < 3")), @"6162633031333437397a7a");
+
+    // nulls
+    assert_snapshot!(c.q("delta_create(NULL, 'abc')"), @"NULL");
+    assert_snapshot!(c.q("delta_apply('abc', NULL)"), @"NULL");
+    assert_snapshot!(c.text("CAST(delta_output_size(NULL) AS TEXT)"), @"NULL");
+
+    // errors
+    assert_snapshot!(c.q("delta_create(x'0123')"), @"wrong number of arguments to function delta_create()");
+    assert_snapshot!(c.q("delta_create(x'0123', x'4567', 1, 2)"), @"wrong number of arguments to function delta_create()");
+    assert_snapshot!(c.q("delta_apply('abc', x'0123')"), @"corrupt fossil delta");
+    assert_snapshot!(c.q("delta_apply('', delta_create('abcdefghijklmnopqrstuvwxyz', 'abcdefghijklmnopqrstuvwxyz!'))"), @"corrupt fossil delta");
+    assert_snapshot!(c.q("delta_apply('abc', '3\n3:abd1;')"), @"corrupt fossil delta");
+    assert_snapshot!(c.text("CAST(delta_output_size('abc') AS TEXT)"), @"corrupt fossil delta");
+}
+
+#[test]
+#[cfg(all(feature = "gzip", feature = "fossil"))]
+fn compression_error() {
+    use rusqlite::Error::UserFunctionError;
+    use sqlite_compressions::{
+        CompressionError, Differ as _, Encoder as _, FossilDiffer, GzipEncoder,
+    };
+
+    /// A reader with invalid input
+    struct FailingReader;
+    impl std::io::Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "bad input",
+            ))
+        }
+    }
+
+    let err = GzipEncoder::decode(b"not gzip").unwrap_err();
+    assert_eq!(err.algorithm(), "gzip");
+    assert_snapshot!(format!("{err:?}"), @r#"CorruptData { algorithm: "gzip", offset: None, message: "unexpected end of file" }"#);
+    let err = GzipEncoder::encode(b"abc", Some(10)).unwrap_err();
+    assert_snapshot!(format!("{err:?}"), @r#"InvalidLevel { algorithm: "gzip", message: "The optional second argument to gzip() must be between 0 and 9" }"#);
+    // Invalid input of a reader is an I/O error when encoding, and only corrupt data when decoding
+    let err = GzipEncoder::encode_stream(&mut FailingReader, Vec::new(), None).unwrap_err();
+    assert_snapshot!(format!("{err:?}"), @r#"Io { algorithm: "gzip", source: Custom { kind: InvalidInput, error: "bad input" } }"#);
+    let err = FossilDiffer::patch(b"abc", b"3\n3:abd1;").unwrap_err();
+    assert_snapshot!(format!("{err:?}"), @r#"CorruptData { algorithm: "fossil", offset: Some(8), message: "corrupt fossil delta" }"#);
+
+    // SQL functions only report the message
+    let c = Conn::default();
+    assert_eq!(c.q("delta_apply('abc', '3\n3:abd1;')"), err.to_string());
+
+    // Rust APIs that return a rusqlite error keep the original error
+    let UserFunctionError(err) = rusqlite::Error::from(err) else {
+        panic!("expected a user function error");
+    };
+    let err = err.downcast_ref::<CompressionError>().unwrap();
+    assert!(matches!(
+        err,
+        CompressionError::CorruptData {
+            algorithm: "fossil",
+            offset: Some(8),
+            ..
+        }
+    ));
+}
+
+#[rstest::rstest]
+#[cfg_attr(
+    feature = "bsdiff4",
+    case("bsdiff4", "bspatch4", "bspatch4_test", "bspatch4_chain")
+)]
+#[cfg_attr(
+    feature = "bsdiff4",
+    case("bsdiff43", "bspatch43", "bspatch43_test", "bspatch43_chain")
+)]
+#[cfg_attr(
+    feature = "bsdiffraw",
+    case("bsdiffraw", "bspatchraw", "bspatchraw_test", "bspatchraw_chain")
+)]
+#[cfg_attr(
+    feature = "fossil",
+    case("delta_create", "delta_apply", "delta_apply_test", "delta_apply_chain")
+)]
+#[cfg_attr(
+    feature = "json",
+    case("json_diff", "json_patch_apply", "json_patch_test", "json_patch_chain")
+)]
+#[cfg_attr(
+    feature = "json",
+    case(
+        "json_merge_diff",
+        "json_merge_patch",
+        "json_merge_patch_test",
+        "json_merge_patch_chain"
+    )
+)]
+#[cfg_attr(
+    feature = "rsync",
+    case("rsync_diff", "rsync_patch", "rsync_patch_test", "rsync_patch_chain")
+)]
+#[cfg_attr(
+    feature = "text",
+    case("text_diff", "text_patch", "text_patch_test", "text_patch_chain")
+)]
+#[cfg_attr(
+    feature = "vcdiff",
+    case(
+        "vcdiff_diff",
+        "vcdiff_patch",
+        "vcdiff_patch_test",
+        "vcdiff_patch_chain"
+    )
+)]
+#[cfg_attr(
+    feature = "zstd",
+    case("zstd_diff", "zstd_patch", "zstd_patch_test", "zstd_patch_chain")
+)]
+#[trace]
+#[test]
+#[cfg(any(
+    feature = "bsdiff4",
+    feature = "bsdiffraw",
+    feature = "fossil",
+    feature = "json",
+    feature = "rsync",
+    feature = "text",
+    feature = "vcdiff",
+    feature = "zstd"
+))]
+fn verified(#[case] diff: &str, #[case] patch: &str, #[case] test: &str, #[case] chain: &str) {
+    let c = Conn::default();
+    let q = |expr: &str| {
+        let expr = expr
+            .replace("%d", diff)
+            .replace("%p", patch)
+            .replace("%t", test)
+            .replace("%c", chain);
+        let sql = format!(
+            r#"(WITH s(a, b) AS (SELECT '{{"a":1,"b":"x"}}', '{{"a":2,"b":"x"}}'), v AS (SELECT a, b, %d(a, b, 'verified') AS v FROM s) SELECT {expr} FROM v)"#
+        )
+        .replace("%d", diff);
+        c.text(&format!("CAST({sql} AS TEXT)")).replace(patch, "%p")
+    };
+    insta::allow_duplicates!(
+        assert_snapshot!(q("hex(substr(v, 1, 8))"), @"53514C4344563100");
+        assert_snapshot!(q("CAST(%p(a, v) AS TEXT) = b"), @"1");
+        assert_snapshot!(q("%c(a, v)"), @r#"{"a":2,"b":"x"}"#);
+        assert_snapshot!(q("%t(v)"), @"1");
+        assert_snapshot!(q("%t(a, v)"), @"1");
+        assert_snapshot!(q("%t(b, v)"), @"0");
+        assert_snapshot!(q("%t(substr(v, 1, 40))"), @"0");
+
+        // The source is checked before patching, and the result after patching
+        assert_snapshot!(q("%p(b, v)"), @"%p(): the source does not match the one the verified patch was created for");
+        assert_snapshot!(q("%p(a, CAST(substr(v, 1, 40) || zeroblob(32) || substr(v, 73) AS BLOB))"), @"%p(): the result does not match the target of the verified patch");
+        assert_snapshot!(q("%t(a, CAST(substr(v, 1, 40) || zeroblob(32) || substr(v, 73) AS BLOB))"), @"0");
+        assert_snapshot!(q("%p(a, substr(v, 1, 40))"), @"%p(): the verified patch is truncated");
+
+        // Unverified patches still work
+        assert_snapshot!(q("CAST(%p(a, %d(a, b, NULL)) AS TEXT) = b"), @"1");
+    );
+}
+
+#[rstest::rstest]
+#[cfg_attr(feature = "bsdiff4", case("bsdiff4", "bspatch4_chain"))]
+#[cfg_attr(feature = "bsdiff4", case("bsdiff43", "bspatch43_chain"))]
+#[cfg_attr(feature = "bsdiffraw", case("bsdiffraw", "bspatchraw_chain"))]
+#[cfg_attr(feature = "fossil", case("delta_create", "delta_apply_chain"))]
+#[cfg_attr(feature = "json", case("json_diff", "json_patch_chain"))]
+#[cfg_attr(feature = "json", case("json_merge_diff", "json_merge_patch_chain"))]
+#[cfg_attr(feature = "rsync", case("rsync_diff", "rsync_patch_chain"))]
+#[cfg_attr(feature = "text", case("text_diff", "text_patch_chain"))]
+#[cfg_attr(feature = "vcdiff", case("vcdiff_diff", "vcdiff_patch_chain"))]
+#[cfg_attr(feature = "zstd", case("zstd_diff", "zstd_patch_chain"))]
+#[trace]
+#[test]
+#[cfg(any(
+    feature = "bsdiff4",
+    feature = "bsdiffraw",
+    feature = "fossil",
+    feature = "json",
+    feature = "rsync",
+    feature = "text",
+    feature = "vcdiff",
+    feature = "zstd"
+))]
+fn diff_prev(#[case] diff: &str, #[case] chain: &str) {
+    let c = Conn::default();
+    let prev = if diff == "delta_create" {
+        "delta_prev".to_string()
+    } else {
+        format!("{diff}_prev")
+    };
+    let history = r#"SELECT 1 AS k, 1 AS v, '{"a":1}' AS d UNION ALL SELECT 1, 2, '{"a":2}' UNION ALL SELECT 1, 3, '{"a":2,"b":[1]}'
+        UNION ALL SELECT 2, 1, '{"x":1}' UNION ALL SELECT 2, 2, NULL UNION ALL SELECT 2, 3, '{"x":3}' UNION ALL SELECT 2, 4, '{"x":4}'"#;
+    let q = |sql: &str| {
+        let sql = sql
+            .replace("%p", &prev)
+            .replace("%d", diff)
+            .replace("%c", chain);
+        c.text(&format!("CAST(({sql}) AS TEXT)"))
+            .replace(&prev, "%p")
+    };
+    insta::allow_duplicates!(
+        // Same as diffing against lag(), per partition, and NULL around NULL values
+        assert_snapshot!(q(&format!("SELECT group_concat(quote(p) IS quote(l), '') FROM (SELECT %p(d) OVER w AS p, %d(lag(d) OVER w, d) AS l FROM ({history}) WINDOW w AS (PARTITION BY k ORDER BY v))")), @"1111111");
+        assert_snapshot!(q(&format!("SELECT group_concat(p IS NULL, '') FROM (SELECT %p(d) OVER (PARTITION BY k ORDER BY v) AS p FROM ({history}) ORDER BY k, v)")), @"1001110");
+        // A sliding frame removes the older rows with the inverse function
+        assert_snapshot!(q(&format!("SELECT group_concat(quote(p) IS quote(l), '') FROM (SELECT %p(d) OVER (ORDER BY k, v ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) AS p, %d(lag(d) OVER (ORDER BY k, v), d) AS l FROM ({history}))")), @"1111111");
+        assert_snapshot!(q(&format!("SELECT group_concat(p IS NULL, '') FROM (SELECT %p(d) OVER (ORDER BY k, v ROWS BETWEEN CURRENT ROW AND CURRENT ROW) AS p FROM ({history}))")), @"1111111");
+        // The deltas rebuild the last version
+        assert_snapshot!(q(&format!("SELECT %c(CASE v WHEN 1 THEN d END, p) FROM (SELECT v, %p(d) OVER (ORDER BY v) AS p, d FROM ({history}) WHERE k = 1 ORDER BY v)")), @r#"{"a":2,"b":[1]}"#);
+        // Ties in ORDER BY add several rows to the default frame at once, so the current row is unknown
+        assert_snapshot!(q(&format!("SELECT group_concat(p IS NULL, '') FROM (SELECT %p(d) OVER (ORDER BY v) AS p FROM ({history}))")), @"%p() must be used as a window function whose frame ends at the current row, without ties in ORDER BY, e.g. OVER (ORDER BY version)");
+        assert_snapshot!(q(&format!("SELECT group_concat(quote(p) IS quote(l), '') FROM (SELECT %p(d) OVER (ORDER BY v ROWS 1 PRECEDING) AS p, %d(lag(d) OVER (ORDER BY v), d) AS l FROM ({history}))")), @"1111111");
+        // Frames that do not end at the current row
+        assert_snapshot!(q(&format!("SELECT group_concat(p IS NULL, '') FROM (SELECT %p(d) OVER (ORDER BY k, v ROWS BETWEEN CURRENT ROW AND 1 FOLLOWING) AS p FROM ({history}))")), @"%p() must be used as a window function whose frame ends at the current row, without ties in ORDER BY, e.g. OVER (ORDER BY version)");
+        assert_snapshot!(q(&format!("SELECT group_concat(p IS NULL, '') FROM (SELECT %p(d) OVER (ORDER BY k, v ROWS BETWEEN 2 PRECEDING AND 1 PRECEDING) AS p FROM ({history}))")), @"%p() must be used as a window function whose frame ends at the current row, without ties in ORDER BY, e.g. OVER (ORDER BY version)");
+        assert_snapshot!(q(&format!("SELECT group_concat(p IS NULL, '') FROM (SELECT %p(d) OVER (ORDER BY k, v ROWS BETWEEN 1 PRECEDING AND CURRENT ROW EXCLUDE TIES) AS p FROM ({history}))")), @"%p() must be used as a window function whose frame ends at the current row, without ties in ORDER BY, e.g. OVER (ORDER BY version)");
+        // Not as a plain aggregate
+        assert_snapshot!(q(&format!("SELECT %p(d) FROM ({history}) WHERE k = 1")), @"%p() must be used as a window function whose frame ends at the current row, without ties in ORDER BY, e.g. OVER (ORDER BY version)");
+        assert_snapshot!(q("SELECT %p('{}') WHERE 0"), @"NULL");
+        assert_snapshot!(q("SELECT %p('{}', '{}')"), @"wrong number of arguments to function %p()");
+    );
+}
+
+#[test]
+#[cfg(feature = "cdc")]
+fn cdc() {
+    let c = Conn::default();
+    c.0.execute_batch(
+        "CREATE TABLE t(id INTEGER PRIMARY KEY, b BLOB);
+         INSERT INTO t VALUES (1, randomblob(200000));
+         INSERT INTO t SELECT 2, CAST(substr(b, 1, 100000) || 'inserted' || substr(b, 100001) AS BLOB) FROM t;
+         CREATE TABLE chunks(id, seq, hash, data);
+         INSERT INTO chunks SELECT t.id, key, value->>'hash', substr(b, value->>'offset' + 1, value->>'length')
+           FROM t, json_each(cdc_chunks(b, 1024, 8192, 65536));",
+    )
+    .unwrap();
+
+    assert_snapshot!(c.text("cdc_chunks(zeroblob(3000), 1024, 1024, 1024)"), @r#"[{"offset":0,"length":1024,"hash":"5F70BF18A086007016E948B04AED3B82103A36BEA41755B6CDDFAF10ACE3C6EF"},{"offset":1024,"length":1024,"hash":"5F70BF18A086007016E948B04AED3B82103A36BEA41755B6CDDFAF10ACE3C6EF"},{"offset":2048,"length":952,"hash":"F438A4E713DF6A982AFBE2EEC993CD582EDC37A876FEE88E1DDABB478F2B5EE0"}]"#);
+    assert_snapshot!(c.text("cdc_chunks(x'', 64, 256, 1024)"), @"[]");
+    assert_snapshot!(c.text("cdc_chunks(CAST('abc' AS BLOB), 64, 256, 1024)"), @r#"[{"offset":0,"length":3,"hash":"BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD"}]"#);
+    // The hashes are SHA-256 of the chunk content
+    assert_snapshot!(c.text("CAST(count(*) AS TEXT) FROM chunks WHERE length(hash) != 64"), @"0");
+    // Chunks around the insertion change, the others are shared
+    assert_snapshot!(c.text("CAST(count(*) BETWEEN 1 AND 3 AS TEXT) FROM chunks WHERE id = 2 AND hash NOT IN (SELECT hash FROM chunks WHERE id = 1)"), @"1");
+    assert_snapshot!(c.text("CAST(count(*) > 10 AS TEXT) FROM chunks WHERE id = 2"), @"1");
+    assert_snapshot!(c.text("CAST(cdc_reassemble(data) = (SELECT b FROM t WHERE id = 2) AS TEXT) FROM (SELECT data FROM chunks WHERE id = 2 ORDER BY seq)"), @"1");
+    assert_snapshot!(c.text("CAST(cdc_reassemble(data) = (SELECT b FROM t WHERE id = 1) AS TEXT) FROM (SELECT data FROM chunks WHERE id = 1 ORDER BY seq)"), @"1");
+    // Text is chunked as a blob, so that substr() counts bytes like the offsets
+    assert_snapshot!(c.text("CAST(cdc_reassemble(chunk) = CAST(replace(hex(zeroblob(2000)), '00', 'é') AS BLOB) AS TEXT) FROM (SELECT substr(b, value->>'offset' + 1, value->>'length') AS chunk FROM (SELECT CAST(replace(hex(zeroblob(2000)), '00', 'é') AS BLOB) AS b), json_each(cdc_chunks(b, 64, 256, 1024)) ORDER BY key)"), @"1");
+    // An empty blob has no chunks
+    assert_snapshot!(c.text("quote(cdc_reassemble(x)) FROM (SELECT x'01' AS x) WHERE 0"), @"X''");
+
+    // nulls
+    assert_snapshot!(c.q("cdc_chunks(NULL, 64, 256, 1024)"), @"NULL");
+    assert_snapshot!(c.q("cdc_reassemble(x) FROM (SELECT x'01' AS x UNION ALL SELECT NULL)"), @"NULL");
+    assert_snapshot!(c.q("cdc_reassemble(x) FROM (SELECT x'01' AS x UNION ALL SELECT 'a')"), @"0161");
+
+    // errors
+    assert_snapshot!(c.q("cdc_chunks(x'61', 63, 256, 1024)"), @"cdc_chunks() min size must be between 64 and 1048576, got 63");
+    assert_snapshot!(c.q("cdc_chunks(x'61', 64, 256, 16777218)"), @"cdc_chunks() max size must be between 1024 and 16777216, got 16777218");
+    assert_snapshot!(c.q("cdc_chunks(x'61', 64, -1, 1024)"), @"cdc_chunks() avg size must be between 256 and 4194304, got -1");
+    assert_snapshot!(c.q("cdc_chunks(x'61', 4096, 2048, 8192)"), @"cdc_chunks() sizes must be ordered as min <= avg <= max");
+    assert_snapshot!(c.q("cdc_chunks(1, 64, 256, 1024)"), @"Invalid function parameter type Integer at index 0");
+    assert_snapshot!(c.q("cdc_chunks('é', 64, 256, 1024)"), @"cdc_chunks() offsets are in bytes, so text must be cast to a blob first, e.g. cdc_chunks(CAST(t AS BLOB), ...)");
+    assert_snapshot!(c.q("cdc_chunks(x'61', 64, 256)"), @"wrong number of arguments to function cdc_chunks()");
+    assert_snapshot!(c.q("cdc_reassemble(1)"), @"Invalid function parameter type Integer at index 0");
+}
+
+#[rstest::rstest]
+#[cfg_attr(feature = "gzip", case("gzip"))]
+#[cfg_attr(feature = "brotli", case("brotli"))]
+#[cfg_attr(feature = "bzip2", case("bzip2"))]
+#[cfg_attr(feature = "zstd", case("zstd"))]
+#[trace]
+#[test]
+#[cfg(any(
+    feature = "brotli",
+    feature = "bzip2",
+    feature = "gzip",
+    feature = "zstd"
+))]
+fn ncd(#[case] algo: &str) {
+    let c = Conn::default();
+    c.0.execute_batch(
+        "CREATE TABLE docs(id INTEGER PRIMARY KEY, doc);
+         INSERT INTO docs VALUES
+           (1, 'the quick brown fox jumps over the lazy dog, again and again, until the dog wakes up'),
+           (2, 'the quick brown fox jumped over the lazy dog, again and again, until the dog woke up'),
+           (3, 'lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor');",
+    )
+    .unwrap();
+    let q = |sql: &str| c.text(&format!("CAST(({}) AS TEXT)", sql.replace('%', algo)));
+    insta::allow_duplicates!(
+        // The closest document to each one in a self-join
+        assert_snapshot!(q("SELECT group_concat(b, ',') FROM (SELECT y.id AS b, min(ncd(x.doc, y.doc, '%')) FROM docs x, docs y WHERE x.id != y.id AND x.id IN (1, 2) GROUP BY x.id ORDER BY x.id)"), @"2,1");
+        assert_snapshot!(q("SELECT ncd(x.doc, y.doc, '%') BETWEEN 0 AND 1.1 FROM docs x, docs y WHERE x.id = 1 AND y.id = 3"), @"1");
+        assert_snapshot!(q("SELECT ncd(x.doc, y.doc, '%', 1) BETWEEN 0 AND 1.1 FROM docs x, docs y WHERE x.id = 1 AND y.id = 3"), @"1");
+        assert_snapshot!(q("SELECT typeof(ncd('', '', '%'))"), @"real");
+        // Identical values are close as long as they fit in the window of the compressor
+        assert_snapshot!(q("SELECT ncd(x, x, '%') < 0.3 FROM (SELECT randomblob(20000) AS x)"), @"1");
+        assert_snapshot!(q("SELECT ncd(x'00', NULL, '%')"), @"NULL");
+        assert_snapshot!(q("SELECT ncd(NULL, x'00', '%')"), @"NULL");
+        assert_snapshot!(q("SELECT ncd(1, x'00', '%')"), @"Invalid function parameter type Integer at index 0");
+        assert_snapshot!(q("SELECT ncd('a', 'b', 'zip')"), @"Unknown compression algorithm 'zip'");
+        assert_snapshot!(q("SELECT ncd('a', 'b')"), @"Wrong number of parameters passed to query. Got 2, needed 3");
+    );
+    if algo == "gzip" {
+        // Beyond the 32 KiB window, identical values look unrelated
+        assert_snapshot!(q("SELECT ncd(x, x, '%') > 0.9 FROM (SELECT randomblob(100000) AS x)"), @"1");
+    }
+}
+
+#[test]
+#[cfg(all(feature = "brotli", feature = "bzip2", feature = "gzip"))]
+fn recompress() {
+    let c = Conn::default();
+    assert_snapshot!(c.q("recompress(gzip('hello'), 'gzip', 'brotli')"), @"0b028068656c6c6f03");
+    assert_snapshot!(c.q("recompress(gzip('hello'), 'GZIP', 'Brotli', 1)"), @"0b028068656c6c6f03");
+    assert_snapshot!(c.q("recompress(brotli('hello'), 'brotli', 'gzip', 9)"), @"1f8b08000000000002ffcb48cdc9c9070086a6103605000000");
+    assert_snapshot!(c.q("recompress(bzip2(x'0123'), 'bzip2', 'bzip2', 1)"), @"425a6831314159265359b831ca670000005000200008002000210082b177245385090b831ca670");
+    assert_snapshot!(c.q("gzip_decode(recompress(bzip2('hello'), 'bzip2', 'gzip'))"), @"68656c6c6f");
+
+    // nulls
+    assert_snapshot!(c.q("recompress(NULL, 'gzip', 'brotli')"), @"NULL");
+
+    // errors
+    assert_snapshot!(c.q("recompress(gzip('hello'), 'gzip')"), @"Wrong number of parameters passed to query. Got 2, needed 3");
+    assert_snapshot!(c.q("recompress(gzip('hello'), 'gzip', 'brotli', 1, 2)"), @"Wrong number of parameters passed to query. Got 5, needed 3");
+    assert_snapshot!(c.q("recompress('hello', 'gzip', 'brotli')"), @"Invalid function parameter type Text at index 0");
+    assert_snapshot!(c.q("recompress(gzip('hello'), 'zip', 'brotli')"), @"Unknown compression algorithm 'zip'");
+    assert_snapshot!(c.q("recompress(gzip('hello'), 'gzip', 'zip')"), @"Unknown compression algorithm 'zip'");
+    assert_snapshot!(c.q("recompress(gzip('hello'), 'brotli', 'gzip')"), @"Invalid Data");
+    assert_snapshot!(c.q("recompress(gzip('hello'), 'gzip', 'bzip2', 10)"), @"The optional second argument to bzip2() must be between 1 and 9");
+}
+
+#[test]
+#[cfg(all(
+    feature = "gzip",
+    feature = "brotli",
+    feature = "bsdiff4",
+    feature = "bsdiffraw",
+    feature = "text"
+))]
+fn compressed_diff() {
+    let c = Conn::default();
+    assert_snapshot!(c.bool("compressed_diff", "%(gzip('013479'), gzip('23456789'), 'gzip', 'bsdiff4') = bsdiff4('013479', '23456789')"), @"true");
+    assert_snapshot!(c.bool("compressed_diff", "compressed_patch(gzip('013479'), %(gzip('013479'), gzip('23456789'), 'gzip', 'bsdiff4'), 'gzip', 'bsdiff4') = gzip('23456789')"), @"true");
+    assert_snapshot!(c.bool("compressed_diff", "compressed_patch(brotli('013479', 9), %(brotli('013479', 9), brotli('23456789', 9), 'Brotli', 'BSDIFFRAW'), 'brotli', 'bsdiffraw', 9) = brotli('23456789', 9)"), @"true");
+    assert_snapshot!(c.text("CAST(compressed_diff(gzip('a\nb\n'), gzip('a\nc\n'), 'gzip', 'text_diff') AS TEXT)"), @r"
+    @@ -1,2 +1,2 @@
+     a
+    -b
+    +c
+    ");
+    assert_snapshot!(c.q("gzip_decode(compressed_patch(gzip('a\nb\n'), text_diff('a\nb\n', 'a\nc\n'), 'gzip', 'text_diff'))"), @"610a630a");
+
+    // The patch of the decompressed contents is much smaller
+    let blobs = "WITH s(a) AS MATERIALIZED (SELECT hex(randomblob(5000))), v(a, b) AS (SELECT gzip(a), gzip(substr(a, 1, 5000) || 'changed' || substr(a, 5001)) FROM s)";
+    assert_snapshot!(c.bool("compressed_diff", &format!("({blobs} SELECT length(%(a, b, 'gzip', 'bsdiff4')) * 10 < length(bsdiff4(a, b)) FROM v)")), @"true");
+    assert_snapshot!(c.bool("compressed_diff", &format!("({blobs} SELECT compressed_patch(a, %(a, b, 'gzip', 'bsdiff4'), 'gzip', 'bsdiff4') = b FROM v)")), @"true");
+
+    // verified patches
+    assert_snapshot!(c.bool("compressed_diff", "compressed_patch(gzip('013479'), bsdiffraw('013479', '23456789', 'verified'), 'gzip', 'bsdiffraw') = gzip('23456789')"), @"true");
+    assert_snapshot!(c.q("compressed_patch(gzip('01347'), bsdiffraw('013479', '23456789', 'verified'), 'gzip', 'bsdiffraw')"), @"bspatchraw(): the source does not match the one the verified patch was created for");
+
+    // nulls
+    assert_snapshot!(c.q("compressed_diff(NULL, gzip('a'), 'gzip', 'bsdiff4')"), @"NULL");
+    assert_snapshot!(c.q("compressed_diff(gzip('a'), NULL, 'gzip', 'bsdiff4')"), @"NULL");
+    assert_snapshot!(c.q("compressed_patch(NULL, x'00', 'gzip', 'bsdiff4')"), @"NULL");
+    assert_snapshot!(c.q("compressed_patch(gzip('a'), NULL, 'gzip', 'bsdiff4')"), @"NULL");
+
+    // errors
+    assert_snapshot!(c.q("compressed_diff(gzip('a'), gzip('b'), 'zip', 'bsdiff4')"), @"Unknown compression algorithm 'zip'");
+    assert_snapshot!(c.q("compressed_diff(gzip('a'), gzip('b'), 'gzip', 'bspatch4')"), @"Unknown diff function 'bspatch4'");
+    assert_snapshot!(c.q("compressed_patch(gzip('a'), x'00', 'zip', 'bsdiff4')"), @"Unknown compression algorithm 'zip'");
+    assert_snapshot!(c.q("compressed_patch(gzip('a'), x'00', 'gzip', 'diff')"), @"Unknown diff function 'diff'");
+    assert_snapshot!(c.q("compressed_diff('a', gzip('b'), 'gzip', 'bsdiff4')"), @"unexpected end of file");
+    assert_snapshot!(c.q("compressed_patch(gzip('a'), x'00', 'gzip', 'bsdiff4')"), @"not a valid patch");
+    assert_snapshot!(c.q("compressed_diff(gzip('a'), 1, 'gzip', 'bsdiff4')"), @"Invalid function parameter type Integer at index 1");
+    assert_snapshot!(c.q("compressed_diff(gzip('a'), gzip('b'), 'gzip')"), @"wrong number of arguments to function compressed_diff()");
+    assert_snapshot!(c.q("compressed_patch(gzip('a'), x'00', 'gzip', 'bsdiff4', 1, 2)"), @"wrong number of arguments to function compressed_patch()");
+}